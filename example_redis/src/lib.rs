@@ -29,17 +29,18 @@ static REDIS_CLIENT: OnceLock<redis::Client> = OnceLock::new();
 
 /// Gets or initializes the Redis client.
 /// Uses OnceLock to ensure the client is only created once.
-fn get_redis_client() -> Result<&'static redis::Client, redis::RedisError> {
+/// `REDIS_URL` comes from the function's `.env`, delivered with the request.
+fn get_redis_client(req: &FezzWireRequest) -> Result<&'static redis::Client, redis::RedisError> {
     // Initialize the client only once
     let client = REDIS_CLIENT.get_or_init(|| {
-        redis::Client::open("redis://127.0.0.1:6379/")
-            .expect("Failed to create Redis client")
+        let url = req.env("REDIS_URL").unwrap_or("redis://127.0.0.1:6379/");
+        redis::Client::open(url).expect("Failed to create Redis client")
     });
     Ok(client)
 }
 
-fn get_redis_connection() -> Result<redis::Connection, redis::RedisError> {
-    let client = get_redis_client()?;
+fn get_redis_connection(req: &FezzWireRequest) -> Result<redis::Connection, redis::RedisError> {
+    let client = get_redis_client(req)?;
     client.get_connection()
 }
 
@@ -67,7 +68,7 @@ pub fn redis_demo(req: FezzWireRequest) -> FezzWireResponse {
                 }
             };
 
-            let mut conn = match get_redis_connection() {
+            let mut conn = match get_redis_connection(&req) {
                 Ok(c) => c,
                 Err(e) => {
                     return json_response(500, RedisResponse {
@@ -109,7 +110,7 @@ pub fn redis_demo(req: FezzWireRequest) -> FezzWireResponse {
                 });
             }
 
-            let mut conn = match get_redis_connection() {
+            let mut conn = match get_redis_connection(&req) {
                 Ok(c) => c,
                 Err(e) => {
                     return json_response(500, RedisResponse {
@@ -157,7 +158,7 @@ pub fn redis_demo(req: FezzWireRequest) -> FezzWireResponse {
                 }
             };
 
-            let mut conn = match get_redis_connection() {
+            let mut conn = match get_redis_connection(&req) {
                 Ok(c) => c,
                 Err(e) => {
                     return json_response(500, RedisResponse {
@@ -205,7 +206,7 @@ pub fn redis_demo(req: FezzWireRequest) -> FezzWireResponse {
                 }
             };
 
-            let mut conn = match get_redis_connection() {
+            let mut conn = match get_redis_connection(&req) {
                 Ok(c) => c,
                 Err(e) => {
                     return json_response(500, RedisResponse {
@@ -9,7 +9,16 @@ use deno_runtime::{
     worker::{MainWorker, WorkerOptions},
     BootstrapOptions,
 };
-use std::{collections::HashMap, fs, path::Path, sync::Arc};
+use std::{
+    collections::HashMap,
+    fmt, fs,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Weak,
+    },
+    time::Duration,
+};
 use tokio::sync::Mutex;
 
 #[derive(Clone, Debug, Eq, Hash, PartialEq)]
@@ -26,8 +35,50 @@ pub struct JsInvoke {
     pub headers: Vec<(String, String)>,
     pub body: Vec<u8>,
     pub env: Vec<(String, String)>,
+    /// Execution budget; the isolate is terminated when it runs out.
+    /// Exposed to the handler as `ctx.deadlineMs`.
+    pub timeout: Option<Duration>,
+    /// Exposed as `ctx.traceId`.
+    pub trace_id: Option<String>,
+    /// Exposed as `ctx.clientIp`.
+    pub client_ip: Option<String>,
+    /// Verified caller, exposed as `ctx.principal`.
+    pub principal: Option<String>,
+    /// Verified token claims, exposed as the `ctx.claims` object.
+    pub claims: Vec<(String, String)>,
+    /// Receives what the handler writes to `console`, also when it fails.
+    pub logs: JsLogs,
+}
+
+/// `(level, message)` lines written to `console` during one invocation.
+///
+/// Clones share the same buffer; `console` itself is replaced per isolate,
+/// so nothing reaches the host's stdout.
+#[derive(Clone, Debug, Default)]
+pub struct JsLogs(Arc<std::sync::Mutex<Vec<(String, String)>>>);
+
+impl JsLogs {
+    pub fn take(&self) -> Vec<(String, String)> {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
+
+    fn extend(&self, lines: Vec<(String, String)>) {
+        self.0.lock().unwrap().extend(lines);
+    }
 }
 
+/// Error returned by [`JsRuntimeManager::invoke`] when the budget ran out.
+#[derive(Debug)]
+pub struct JsTimeout(pub Duration);
+
+impl fmt::Display for JsTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "JS function timed out after {:?}", self.0)
+    }
+}
+
+impl std::error::Error for JsTimeout {}
+
 #[derive(Clone, Debug)]
 pub struct JsResult {
     pub status: u16,
@@ -35,18 +86,53 @@ pub struct JsResult {
     pub body: Vec<u8>,
 }
 
+/// Outcome of a module's optional `health` export.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum JsHealth {
+    /// The module exports no `health` function.
+    Missing,
+    Healthy,
+    /// `health` returned `false`, threw or rejected.
+    Unhealthy(String),
+}
+
 pub struct JsRuntimeManager {
     script_cache: Mutex<HashMap<JsKey, String>>,
+    /// Isolates currently executing, for [`JsRuntimeManager::terminate_all`].
+    running: std::sync::Mutex<Vec<Weak<Termination>>>,
 }
 
 impl JsRuntimeManager {
     pub fn new() -> Self {
         Self {
             script_cache: Mutex::new(HashMap::new()),
+            running: std::sync::Mutex::new(Vec::new()),
         }
     }
 
-    pub async fn invoke(&self, key: &JsKey, script_path: &str, req: JsInvoke) -> Result<JsResult> {
+    /// Terminates every isolate that is still executing, e.g. when the host
+    /// shuts down. Their invocations fail like a timed out one.
+    pub fn terminate_all(&self) -> usize {
+        let running = self.running.lock().unwrap();
+        let mut terminated = 0;
+        for termination in running.iter().filter_map(Weak::upgrade) {
+            termination.terminate();
+            terminated += 1;
+        }
+        terminated
+    }
+
+    /// Runs the module's `fetch` for `req` in a fresh isolate.
+    ///
+    /// `guard` (e.g. a concurrency permit) is dropped when the isolate has
+    /// actually stopped, which after a timeout is later than `invoke` returns.
+    pub async fn invoke<G: Send + 'static>(
+        &self,
+        key: &JsKey,
+        script_path: &str,
+        req: JsInvoke,
+        guard: G,
+    ) -> Result<JsResult> {
         if !Path::new(script_path).exists() {
             return Err(anyhow!("JS bundle not found at {}", script_path));
         }
@@ -58,14 +144,178 @@ impl JsRuntimeManager {
                 .or_insert_with(|| script_path.to_string());
         }
 
+        let span = tracing::info_span!("run_js", script = %script_path);
+        let timeout = req.timeout;
         let script_path = script_path.to_string();
-        tokio::task::spawn_blocking(move || run_js(&script_path, req))
-            .await
-            .context("Failed to join JS task")?
+        self.run_isolate(span, timeout, move |termination| {
+            let result = run_js(&script_path, req, termination);
+            drop(guard);
+            result
+        })
+        .await
+    }
+
+    /// Loads the module and calls its `health(env)` export, if any, in a fresh
+    /// isolate. A `health` that returns `false`, throws or rejects is unhealthy.
+    pub async fn health(
+        &self,
+        script_path: &str,
+        env: Vec<(String, String)>,
+        timeout: Duration,
+    ) -> Result<JsHealth> {
+        if !Path::new(script_path).exists() {
+            return Err(anyhow!("JS bundle not found at {}", script_path));
+        }
+
+        let span = tracing::info_span!("run_js_health", script = %script_path);
+        let script_path = script_path.to_string();
+        self.run_isolate(span, Some(timeout), move |termination| {
+            run_health(&script_path, &env, termination)
+        })
+        .await
+    }
+
+    /// Loads and evaluates the module in a fresh isolate and checks that it
+    /// exports a `fetch` handler, without calling it.
+    pub async fn validate(&self, script_path: &str, timeout: Duration) -> Result<()> {
+        if !Path::new(script_path).exists() {
+            return Err(anyhow!("JS bundle not found at {}", script_path));
+        }
+
+        let span = tracing::info_span!("run_js_validate", script = %script_path);
+        let script_path = script_path.to_string();
+        self.run_isolate(span, Some(timeout), move |termination| {
+            run_validate(&script_path, termination)
+        })
+        .await
+    }
+
+    /// Runs `run` on the blocking pool, terminating its isolate once
+    /// `timeout` runs out or on [`JsRuntimeManager::terminate_all`].
+    async fn run_isolate<T, F>(
+        &self,
+        span: tracing::Span,
+        timeout: Option<Duration>,
+        run: F,
+    ) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Termination) -> Result<T> + Send + 'static,
+    {
+        let termination = Arc::new(Termination::default());
+        {
+            // The blocking task keeps its entry alive while the isolate runs.
+            let mut running = self.running.lock().unwrap();
+            running.retain(|termination| termination.strong_count() > 0);
+            running.push(Arc::downgrade(&termination));
+        }
+        let task = tokio::task::spawn_blocking({
+            let termination = termination.clone();
+            move || span.in_scope(|| run(&termination))
+        });
+
+        let Some(timeout) = timeout else {
+            return task.await.context("Failed to join JS task")?;
+        };
+
+        match tokio::time::timeout(timeout, task).await {
+            Ok(joined) => joined.context("Failed to join JS task")?,
+            Err(_) => {
+                tracing::warn!("Terminating JS isolate after {:?}", timeout);
+                termination.terminate();
+                Err(JsTimeout(timeout).into())
+            }
+        }
+    }
+}
+
+/// Lets the async side terminate an isolate running on the blocking pool.
+#[derive(Default)]
+struct Termination {
+    handle: std::sync::Mutex<Option<v8::IsolateHandle>>,
+    terminated: AtomicBool,
+}
+
+impl Termination {
+    fn attach(&self, handle: v8::IsolateHandle) {
+        let mut slot = self.handle.lock().unwrap();
+        if self.terminated.load(Ordering::SeqCst) {
+            handle.terminate_execution();
+        }
+        *slot = Some(handle);
+    }
+
+    fn terminate(&self) {
+        let slot = self.handle.lock().unwrap();
+        self.terminated.store(true, Ordering::SeqCst);
+        if let Some(handle) = slot.as_ref() {
+            handle.terminate_execution();
+        }
     }
 }
 
 const BOOTSTRAP: &str = r#"
+const FEZZ_MAX_LOG_BYTES = 64 * 1024;
+const fezzLogs = [];
+let fezzLogBytes = 0;
+let fezzLogsTruncated = false;
+
+function formatLogArg(arg) {
+  if (typeof arg === "string") {
+    return arg;
+  }
+  if (arg instanceof Error) {
+    return arg.stack ?? String(arg);
+  }
+  if (arg && typeof arg === "object") {
+    try {
+      return JSON.stringify(arg);
+    } catch {
+      return String(arg);
+    }
+  }
+  return String(arg);
+}
+
+function captureLog(level) {
+  return (...args) => {
+    const message = args.map(formatLogArg).join(" ");
+    if (fezzLogBytes + message.length > FEZZ_MAX_LOG_BYTES) {
+      fezzLogsTruncated = true;
+      return;
+    }
+    fezzLogBytes += message.length;
+    fezzLogs.push([level, message]);
+  };
+}
+
+Object.defineProperty(globalThis, "console", {
+  value: {
+    log: captureLog("info"),
+    info: captureLog("info"),
+    debug: captureLog("debug"),
+    trace: captureLog("debug"),
+    warn: captureLog("warn"),
+    error: captureLog("error"),
+    dir: captureLog("info"),
+    table: captureLog("info"),
+  },
+  writable: true,
+  configurable: true,
+  enumerable: false,
+});
+
+function __fezz_take_logs() {
+  const lines = fezzLogs.splice(0);
+  if (fezzLogsTruncated) {
+    lines.push(["warn", `console output truncated at ${FEZZ_MAX_LOG_BYTES} bytes`]);
+    fezzLogsTruncated = false;
+  }
+  fezzLogBytes = 0;
+  return lines;
+}
+globalThis.__fezz_take_logs = __fezz_take_logs;
+
 function __fezz_normalize_response(resp) {
   if (resp instanceof Response) {
     return {
@@ -112,7 +362,41 @@ function normalizeBody(body) {
 globalThis.__fezz_normalize_response = __fezz_normalize_response;
 "#;
 
-fn run_js(script_path: &str, req: JsInvoke) -> Result<JsResult> {
+fn run_js(script_path: &str, req: JsInvoke, termination: &Termination) -> Result<JsResult> {
+    let (mut worker, module_specifier) = bootstrap(script_path, termination)?;
+    let runtime = &mut worker.js_runtime;
+    let result = call_fetch(runtime, &module_specifier, &req);
+    drain_console(runtime, &req.logs);
+    result
+}
+
+fn run_health(
+    script_path: &str,
+    env: &[(String, String)],
+    termination: &Termination,
+) -> Result<JsHealth> {
+    let (mut worker, module_specifier) = bootstrap(script_path, termination)?;
+    call_health(&mut worker.js_runtime, &module_specifier, env)
+}
+
+fn run_validate(script_path: &str, termination: &Termination) -> Result<()> {
+    let (mut worker, module_specifier) = bootstrap(script_path, termination)?;
+    let runtime = &mut worker.js_runtime;
+    let module_namespace = load_module(runtime, &module_specifier)?;
+    let scope = &mut v8::HandleScope::new(runtime.v8_isolate());
+    let context = v8::Local::new(scope, runtime.main_context());
+    let mut scope = v8::ContextScope::new(scope, context);
+    let module_namespace = v8::Local::new(&mut scope, module_namespace);
+    let mut scope = v8::PinScope::new(&mut scope);
+    resolve_fetch(&mut scope, module_namespace).map(|_| ())
+}
+
+/// Creates a worker for the module at `script_path` with the `console`
+/// capture installed, attached to `termination`.
+fn bootstrap(
+    script_path: &str,
+    termination: &Termination,
+) -> Result<(MainWorker, ModuleSpecifier)> {
     let canonical_path = fs::canonicalize(script_path)
         .with_context(|| format!("Failed to canonicalize JS module path: {}", script_path))?;
     let module_specifier = ModuleSpecifier::from_file_path(&canonical_path).map_err(|_| {
@@ -145,12 +429,20 @@ fn run_js(script_path: &str, req: JsInvoke) -> Result<JsResult> {
         worker_options,
     );
     let runtime = &mut worker.js_runtime;
+    termination.attach(runtime.v8_isolate().thread_safe_handle());
 
     runtime
         .execute_script("<fezz-bootstrap>", BOOTSTRAP)
         .context("Failed to execute JS bootstrap")?;
+    Ok((worker, module_specifier))
+}
 
-    let module_id = block_on(runtime.load_main_es_module(&module_specifier))
+/// Loads and evaluates the module, returning its namespace.
+fn load_module(
+    runtime: &mut JsRuntime,
+    module_specifier: &ModuleSpecifier,
+) -> Result<v8::Global<v8::Object>> {
+    let module_id = block_on(runtime.load_main_es_module(module_specifier))
         .context("Failed to load JS module")?;
 
     let evaluation = runtime.mod_evaluate(module_id);
@@ -163,9 +455,67 @@ fn run_js(script_path: &str, req: JsInvoke) -> Result<JsResult> {
     })
     .context("Failed to evaluate JS module")?;
 
-    let module_namespace = runtime
+    runtime
         .get_module_namespace(module_id)
-        .context("Failed to get module namespace")?;
+        .context("Failed to get module namespace")
+}
+
+/// Evaluates the module and calls its `health` export, if any.
+fn call_health(
+    runtime: &mut JsRuntime,
+    module_specifier: &ModuleSpecifier,
+    env: &[(String, String)],
+) -> Result<JsHealth> {
+    let module_namespace = load_module(runtime, module_specifier)?;
+    let scope = &mut v8::HandleScope::new(runtime.v8_isolate());
+    let context = v8::Local::new(scope, runtime.main_context());
+    let mut scope = v8::ContextScope::new(scope, context);
+    let module_namespace = v8::Local::new(&mut scope, module_namespace);
+    let mut scope = v8::PinScope::new(&mut scope);
+
+    let Some(health_fn) = resolve_export(&mut scope, module_namespace, "health") else {
+        return Ok(JsHealth::Missing);
+    };
+    let env_value = build_env(&mut scope, env)?;
+    let undefined = v8::undefined(&mut scope).into();
+    let Some(result) = health_fn.call(&mut scope, undefined, &[env_value.into()]) else {
+        return Ok(JsHealth::Unhealthy(
+            "JS health check threw an exception".to_string(),
+        ));
+    };
+
+    if result.is_promise() {
+        let promise: v8::Local<v8::Promise> = unsafe { result.cast() };
+        let promise = v8::Global::new(&mut scope, promise);
+        drop(scope);
+        let resolved = match resolve_promise(runtime, promise) {
+            Ok(resolved) => resolved,
+            Err(err) => return Ok(JsHealth::Unhealthy(err.to_string())),
+        };
+        let scope = &mut v8::HandleScope::new(runtime.v8_isolate());
+        let context = v8::Local::new(scope, runtime.main_context());
+        let mut scope = v8::ContextScope::new(scope, context);
+        let resolved_value = v8::Local::new(&mut scope, &resolved);
+        return Ok(health_of(resolved_value.is_false()));
+    }
+    Ok(health_of(result.is_false()))
+}
+
+fn health_of(returned_false: bool) -> JsHealth {
+    if returned_false {
+        JsHealth::Unhealthy("JS health check returned false".to_string())
+    } else {
+        JsHealth::Healthy
+    }
+}
+
+/// Evaluates the module and calls its `fetch` handler.
+fn call_fetch(
+    runtime: &mut JsRuntime,
+    module_specifier: &ModuleSpecifier,
+    req: &JsInvoke,
+) -> Result<JsResult> {
+    let module_namespace = load_module(runtime, module_specifier)?;
     let scope = &mut v8::HandleScope::new(runtime.v8_isolate());
     let context = v8::Local::new(scope, runtime.main_context());
     let mut scope = v8::ContextScope::new(scope, context);
@@ -174,9 +524,9 @@ fn run_js(script_path: &str, req: JsInvoke) -> Result<JsResult> {
 
     let fetch_fn = resolve_fetch(&mut scope, module_namespace)?;
 
-    let req_value = build_request(&mut scope, &req)?;
+    let req_value = build_request(&mut scope, req)?;
     let env_value = build_env(&mut scope, &req.env)?;
-    let ctx_value = v8::Object::new(&mut scope);
+    let ctx_value = build_ctx(&mut scope, req)?;
 
     let undefined = v8::undefined(&mut scope).into();
     let result = fetch_fn
@@ -191,7 +541,7 @@ fn run_js(script_path: &str, req: JsInvoke) -> Result<JsResult> {
         let promise: v8::Local<v8::Promise> = unsafe { result.cast() };
         let promise = v8::Global::new(&mut scope, promise);
         drop(scope);
-        let resolved = resolve_promise(&mut runtime, promise)?;
+        let resolved = resolve_promise(runtime, promise)?;
         let scope = &mut v8::HandleScope::new(runtime.v8_isolate());
         let context = v8::Local::new(scope, runtime.main_context());
         let mut scope = v8::ContextScope::new(scope, context);
@@ -205,6 +555,24 @@ fn run_js(script_path: &str, req: JsInvoke) -> Result<JsResult> {
     extract_response(&mut scope, normalized)
 }
 
+/// Moves the lines buffered by the bootstrap `console` into `logs`. A
+/// terminated isolate can no longer run the script; its lines are lost.
+fn drain_console(runtime: &mut JsRuntime, logs: &JsLogs) {
+    let taken = runtime.execute_script("<fezz-logs>", "globalThis.__fezz_take_logs()");
+    let Ok(taken) = taken else {
+        return;
+    };
+    let scope = &mut v8::HandleScope::new(runtime.v8_isolate());
+    let context = v8::Local::new(scope, runtime.main_context());
+    let mut scope = v8::ContextScope::new(scope, context);
+    let taken = v8::Local::new(&mut scope, &taken);
+    let mut scope = v8::PinScope::new(&mut scope);
+    if taken.is_array() {
+        let array: v8::Local<v8::Array> = unsafe { taken.cast() };
+        logs.extend(read_string_pairs(&mut scope, array));
+    }
+}
+
 fn resolve_promise(
     runtime: &mut JsRuntime,
     promise: v8::Global<v8::Promise>,
@@ -226,7 +594,7 @@ fn resolve_promise(
             v8::PromiseState::Rejected => {
                 let reason = promise.result(&mut scope);
                 let reason = format_js_error(&mut scope, reason);
-                return Err(anyhow!("JS fetch promise rejected: {}", reason));
+                return Err(anyhow!("JS promise rejected: {}", reason));
             }
         }
     }
@@ -245,6 +613,28 @@ fn format_js_error<'a>(
     "<non-string rejection>".to_string()
 }
 
+/// A function exported as `name` or as `default.name`.
+fn resolve_export<'a>(
+    scope: &mut v8::PinScope<'a, '_>,
+    module_namespace: v8::Local<'a, v8::Object>,
+    name: &str,
+) -> Option<v8::Local<'a, v8::Function>> {
+    let key = v8::String::new(scope, name).unwrap();
+    let value = module_namespace.get(scope, key.into())?;
+    if value.is_function() {
+        return Some(unsafe { value.cast() });
+    }
+
+    let default_key = v8::String::new(scope, "default").unwrap();
+    let default_value = module_namespace.get(scope, default_key.into())?;
+    if !default_value.is_object() {
+        return None;
+    }
+    let default_obj: v8::Local<v8::Object> = unsafe { default_value.cast() };
+    let value = default_obj.get(scope, key.into())?;
+    value.is_function().then(|| unsafe { value.cast() })
+}
+
 fn resolve_fetch<'a>(
     scope: &mut v8::PinScope<'a, '_>,
     module_namespace: v8::Local<'a, v8::Object>,
@@ -304,6 +694,48 @@ fn build_request<'a>(
     Ok(obj)
 }
 
+fn build_ctx<'a>(
+    scope: &mut v8::PinScope<'a, '_>,
+    req: &JsInvoke,
+) -> Result<v8::Local<'a, v8::Object>> {
+    let obj = v8::Object::new(scope);
+
+    if let Some(timeout) = req.timeout {
+        let deadline_key = v8::String::new(scope, "deadlineMs").unwrap();
+        let deadline_value = v8::Number::new(scope, timeout.as_millis() as f64);
+        obj.set(scope, deadline_key.into(), deadline_value.into());
+    }
+
+    if let Some(trace_id) = &req.trace_id {
+        let trace_key = v8::String::new(scope, "traceId").unwrap();
+        let trace_value = v8::String::new(scope, trace_id).unwrap();
+        obj.set(scope, trace_key.into(), trace_value.into());
+    }
+
+    if let Some(client_ip) = &req.client_ip {
+        let ip_key = v8::String::new(scope, "clientIp").unwrap();
+        let ip_value = v8::String::new(scope, client_ip).unwrap();
+        obj.set(scope, ip_key.into(), ip_value.into());
+    }
+
+    if let Some(principal) = &req.principal {
+        let principal_key = v8::String::new(scope, "principal").unwrap();
+        let principal_value = v8::String::new(scope, principal).unwrap();
+        obj.set(scope, principal_key.into(), principal_value.into());
+    }
+
+    let claims = v8::Object::new(scope);
+    for (name, value) in &req.claims {
+        let claim_key = v8::String::new(scope, name).unwrap();
+        let claim_value = v8::String::new(scope, value).unwrap();
+        claims.set(scope, claim_key.into(), claim_value.into());
+    }
+    let claims_key = v8::String::new(scope, "claims").unwrap();
+    obj.set(scope, claims_key.into(), claims.into());
+
+    Ok(obj)
+}
+
 fn build_headers<'a>(
     scope: &mut v8::PinScope<'a, '_>,
     headers: &[(String, String)],
@@ -424,7 +856,15 @@ fn get_headers<'a>(
     }
 
     let array: v8::Local<v8::Array> = unsafe { value.cast() };
-    let mut headers = Vec::new();
+    Ok(read_string_pairs(scope, array))
+}
+
+/// Reads `[[name, value], ...]`, skipping malformed entries.
+fn read_string_pairs<'a>(
+    scope: &mut v8::PinScope<'a, '_>,
+    array: v8::Local<'a, v8::Array>,
+) -> Vec<(String, String)> {
+    let mut pairs = Vec::new();
     for idx in 0..array.length() {
         let entry_index = v8::Integer::new(scope, idx as i32);
         let entry = array
@@ -445,11 +885,10 @@ fn get_headers<'a>(
             .and_then(|val| val.to_string(scope))
             .map(|s| s.to_rust_string_lossy(scope.get_isolate()));
         if let (Some(name), Some(value)) = (name, value) {
-            headers.push((name, value));
+            pairs.push((name, value));
         }
     }
-
-    Ok(headers)
+    pairs
 }
 
 fn get_body<'a>(
@@ -20,6 +20,9 @@ pub fn fezz_function(_args: TokenStream, input: TokenStream) -> TokenStream {
         pub unsafe extern "C" fn fezz_handle_v2(req: fezz_sdk::FezzSlice) -> fezz_sdk::FezzOwned {
             use fezz_sdk::{FezzWireHeader, FezzWireResponse};
 
+            // Lines logged through fezz_sdk::log travel back in the response.
+            fezz_sdk::log::begin_capture();
+
             // Wrap the entire function body in catch_unwind to prevent panics
             // from crossing the FFI boundary (which is undefined behavior).
             let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
@@ -49,7 +52,7 @@ pub fn fezz_function(_args: TokenStream, input: TokenStream) -> TokenStream {
                 Ok(#func_name(req))
             }));
 
-            let resp: FezzWireResponse = match result {
+            let mut resp: FezzWireResponse = match result {
                 Ok(Ok(r)) => r,
                 Ok(Err(message)) => FezzWireResponse::new(
                     400,
@@ -65,6 +68,7 @@ pub fn fezz_function(_args: TokenStream, input: TokenStream) -> TokenStream {
                     } else {
                         "Unknown panic".to_string()
                     };
+                    fezz_sdk::log::error(format!("Function panicked: {}", panic_msg));
 
                     FezzWireResponse::new(
                         500,
@@ -74,6 +78,8 @@ pub fn fezz_function(_args: TokenStream, input: TokenStream) -> TokenStream {
                 }
             };
 
+            resp.logs.extend(fezz_sdk::log::end_capture());
+
             let mut resp_bytes = match fezz_sdk::encode_response(&resp) {
                 Ok(b) => b,
                 Err(_) => Vec::new(),
@@ -95,3 +101,77 @@ pub fn fezz_function(_args: TokenStream, input: TokenStream) -> TokenStream {
 
     expanded.into()
 }
+
+/// Exports a `fn(FezzWireHealthCheck) -> Result<(), String>` as the optional
+/// `fezz_health_v1` self-check HHRF probes for readiness. Use it in a crate
+/// that also has a `#[fezz_function]`, whose `fezz_free_v2` releases the
+/// returned buffer.
+#[proc_macro_attribute]
+pub fn fezz_health(_args: TokenStream, input: TokenStream) -> TokenStream {
+    let func = parse_macro_input!(input as ItemFn);
+    let func_name = &func.sig.ident;
+
+    let expanded = quote! {
+        #func
+
+        /// FFI entry point for the Fezz health check.
+        ///
+        /// # Safety
+        ///
+        /// The caller must ensure that `check` points to a valid byte slice of length `len`.
+        #[no_mangle]
+        pub unsafe extern "C" fn fezz_health_v1(check: fezz_sdk::FezzSlice) -> fezz_sdk::FezzOwned {
+            use fezz_sdk::{FezzWireHealth, FezzWireHealthCheck};
+
+            let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                if check.ptr.is_null() && check.len != 0 {
+                    return Err("Null health check pointer with non-zero length".to_string());
+                }
+
+                // An empty slice means an empty environment.
+                let check = if check.len == 0 {
+                    FezzWireHealthCheck::default()
+                } else {
+                    let check_bytes = std::slice::from_raw_parts(check.ptr, check.len);
+                    fezz_sdk::decode_health_check(check_bytes)
+                        .map_err(|e| format!("Invalid health check: {}", e))?
+                };
+                #func_name(check)
+            }));
+            let health = match result {
+                Ok(Ok(())) => FezzWireHealth {
+                    healthy: true,
+                    message: None,
+                },
+                Ok(Err(message)) => FezzWireHealth {
+                    healthy: false,
+                    message: Some(message),
+                },
+                Err(panic_info) => {
+                    let panic_msg = if let Some(s) = panic_info.downcast_ref::<&str>() {
+                        s.to_string()
+                    } else if let Some(s) = panic_info.downcast_ref::<String>() {
+                        s.clone()
+                    } else {
+                        "Unknown panic".to_string()
+                    };
+                    FezzWireHealth {
+                        healthy: false,
+                        message: Some(format!("Health check panicked: {}", panic_msg)),
+                    }
+                }
+            };
+
+            let mut health_bytes = match fezz_sdk::encode_health(&health) {
+                Ok(b) => b,
+                Err(_) => Vec::new(),
+            };
+            let len = health_bytes.len();
+            let ptr = health_bytes.as_mut_ptr();
+            std::mem::forget(health_bytes);
+            fezz_sdk::FezzOwned { ptr, len }
+        }
+    };
+
+    expanded.into()
+}
@@ -1,53 +1,86 @@
-use fezz_sdk::{FezzOwned, FezzSlice};
+use fezz_sdk::{FezzOwned, FezzSlice, FezzWireHealth};
 use libloading::{Library, Symbol};
+use std::fs::File;
 use std::io::{Read, Write};
+use std::os::fd::{AsFd, FromRawFd, RawFd};
 use std::process::exit;
+use std::sync::Mutex;
+use tracing_subscriber::EnvFilter;
 
 // Same ABI as in HHRF and fezz-macros
 type FezzHandleV2Fn = unsafe extern "C" fn(FezzSlice) -> FezzOwned;
 type FezzFreeV2Fn = unsafe extern "C" fn(FezzOwned);
+type FezzHealthV1Fn = unsafe extern "C" fn(FezzSlice) -> FezzOwned;
 
 fn main() {
+    // HHRF parses these JSON lines and re-emits them in the request span. It
+    // passes a dedicated fd for them, so nothing the plugin prints on
+    // stdout/stderr can pass for a runner event; without one they go to stderr.
+    let subscriber = tracing_subscriber::fmt().json().with_env_filter(
+        EnvFilter::try_from_env("FEZZ_RUNNER_LOG").unwrap_or_else(|_| EnvFilter::new("info")),
+    );
+    let log_fd = std::env::var("FEZZ_RUNNER_LOG_FD")
+        .ok()
+        .and_then(|fd| fd.parse::<RawFd>().ok());
+    match log_fd {
+        Some(fd) => subscriber
+            .with_writer(Mutex::new(unsafe { File::from_raw_fd(fd) }))
+            .init(),
+        None => subscriber.with_writer(std::io::stderr).init(),
+    }
+
     // Args: <path-to-dylib>
     let so_path = match std::env::args().nth(1) {
         Some(p) => p,
         None => {
-            eprintln!("Usage: fezz-runner <path-to-dylib>");
+            tracing::error!("Usage: fezz-runner <path-to-dylib>");
             exit(1);
         }
     };
 
-    eprintln!("[fezz-runner] starting, so_path={}", so_path);
+    tracing::debug!(so_path = %so_path, "starting");
+
+    // HHRF's readiness check: no request, just load and probe the library.
+    // `symbols` (startup discovery) stops after resolving the V2 symbols.
+    if let Some(mode) = std::env::var_os("FEZZ_RUNNER_HEALTH") {
+        check_health(&so_path, mode != "symbols");
+        return;
+    }
 
     // Read request bytes from stdin
     let mut buf = Vec::new();
     if let Err(e) = std::io::stdin().read_to_end(&mut buf) {
-        eprintln!("Failed to read stdin: {}", e);
+        tracing::error!("Failed to read stdin: {}", e);
         exit(1);
     }
 
-    eprintln!(
-        "[fezz-runner] stdin read ok, bytes={}", 
-        buf.len()
-    );
+    tracing::debug!(bytes = buf.len(), "stdin read ok");
 
     // Parse into FezzWireRequest just to validate; we then pass raw bytes to plugin
     if let Err(e) = fezz_sdk::decode_request(&buf) {
-        eprintln!("Invalid request bytes: {}", e);
+        tracing::error!("Invalid request bytes: {}", e);
         exit(1);
     }
 
+    let mut response_out = match reserve_stdout() {
+        Ok(file) => file,
+        Err(e) => {
+            tracing::error!("Failed to redirect stdout: {}", e);
+            exit(1);
+        }
+    };
+
     // Load library
-    eprintln!("[fezz-runner] loading library");
+    tracing::debug!("loading library");
 
     let library = unsafe {
         match Library::new(&so_path) {
             Ok(lib) => {
-                eprintln!("[fezz-runner] library loaded successfully");
+                tracing::debug!("library loaded successfully");
                 lib
             }
             Err(e) => {
-                eprintln!("Failed to load {}: {}", so_path, e);
+                tracing::error!("Failed to load {}: {}", so_path, e);
                 exit(1);
             }
         }
@@ -57,11 +90,11 @@ fn main() {
     let fezz_handle_v2: Symbol<FezzHandleV2Fn> = unsafe {
         match library.get(b"fezz_handle_v2") {
             Ok(sym) => {
-                eprintln!("[fezz-runner] fezz_handle_v2 symbol resolved");
+                tracing::debug!("fezz_handle_v2 symbol resolved");
                 sym
             }
             Err(e) => {
-                eprintln!("Failed to get fezz_handle_v2 symbol: {}", e);
+                tracing::error!("Failed to get fezz_handle_v2 symbol: {}", e);
                 exit(1);
             }
         }
@@ -70,21 +103,21 @@ fn main() {
     let fezz_free_v2: Symbol<FezzFreeV2Fn> = unsafe {
         match library.get(b"fezz_free_v2") {
             Ok(sym) => {
-                eprintln!("[fezz-runner] fezz_free_v2 symbol resolved");
+                tracing::debug!("fezz_free_v2 symbol resolved");
                 sym
             }
             Err(e) => {
-                eprintln!("Failed to get fezz_free_v2 symbol: {}", e);
+                tracing::error!("Failed to get fezz_free_v2 symbol: {}", e);
                 exit(1);
             }
         }
     };
 
     // Call function
-    eprintln!("[fezz-runner] calling fezz_handle_v2");
+    tracing::debug!("calling fezz_handle_v2");
     let owned = unsafe { fezz_handle_v2(FezzSlice { ptr: buf.as_ptr(), len: buf.len() }) };
     if owned.ptr.is_null() && owned.len != 0 {
-        eprintln!("fezz_handle_v2 returned null pointer");
+        tracing::error!("fezz_handle_v2 returned null pointer");
         exit(1);
     }
 
@@ -101,20 +134,121 @@ fn main() {
 
     // Validate that it is a FezzWireResponse (optional but nice)
     if let Err(e) = fezz_sdk::decode_response(&resp_bytes) {
-        eprintln!("Invalid response bytes from plugin: {}", e);
+        tracing::error!("Invalid response bytes from plugin: {}", e);
         exit(1);
     }
 
     // Write raw bytes to stdout for HHRF to consume
-    eprintln!(
-        "[fezz-runner] writing response bytes to stdout, bytes={}",
-        resp_bytes.len()
-    );
+    tracing::debug!(bytes = resp_bytes.len(), "writing response bytes to stdout");
+
+    if let Err(e) = response_out.write_all(&resp_bytes) {
+        tracing::error!("Failed to write stdout: {}", e);
+        exit(1);
+    }
+
+    tracing::debug!("finished successfully");
+}
+
+/// Loads the library, resolves the V2 symbols and writes the CBOR
+/// `FezzWireHealth` of its `fezz_health_v1` (healthy when not exported or
+/// when `call_hook` is false) to stdout. The hook gets the
+/// `FezzWireHealthCheck` HHRF writes to stdin.
+fn check_health(so_path: &str, call_hook: bool) {
+    let mut response_out = match reserve_stdout() {
+        Ok(file) => file,
+        Err(e) => {
+            tracing::error!("Failed to redirect stdout: {}", e);
+            exit(1);
+        }
+    };
+
+    let library = match unsafe { Library::new(so_path) } {
+        Ok(lib) => lib,
+        Err(e) => {
+            tracing::error!("Failed to load {}: {}", so_path, e);
+            exit(1);
+        }
+    };
+    let fezz_free_v2: Symbol<FezzFreeV2Fn> = unsafe {
+        match library.get(b"fezz_free_v2") {
+            Ok(sym) => sym,
+            Err(e) => {
+                tracing::error!("Failed to get fezz_free_v2 symbol: {}", e);
+                exit(1);
+            }
+        }
+    };
+    if let Err(e) = unsafe { library.get::<FezzHandleV2Fn>(b"fezz_handle_v2") } {
+        tracing::error!("Failed to get fezz_handle_v2 symbol: {}", e);
+        exit(1);
+    }
+
+    let hook = if call_hook {
+        unsafe { library.get::<FezzHealthV1Fn>(b"fezz_health_v1") }.ok()
+    } else {
+        None
+    };
+    let health = match hook {
+        Some(fezz_health_v1) => {
+            let mut check_bytes = Vec::new();
+            if let Err(e) = std::io::stdin().read_to_end(&mut check_bytes) {
+                tracing::error!("Failed to read stdin: {}", e);
+                exit(1);
+            }
+            if let Err(e) = fezz_sdk::decode_health_check(&check_bytes) {
+                tracing::error!("Invalid health check bytes: {}", e);
+                exit(1);
+            }
 
-    if let Err(e) = std::io::stdout().write_all(&resp_bytes) {
-        eprintln!("Failed to write stdout: {}", e);
+            tracing::debug!("calling fezz_health_v1");
+            let owned = unsafe {
+                fezz_health_v1(FezzSlice {
+                    ptr: check_bytes.as_ptr(),
+                    len: check_bytes.len(),
+                })
+            };
+            if owned.ptr.is_null() {
+                tracing::error!("fezz_health_v1 returned null pointer");
+                exit(1);
+            }
+            let health_bytes = unsafe { std::slice::from_raw_parts(owned.ptr, owned.len).to_vec() };
+            unsafe {
+                fezz_free_v2(owned);
+            }
+            match fezz_sdk::decode_health(&health_bytes) {
+                Ok(health) => health,
+                Err(e) => {
+                    tracing::error!("Invalid health bytes from plugin: {}", e);
+                    exit(1);
+                }
+            }
+        }
+        None => FezzWireHealth {
+            healthy: true,
+            message: None,
+        },
+    };
+
+    let health_bytes = match fezz_sdk::encode_health(&health) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            tracing::error!("Failed to encode health: {}", e);
+            exit(1);
+        }
+    };
+    if let Err(e) = response_out.write_all(&health_bytes) {
+        tracing::error!("Failed to write stdout: {}", e);
         exit(1);
     }
+}
 
-    eprintln!("[fezz-runner] finished successfully");
+/// Keeps the real stdout for the response and points fd 1 at stderr, so a
+/// plugin's `println!` reaches HHRF's log capture instead of corrupting the
+/// CBOR response.
+fn reserve_stdout() -> std::io::Result<File> {
+    let response_out = std::io::stdout().as_fd().try_clone_to_owned()?;
+    if unsafe { libc::dup2(libc::STDERR_FILENO, libc::STDOUT_FILENO) } < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(File::from(response_out))
 }
@@ -1,5 +1,8 @@
 use serde::{Deserialize, Serialize};
 pub use serde_bytes::ByteBuf;
+use std::collections::BTreeMap;
+
+pub mod log;
 
 #[repr(C)]
 pub struct FezzSlice {
@@ -30,9 +33,18 @@ impl FezzWireHeader {
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct FezzWireMeta {
+    /// W3C trace id (or the caller's `x-request-id`), echoed back by HHRF.
     pub trace_id: Option<String>,
+    /// Remaining execution budget in milliseconds when the call was dispatched.
     pub deadline_ms: Option<u64>,
+    /// Client address, resolved through trusted proxies when configured.
     pub client_ip: Option<String>,
+    /// Caller verified by HHRF (API key principal, JWT `sub` or HMAC key id).
+    #[serde(default)]
+    pub principal: Option<String>,
+    /// Verified JWT claims; non-string values are JSON-encoded.
+    #[serde(default)]
+    pub claims: BTreeMap<String, String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
@@ -44,6 +56,24 @@ pub struct FezzWireRequest {
     pub headers: Vec<FezzWireHeader>,
     pub body: ByteBuf,
     pub meta: Option<FezzWireMeta>,
+    /// Function environment (`.env`) for this invocation only; never set on the host process.
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+}
+
+impl FezzWireRequest {
+    /// Returns the value of a function environment variable for this request.
+    pub fn env(&self, key: &str) -> Option<&str> {
+        self.env.get(key).map(|value| value.as_str())
+    }
+}
+
+/// One line logged by a function through [`log`] during an invocation.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct FezzWireLog {
+    /// `debug`, `info`, `warn` or `error`.
+    pub level: String,
+    pub message: String,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
@@ -51,6 +81,9 @@ pub struct FezzWireResponse {
     pub status: u16,
     pub headers: Vec<FezzWireHeader>,
     pub body: ByteBuf,
+    /// Lines captured by [`log`] while handling this request.
+    #[serde(default)]
+    pub logs: Vec<FezzWireLog>,
 }
 
 impl FezzWireResponse {
@@ -59,10 +92,34 @@ impl FezzWireResponse {
             status,
             headers,
             body: ByteBuf::from(body.into()),
+            logs: Vec::new(),
         }
     }
 }
 
+/// Input of a function's optional `fezz_health_v1` self-check.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct FezzWireHealthCheck {
+    /// Function environment (`.env`), as for a request; never set on the host process.
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+}
+
+impl FezzWireHealthCheck {
+    /// Returns the value of a function environment variable for this check.
+    pub fn env(&self, key: &str) -> Option<&str> {
+        self.env.get(key).map(|value| value.as_str())
+    }
+}
+
+/// Result of a function's optional `fezz_health_v1` self-check.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct FezzWireHealth {
+    pub healthy: bool,
+    /// Why the function is unhealthy, reported by HHRF's readiness check.
+    pub message: Option<String>,
+}
+
 pub fn encode_request(req: &FezzWireRequest) -> Result<Vec<u8>, serde_cbor::Error> {
     serde_cbor::to_vec(req)
 }
@@ -78,3 +135,19 @@ pub fn encode_response(resp: &FezzWireResponse) -> Result<Vec<u8>, serde_cbor::E
 pub fn decode_response(bytes: &[u8]) -> Result<FezzWireResponse, serde_cbor::Error> {
     serde_cbor::from_slice(bytes)
 }
+
+pub fn encode_health_check(check: &FezzWireHealthCheck) -> Result<Vec<u8>, serde_cbor::Error> {
+    serde_cbor::to_vec(check)
+}
+
+pub fn decode_health_check(bytes: &[u8]) -> Result<FezzWireHealthCheck, serde_cbor::Error> {
+    serde_cbor::from_slice(bytes)
+}
+
+pub fn encode_health(health: &FezzWireHealth) -> Result<Vec<u8>, serde_cbor::Error> {
+    serde_cbor::to_vec(health)
+}
+
+pub fn decode_health(bytes: &[u8]) -> Result<FezzWireHealth, serde_cbor::Error> {
+    serde_cbor::from_slice(bytes)
+}
@@ -0,0 +1,92 @@
+//! Per-invocation function logging.
+//!
+//! Lines logged here while `fezz_handle_v2` runs are returned to HHRF inside
+//! `FezzWireResponse.logs` and tagged with the request's org/func/version and
+//! trace id. Unlike `println!`, this works for the in-process backend too,
+//! where the function shares HHRF's stdout.
+//!
+//! Capture is per thread: lines logged from threads spawned by the function
+//! are written to stderr instead.
+
+use crate::FezzWireLog;
+use std::{cell::RefCell, fmt};
+
+/// Upper bound on the bytes captured per invocation; later lines are dropped.
+pub const MAX_CAPTURED_BYTES: usize = 64 * 1024;
+
+struct Capture {
+    lines: Vec<FezzWireLog>,
+    bytes: usize,
+    truncated: bool,
+}
+
+thread_local! {
+    static CAPTURE: RefCell<Option<Capture>> = const { RefCell::new(None) };
+}
+
+pub fn debug(message: impl fmt::Display) {
+    write("debug", message);
+}
+
+pub fn info(message: impl fmt::Display) {
+    write("info", message);
+}
+
+pub fn warn(message: impl fmt::Display) {
+    write("warn", message);
+}
+
+pub fn error(message: impl fmt::Display) {
+    write("error", message);
+}
+
+fn write(level: &str, message: impl fmt::Display) {
+    let message = message.to_string();
+    let captured = CAPTURE.with(|capture| {
+        let mut capture = capture.borrow_mut();
+        let Some(capture) = capture.as_mut() else {
+            return false;
+        };
+        if capture.bytes + message.len() > MAX_CAPTURED_BYTES {
+            capture.truncated = true;
+        } else {
+            capture.bytes += message.len();
+            capture.lines.push(FezzWireLog {
+                level: level.to_string(),
+                message: message.clone(),
+            });
+        }
+        true
+    });
+    if !captured {
+        eprintln!("[{}] {}", level, message);
+    }
+}
+
+/// Starts capturing on the current thread. Called by `#[fezz_function]`.
+#[doc(hidden)]
+pub fn begin_capture() {
+    CAPTURE.with(|capture| {
+        *capture.borrow_mut() = Some(Capture {
+            lines: Vec::new(),
+            bytes: 0,
+            truncated: false,
+        });
+    });
+}
+
+/// Stops capturing and returns the lines. Called by `#[fezz_function]`.
+#[doc(hidden)]
+pub fn end_capture() -> Vec<FezzWireLog> {
+    let Some(capture) = CAPTURE.with(|capture| capture.borrow_mut().take()) else {
+        return Vec::new();
+    };
+    let mut lines = capture.lines;
+    if capture.truncated {
+        lines.push(FezzWireLog {
+            level: "warn".to_string(),
+            message: format!("log output truncated at {} bytes", MAX_CAPTURED_BYTES),
+        });
+    }
+    lines
+}
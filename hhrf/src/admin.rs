@@ -0,0 +1,507 @@
+use crate::{
+    auth::constant_time_eq, body, error_response, health, library_cache, AppState, FunctionKey,
+};
+use axum::{
+    extract::{Path, Query, Request, State},
+    http::{
+        header::{AUTHORIZATION, WWW_AUTHENTICATE},
+        HeaderMap, HeaderValue,
+    },
+    middleware::Next,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    fs,
+    io::Write,
+    path::{Path as FsPath, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+/// One deployable artifact kind, by the file name HHRF looks for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Artifact {
+    /// `fezz.so`, run by the `runner` or `in-process` backend.
+    Native,
+    /// `fezz.js`, run by the `js` backend.
+    Js,
+}
+
+impl Artifact {
+    pub fn file_name(&self) -> &'static str {
+        match self {
+            Artifact::Native => "fezz.so",
+            Artifact::Js => "fezz.js",
+        }
+    }
+}
+
+/// A deployed function version as reported by `GET /functions`.
+#[derive(Debug, Serialize)]
+pub struct DeployedVersion {
+    pub org: String,
+    pub func: String,
+    pub version: String,
+    pub artifact: Artifact,
+    pub size: u64,
+    pub sha256: String,
+    /// Resident in the in-process library cache.
+    pub loaded: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct UploadParams {
+    #[serde(default)]
+    artifact: Option<Artifact>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PurgeParams {
+    #[serde(default)]
+    path_prefix: Option<String>,
+}
+
+/// Routes of the admin API, served on `admin.listen` only.
+///
+/// - `GET /functions` lists every deployed version.
+/// - `PUT /functions/{org}/{func}/{version}[?artifact=js]` deploys the body
+///   as `fezz.so` (or `fezz.js`); versions are immutable, so an existing one
+///   is a 409.
+/// - `DELETE /functions/{org}/{func}/{version}` removes a version.
+/// - `POST /functions/{org}/{func}/{version}/evict` and `POST /cache/evict`
+///   unload one or all cached libraries.
+/// - `GET /functions/{org}/{func}/{version}/logs` returns the recent
+///   function log lines.
+/// - `GET /functions/{org}/{func}/{version}/health` loads the version and
+///   runs its health hook, e.g. before pointing an alias at it.
+/// - `POST /functions/{org}/{func}/{version}/purge[?path_prefix=/items]` and
+///   `POST /response-cache/purge` drop cached responses; version `*` purges
+///   every version of the function.
+pub fn router(state: Arc<AppState>) -> Router {
+    Router::new()
+        .route("/functions", get(list))
+        .route(
+            "/functions/:org/:func/:version",
+            axum::routing::put(upload).delete(remove),
+        )
+        .route("/functions/:org/:func/:version/evict", post(evict))
+        .route("/functions/:org/:func/:version/logs", get(logs))
+        .route("/functions/:org/:func/:version/health", get(check_health))
+        .route("/functions/:org/:func/:version/purge", post(purge))
+        .route("/cache/evict", post(evict_all))
+        .route("/response-cache/purge", post(purge_all))
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            require_token,
+        ))
+        .with_state(state)
+}
+
+/// Rejects requests without `Authorization: Bearer {admin.token}`.
+async fn require_token(State(state): State<Arc<AppState>>, req: Request, next: Next) -> Response {
+    if !has_token(req.headers(), &state.config().admin.token) {
+        let mut resp = error_response(401, "Invalid admin token".to_string());
+        resp.headers_mut().insert(
+            WWW_AUTHENTICATE,
+            HeaderValue::from_static("Bearer realm=\"hhrf-admin\""),
+        );
+        return resp;
+    }
+    next.run(req).await
+}
+
+/// Whether `headers` carry `Authorization: Bearer {token}`. Digests are
+/// compared, so the time taken does not depend on the token's length.
+fn has_token(headers: &HeaderMap, token: &str) -> bool {
+    let expected = Sha256::digest(token.as_bytes());
+    headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|token| constant_time_eq(&Sha256::digest(token.trim().as_bytes()), &expected))
+}
+
+async fn list(State(state): State<Arc<AppState>>) -> Response {
+    let functions_dir = functions_dir(&state);
+    let library_cache = state.library_cache.clone();
+    let listed = tokio::task::spawn_blocking(move || {
+        let mut versions = discover(&functions_dir)?;
+        for version in &mut versions {
+            version.loaded = version.artifact == Artifact::Native
+                && library_cache.contains(&FunctionKey {
+                    org: version.org.clone(),
+                    func: version.func.clone(),
+                    version: version.version.clone(),
+                });
+        }
+        Ok::<_, String>(versions)
+    })
+    .await;
+
+    match listed {
+        Ok(Ok(versions)) => Json(versions).into_response(),
+        Ok(Err(err)) => error_response(500, err),
+        Err(err) => error_response(500, format!("Failed to join listing task: {}", err)),
+    }
+}
+
+async fn upload(
+    State(state): State<Arc<AppState>>,
+    Path((org, func, version)): Path<(String, String, String)>,
+    Query(params): Query<UploadParams>,
+    req: Request,
+) -> Response {
+    let key = FunctionKey { org, func, version };
+    if !key.is_valid() {
+        return error_response(400, format!("Invalid function path '{}'", key));
+    }
+    let artifact = params.artifact.unwrap_or(Artifact::Native);
+
+    let (parts, body) = req.into_parts();
+    let max_upload_bytes = state.config().admin.max_upload_bytes;
+    let bytes = match body::read_limited(&parts.headers, body, max_upload_bytes).await {
+        Ok(bytes) => bytes,
+        Err(err) => return error_response(err.status(), err.to_string()),
+    };
+    if bytes.is_empty() {
+        return error_response(400, "Artifact body is empty".to_string());
+    }
+
+    let functions_dir = functions_dir(&state);
+    let deployed = tokio::task::spawn_blocking({
+        let key = key.clone();
+        move || deploy(&functions_dir, &key, artifact, &bytes)
+    })
+    .await;
+
+    match deployed {
+        Ok(Ok(deployed)) => {
+            tracing::info!("Admin: deployed {} ({:?}, {} bytes)", key, artifact, deployed.size);
+            (axum::http::StatusCode::CREATED, Json(deployed)).into_response()
+        }
+        Ok(Err((status, err))) => error_response(status, err),
+        Err(err) => error_response(500, format!("Failed to join upload task: {}", err)),
+    }
+}
+
+async fn remove(
+    State(state): State<Arc<AppState>>,
+    Path((org, func, version)): Path<(String, String, String)>,
+) -> Response {
+    let key = FunctionKey { org, func, version };
+    if !key.is_valid() {
+        return error_response(400, format!("Invalid function path '{}'", key));
+    }
+
+    let functions_dir = functions_dir(&state);
+    let removed = tokio::task::spawn_blocking({
+        let key = key.clone();
+        move || undeploy(&functions_dir, &key)
+    })
+    .await;
+
+    match removed {
+        Ok(Ok(())) => {
+            state.library_cache.remove(&key);
+            state
+                .response_cache
+                .purge(&key.org, &key.func, Some(&key.version), None);
+            tracing::info!("Admin: removed {}", key);
+            axum::http::StatusCode::NO_CONTENT.into_response()
+        }
+        Ok(Err((status, err))) => error_response(status, err),
+        Err(err) => error_response(500, format!("Failed to join delete task: {}", err)),
+    }
+}
+
+async fn evict(
+    State(state): State<Arc<AppState>>,
+    Path((org, func, version)): Path<(String, String, String)>,
+) -> Response {
+    let key = FunctionKey { org, func, version };
+    if !key.is_valid() {
+        return error_response(400, format!("Invalid function path '{}'", key));
+    }
+    let evicted = state.library_cache.remove(&key);
+    if evicted {
+        tracing::info!("Admin: evicted {} from the library cache", key);
+    }
+    Json(serde_json::json!({ "evicted": usize::from(evicted) })).into_response()
+}
+
+async fn evict_all(State(state): State<Arc<AppState>>) -> Response {
+    let evicted = state.library_cache.clear();
+    tracing::info!("Admin: evicted {} cached libraries", evicted);
+    Json(serde_json::json!({ "evicted": evicted })).into_response()
+}
+
+async fn purge(
+    State(state): State<Arc<AppState>>,
+    Path((org, func, version)): Path<(String, String, String)>,
+    Query(params): Query<PurgeParams>,
+) -> Response {
+    let key = FunctionKey { org, func, version };
+    if !key.is_valid() {
+        return error_response(400, format!("Invalid function path '{}'", key));
+    }
+    let FunctionKey { org, func, version } = key;
+    let version = Some(version.as_str()).filter(|version| *version != "*");
+    let purged = state
+        .response_cache
+        .purge(&org, &func, version, params.path_prefix.as_deref());
+    tracing::info!(
+        "Admin: purged {} cached responses of {}/{}/{}",
+        purged,
+        org,
+        func,
+        version.unwrap_or("*")
+    );
+    Json(serde_json::json!({ "purged": purged })).into_response()
+}
+
+async fn purge_all(State(state): State<Arc<AppState>>) -> Response {
+    let purged = state.response_cache.clear();
+    tracing::info!("Admin: purged {} cached responses", purged);
+    Json(serde_json::json!({ "purged": purged })).into_response()
+}
+
+async fn logs(
+    State(state): State<Arc<AppState>>,
+    Path((org, func, version)): Path<(String, String, String)>,
+) -> Response {
+    let key = FunctionKey { org, func, version };
+    if !key.is_valid() {
+        return error_response(400, format!("Invalid function path '{}'", key));
+    }
+    Json(state.recent_logs.get(&key)).into_response()
+}
+
+async fn check_health(
+    State(state): State<Arc<AppState>>,
+    Path((org, func, version)): Path<(String, String, String)>,
+) -> Response {
+    let key = FunctionKey { org, func, version };
+    if !key.is_valid() {
+        return error_response(400, format!("Invalid function path '{}'", key));
+    }
+    let timeout = Duration::from_millis(state.config().health.timeout_ms);
+    match health::probe(&state, &key.org, &key.func, &key.version, timeout).await {
+        Ok(()) => Json(serde_json::json!({ "healthy": true })).into_response(),
+        Err(err) => {
+            tracing::warn!("Admin: health check of {} failed: {}", key, err);
+            let body = serde_json::json!({ "healthy": false, "error": err });
+            (axum::http::StatusCode::SERVICE_UNAVAILABLE, Json(body)).into_response()
+        }
+    }
+}
+
+fn functions_dir(state: &AppState) -> PathBuf {
+    PathBuf::from(state.root()).join("functions")
+}
+
+/// Walks `functions/{org}/{func}/{version}`, skipping hidden entries such as
+/// in-progress uploads.
+fn discover(functions_dir: &FsPath) -> Result<Vec<DeployedVersion>, String> {
+    let mut versions = Vec::new();
+    for org in visible_dirs(functions_dir)? {
+        for func in visible_dirs(&functions_dir.join(&org))? {
+            for version in visible_dirs(&functions_dir.join(&org).join(&func))? {
+                let dir = functions_dir.join(&org).join(&func).join(&version);
+                for artifact in [Artifact::Native, Artifact::Js] {
+                    let path = dir.join(artifact.file_name());
+                    if !path.is_file() {
+                        continue;
+                    }
+                    versions.push(describe(&org, &func, &version, artifact, &path)?);
+                }
+            }
+        }
+    }
+    Ok(versions)
+}
+
+pub fn visible_dirs(dir: &FsPath) -> Result<Vec<String>, String> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(format!("Failed to read '{}': {}", dir.display(), err)),
+    };
+    let mut names = entries
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_ok_and(|kind| kind.is_dir()))
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter(|name| !name.starts_with('.'))
+        .collect::<Vec<_>>();
+    names.sort();
+    Ok(names)
+}
+
+fn describe(
+    org: &str,
+    func: &str,
+    version: &str,
+    artifact: Artifact,
+    path: &FsPath,
+) -> Result<DeployedVersion, String> {
+    let size = fs::metadata(path)
+        .map_err(|e| format!("Failed to stat '{}': {}", path.display(), e))?
+        .len();
+    Ok(DeployedVersion {
+        org: org.to_string(),
+        func: func.to_string(),
+        version: version.to_string(),
+        artifact,
+        size,
+        sha256: library_cache::hash_file(&path.to_string_lossy())?,
+        loaded: false,
+    })
+}
+
+/// Writes the artifact into a hidden staging directory next to the target
+/// and renames it into place, so requests never see a partial upload.
+fn deploy(
+    functions_dir: &FsPath,
+    key: &FunctionKey,
+    artifact: Artifact,
+    bytes: &[u8],
+) -> Result<DeployedVersion, (u16, String)> {
+    let func_dir = functions_dir.join(&key.org).join(&key.func);
+    let target = func_dir.join(&key.version);
+    if target.exists() {
+        return Err((409, format!("Version '{}' already exists", key)));
+    }
+    fs::create_dir_all(&func_dir)
+        .map_err(|e| (500, format!("Failed to create '{}': {}", func_dir.display(), e)))?;
+
+    let staging = func_dir.join(format!(".{}.upload-{}", key.version, uuid::Uuid::new_v4()));
+    let staged = stage(&staging, artifact, bytes).and_then(|()| {
+        fs::rename(&staging, &target).map_err(|e| {
+            format!("Failed to move upload into '{}': {}", target.display(), e)
+        })
+    });
+    if let Err(err) = staged {
+        let _ = fs::remove_dir_all(&staging);
+        // A concurrent upload of the same version won the rename.
+        let status = if target.exists() { 409 } else { 500 };
+        return Err((status, err));
+    }
+    if let Ok(dir) = fs::File::open(&func_dir) {
+        let _ = dir.sync_all();
+    }
+
+    describe(
+        &key.org,
+        &key.func,
+        &key.version,
+        artifact,
+        &target.join(artifact.file_name()),
+    )
+    .map_err(|err| (500, err))
+}
+
+fn stage(staging: &FsPath, artifact: Artifact, bytes: &[u8]) -> Result<(), String> {
+    fs::create_dir(staging)
+        .map_err(|e| format!("Failed to create '{}': {}", staging.display(), e))?;
+    let path = staging.join(artifact.file_name());
+    let mut file = fs::File::create(&path)
+        .map_err(|e| format!("Failed to create '{}': {}", path.display(), e))?;
+    file.write_all(bytes)
+        .and_then(|()| file.sync_all())
+        .map_err(|e| format!("Failed to write '{}': {}", path.display(), e))
+}
+
+/// Renames the version out of the way first so it disappears atomically,
+/// then deletes it.
+fn undeploy(functions_dir: &FsPath, key: &FunctionKey) -> Result<(), (u16, String)> {
+    let func_dir = functions_dir.join(&key.org).join(&key.func);
+    let target = func_dir.join(&key.version);
+    if !target.is_dir() {
+        return Err((404, format!("Version '{}' not found", key)));
+    }
+
+    let trash = func_dir.join(format!(".{}.deleted-{}", key.version, uuid::Uuid::new_v4()));
+    fs::rename(&target, &trash)
+        .map_err(|e| (500, format!("Failed to remove '{}': {}", target.display(), e)))?;
+    fs::remove_dir_all(&trash)
+        .map_err(|e| (500, format!("Failed to delete '{}': {}", trash.display(), e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(version: &str) -> FunctionKey {
+        FunctionKey {
+            org: "acme".to_string(),
+            func: "todos".to_string(),
+            version: version.to_string(),
+        }
+    }
+
+    fn bearer(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, HeaderValue::from_str(value).unwrap());
+        headers
+    }
+
+    #[test]
+    fn only_the_admin_token_is_accepted() {
+        assert!(has_token(&bearer("Bearer s3cret"), "s3cret"));
+        assert!(!has_token(&bearer("Bearer s3cre"), "s3cret"));
+        assert!(!has_token(&bearer("Bearer s3cret2"), "s3cret"));
+        assert!(!has_token(&bearer("Basic s3cret"), "s3cret"));
+        assert!(!has_token(&bearer("s3cret"), "s3cret"));
+        assert!(!has_token(&HeaderMap::new(), "s3cret"));
+    }
+
+    #[test]
+    fn upload_lands_whole_and_leaves_no_staging() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = b"export default 1";
+        let deployed = deploy(dir.path(), &key("0.1.0"), Artifact::Js, source).unwrap();
+        assert_eq!((deployed.size, deployed.artifact), (16, Artifact::Js));
+
+        let func_dir = dir.path().join("acme").join("todos");
+        let written = fs::read(func_dir.join("0.1.0").join("fezz.js")).unwrap();
+        assert_eq!(written, source);
+        assert_eq!(visible_dirs(&func_dir).unwrap(), ["0.1.0"]);
+        assert_eq!(fs::read_dir(&func_dir).unwrap().count(), 1);
+
+        let listed = discover(dir.path()).unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].sha256, deployed.sha256);
+    }
+
+    #[test]
+    fn existing_version_is_a_conflict() {
+        let dir = tempfile::tempdir().unwrap();
+        deploy(dir.path(), &key("0.1.0"), Artifact::Native, b"v1").unwrap();
+        let (status, _) = deploy(dir.path(), &key("0.1.0"), Artifact::Native, b"v2").unwrap_err();
+        assert_eq!(status, 409);
+
+        let path = dir.path().join("acme/todos/0.1.0/fezz.so");
+        assert_eq!(fs::read(path).unwrap(), b"v1");
+    }
+
+    #[test]
+    fn undeploy_removes_the_version() {
+        let dir = tempfile::tempdir().unwrap();
+        deploy(dir.path(), &key("0.1.0"), Artifact::Native, b"v1").unwrap();
+        deploy(dir.path(), &key("0.2.0"), Artifact::Native, b"v2").unwrap();
+
+        undeploy(dir.path(), &key("0.1.0")).unwrap();
+        let func_dir = dir.path().join("acme").join("todos");
+        assert_eq!(fs::read_dir(&func_dir).unwrap().count(), 1);
+        assert_eq!(visible_dirs(&func_dir).unwrap(), ["0.2.0"]);
+
+        let (status, _) = undeploy(dir.path(), &key("0.1.0")).unwrap_err();
+        assert_eq!(status, 404);
+        // The version can be deployed again once it is gone.
+        assert!(deploy(dir.path(), &key("0.1.0"), Artifact::Native, b"v3").is_ok());
+    }
+}
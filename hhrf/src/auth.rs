@@ -0,0 +1,615 @@
+use crate::config::{AuthConfig, HmacConfig, JwtConfig};
+use axum::{
+    extract::OriginalUri,
+    http::{
+        header::{AUTHORIZATION, WWW_AUTHENTICATE},
+        request::Parts,
+        HeaderValue,
+    },
+};
+use hmac::{Hmac, Mac};
+use jsonwebtoken::{jwk::JwkSet, Algorithm, DecodingKey, Validation};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const HMAC_KEY_ID_HEADER: &str = "x-fezz-key-id";
+const HMAC_TIMESTAMP_HEADER: &str = "x-fezz-timestamp";
+const HMAC_SIGNATURE_HEADER: &str = "x-fezz-signature";
+
+/// The verified caller, forwarded to the function through `FezzWireMeta`.
+#[derive(Clone, Debug, Default)]
+pub struct Identity {
+    /// API key principal, JWT `sub` or HMAC key id.
+    pub principal: Option<String>,
+    /// Verified JWT claims; non-string values are JSON-encoded.
+    pub claims: BTreeMap<String, String>,
+}
+
+/// Why a request was rejected (401).
+#[derive(Debug)]
+pub struct AuthError {
+    message: String,
+    bearer: bool,
+}
+
+impl AuthError {
+    fn new(auth: &AuthConfig, message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            bearer: auth.jwt.is_some(),
+        }
+    }
+
+    pub fn response(&self) -> axum::response::Response {
+        let mut resp = crate::error_response(401, self.message.clone());
+        if self.bearer {
+            resp.headers_mut().insert(
+                WWW_AUTHENTICATE,
+                HeaderValue::from_static("Bearer realm=\"hhrf\""),
+            );
+        }
+        resp
+    }
+}
+
+struct CachedJwks {
+    stamp: (SystemTime, u64),
+    keys: Arc<JwkSet>,
+}
+
+/// Verifies the credentials required by a function's `auth` config.
+///
+/// JWKS files are cached and re-read when their mtime or size changes, so
+/// keys can be rotated without a restart.
+#[derive(Default)]
+pub struct Authenticator {
+    jwks: Mutex<HashMap<PathBuf, CachedJwks>>,
+}
+
+impl Authenticator {
+    /// Checks every credential the request carries, in the order API key,
+    /// bearer token, HMAC signature, and accepts the first that verifies; a
+    /// stale API key does not shadow a valid token. When none verifies, the
+    /// last failure is reported. A function without any configured method is
+    /// public and yields an anonymous identity.
+    pub fn authenticate(
+        &self,
+        auth: &AuthConfig,
+        parts: &Parts,
+        body: &[u8],
+        api_key_header: &str,
+    ) -> Result<Identity, AuthError> {
+        if auth.is_public() {
+            return Ok(Identity::default());
+        }
+
+        let mut failure = None;
+        if !auth.api_keys.is_empty() {
+            if let Some(api_key) = header_str(parts, api_key_header) {
+                match verify_api_key(auth, api_key) {
+                    Some(identity) => return Ok(identity),
+                    None => failure = Some("Invalid API key".to_string()),
+                }
+            }
+        }
+
+        if let Some(jwt) = &auth.jwt {
+            if let Some(token) = header_str(parts, AUTHORIZATION.as_str())
+                .and_then(|value| value.strip_prefix("Bearer "))
+            {
+                match self.verify_jwt(jwt, token.trim()) {
+                    Ok(identity) => return Ok(identity),
+                    Err(message) => failure = Some(message),
+                }
+            }
+        }
+
+        if let Some(hmac) = &auth.hmac {
+            if header_str(parts, HMAC_SIGNATURE_HEADER).is_some() {
+                match verify_hmac(hmac, parts, body) {
+                    Ok(identity) => return Ok(identity),
+                    Err(message) => failure = Some(message),
+                }
+            }
+        }
+
+        Err(AuthError::new(
+            auth,
+            failure.unwrap_or_else(|| "Missing credentials".to_string()),
+        ))
+    }
+
+    fn verify_jwt(&self, jwt: &JwtConfig, token: &str) -> Result<Identity, String> {
+        let header = jsonwebtoken::decode_header(token)
+            .map_err(|e| format!("Invalid bearer token: {}", e))?;
+        if !matches!(header.alg, Algorithm::HS256 | Algorithm::RS256 | Algorithm::ES256) {
+            return Err(format!("Unsupported token algorithm {:?}", header.alg));
+        }
+
+        let jwks = self.jwks(&jwt.jwks_file)?;
+        let jwk = match &header.kid {
+            Some(kid) => jwks.find(kid),
+            None if jwks.keys.len() == 1 => jwks.keys.first(),
+            None => None,
+        }
+        .ok_or_else(|| "No matching key for bearer token".to_string())?;
+        let key = DecodingKey::from_jwk(jwk).map_err(|e| format!("Unusable JWK: {}", e))?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.leeway = jwt.leeway_secs;
+        if let Some(issuer) = &jwt.issuer {
+            validation.set_issuer(&[issuer]);
+        }
+        match &jwt.audience {
+            Some(audience) => validation.set_audience(&[audience]),
+            None => validation.validate_aud = false,
+        }
+
+        let data = jsonwebtoken::decode::<serde_json::Map<String, serde_json::Value>>(
+            token,
+            &key,
+            &validation,
+        )
+        .map_err(|e| format!("Invalid bearer token: {}", e))?;
+
+        let claims = data
+            .claims
+            .into_iter()
+            .map(|(name, value)| match value {
+                serde_json::Value::String(value) => (name, value),
+                value => (name, value.to_string()),
+            })
+            .collect::<BTreeMap<_, _>>();
+        Ok(Identity {
+            principal: claims.get("sub").cloned(),
+            claims,
+        })
+    }
+
+    fn jwks(&self, path: &Path) -> Result<Arc<JwkSet>, String> {
+        let metadata = fs::metadata(path)
+            .map_err(|e| format!("Failed to stat JWKS '{}': {}", path.display(), e))?;
+        let stamp = (metadata.modified().unwrap_or(UNIX_EPOCH), metadata.len());
+
+        let mut cache = self.jwks.lock().unwrap();
+        if let Some(cached) = cache.get(path) {
+            if cached.stamp == stamp {
+                return Ok(cached.keys.clone());
+            }
+        }
+        let keys = Arc::new(load_jwks(path)?);
+        cache.insert(
+            path.to_path_buf(),
+            CachedJwks {
+                stamp,
+                keys: keys.clone(),
+            },
+        );
+        Ok(keys)
+    }
+}
+
+/// Reads and parses a JWKS file.
+pub fn load_jwks(path: &Path) -> Result<JwkSet, String> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read JWKS '{}': {}", path.display(), e))?;
+    serde_json::from_str(&contents)
+        .map_err(|e| format!("Invalid JWKS '{}': {}", path.display(), e))
+}
+
+/// Compares SHA-256 digests so that neither length nor content leaks through timing.
+fn verify_api_key(auth: &AuthConfig, api_key: &str) -> Option<Identity> {
+    let presented = Sha256::digest(api_key.as_bytes());
+    let mut matched = None;
+    for entry in &auth.api_keys {
+        let expected = Sha256::digest(entry.key.as_bytes());
+        if constant_time_eq(&presented, &expected) && matched.is_none() {
+            matched = Some(entry.principal.clone());
+        }
+    }
+    matched.map(|principal| Identity {
+        principal: Some(principal),
+        claims: BTreeMap::new(),
+    })
+}
+
+/// Verifies `x-fezz-signature`: hex HMAC-SHA256 over
+/// `{timestamp}\n{METHOD}\n{path_and_query}\n{hex sha256(body)}`.
+fn verify_hmac(hmac: &HmacConfig, parts: &Parts, body: &[u8]) -> Result<Identity, String> {
+    let key_id = header_str(parts, HMAC_KEY_ID_HEADER)
+        .ok_or_else(|| format!("Missing {} header", HMAC_KEY_ID_HEADER))?;
+    let timestamp = header_str(parts, HMAC_TIMESTAMP_HEADER)
+        .ok_or_else(|| format!("Missing {} header", HMAC_TIMESTAMP_HEADER))?;
+    let signature = header_str(parts, HMAC_SIGNATURE_HEADER)
+        .and_then(decode_hex)
+        .ok_or_else(|| format!("Malformed {} header", HMAC_SIGNATURE_HEADER))?;
+
+    let signed_at = timestamp
+        .parse::<u64>()
+        .map_err(|_| format!("Malformed {} header", HMAC_TIMESTAMP_HEADER))?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    if now.abs_diff(signed_at) > hmac.max_skew_secs {
+        return Err("Request signature expired".to_string());
+    }
+
+    let secret = hmac
+        .keys
+        .iter()
+        .find(|key| key.key_id == key_id)
+        .map(|key| key.secret.as_bytes())
+        .ok_or_else(|| "Unknown signing key".to_string())?;
+
+    // The target the client sent, not the `/rpc/...` it was rewritten to.
+    let uri = parts
+        .extensions
+        .get::<OriginalUri>()
+        .map_or(&parts.uri, |OriginalUri(uri)| uri);
+    let path_and_query = uri
+        .path_and_query()
+        .map(|path| path.as_str())
+        .unwrap_or("/");
+    let body_hash = hex(&Sha256::digest(body));
+    let canonical = format!(
+        "{}\n{}\n{}\n{}",
+        timestamp, parts.method, path_and_query, body_hash
+    );
+
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret)
+        .map_err(|e| format!("Invalid signing key: {}", e))?;
+    mac.update(canonical.as_bytes());
+    mac.verify_slice(&signature)
+        .map_err(|_| "Invalid request signature".to_string())?;
+
+    Ok(Identity {
+        principal: Some(key_id.to_string()),
+        claims: BTreeMap::new(),
+    })
+}
+
+fn header_str<'a>(parts: &'a Parts, name: &str) -> Option<&'a str> {
+    parts
+        .headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+}
+
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn decode_hex(value: &str) -> Option<Vec<u8>> {
+    value
+        .as_bytes()
+        .chunks(2)
+        .map(|pair| {
+            let pair = std::str::from_utf8(pair).ok().filter(|pair| pair.len() == 2)?;
+            u8::from_str_radix(pair, 16).ok()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ApiKeyConfig, HmacKeyConfig};
+    use axum::http::Request;
+    use jsonwebtoken::{EncodingKey, Header};
+
+    const API_KEY_HEADER: &str = "x-api-key";
+    const SECRET_ONE: &[u8] = b"fezz-test-secret-one-0123456789ab";
+    const SECRET_TWO: &[u8] = b"fezz-test-secret-two-0123456789ab";
+    /// `SECRET_ONE` and `SECRET_TWO`, base64url without padding.
+    const JWK_ONE: &str = concat!(
+        r#"{"keys":[{"kty":"oct","kid":"one","alg":"HS256","#,
+        r#""k":"ZmV6ei10ZXN0LXNlY3JldC1vbmUtMDEyMzQ1Njc4OWFi"}]}"#
+    );
+    const JWK_TWO: &str = concat!(
+        r#"{"keys":[{"kty":"oct","kid":"two-rotated","alg":"HS256","#,
+        r#""k":"ZmV6ei10ZXN0LXNlY3JldC10d28tMDEyMzQ1Njc4OWFi"}]}"#
+    );
+
+    fn parts(method: &str, uri: &str, headers: &[(&str, &str)]) -> Parts {
+        let mut builder = Request::builder().method(method).uri(uri);
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+        builder.body(()).unwrap().into_parts().0
+    }
+
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    fn api_keys() -> Vec<ApiKeyConfig> {
+        vec![ApiKeyConfig {
+            principal: "ci".to_string(),
+            key: "valid-key".to_string(),
+        }]
+    }
+
+    fn jwt_config(jwks_file: &Path) -> JwtConfig {
+        JwtConfig {
+            jwks_file: jwks_file.to_path_buf(),
+            issuer: Some("https://issuer.example.com".to_string()),
+            audience: None,
+            leeway_secs: 0,
+        }
+    }
+
+    fn token(kid: &str, secret: &[u8], issuer: &str, exp: u64) -> String {
+        let header = Header {
+            kid: Some(kid.to_string()),
+            ..Header::new(Algorithm::HS256)
+        };
+        let claims = serde_json::json!({
+            "sub": "user-1",
+            "iss": issuer,
+            "exp": exp,
+            "admin": true,
+        });
+        jsonwebtoken::encode(&header, &claims, &EncodingKey::from_secret(secret)).unwrap()
+    }
+
+    fn hmac_config() -> HmacConfig {
+        HmacConfig {
+            keys: vec![HmacKeyConfig {
+                key_id: "partner".to_string(),
+                secret: "hmac-secret".to_string(),
+            }],
+            max_skew_secs: 300,
+        }
+    }
+
+    fn sign(secret: &str, timestamp: u64, method: &str, path: &str, body: &[u8]) -> String {
+        let canonical = format!(
+            "{}\n{}\n{}\n{}",
+            timestamp,
+            method,
+            path,
+            hex(&Sha256::digest(body))
+        );
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+        mac.update(canonical.as_bytes());
+        hex(&mac.finalize().into_bytes())
+    }
+
+    fn jwks_file(dir: &tempfile::TempDir, contents: &str) -> PathBuf {
+        let path = dir.path().join("jwks.json");
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn public_function_needs_no_credentials() {
+        let identity = Authenticator::default()
+            .authenticate(&AuthConfig::default(), &parts("GET", "/", &[]), b"", API_KEY_HEADER)
+            .unwrap();
+        assert!(identity.principal.is_none());
+    }
+
+    #[test]
+    fn api_key_is_verified() {
+        let auth = AuthConfig {
+            api_keys: api_keys(),
+            ..Default::default()
+        };
+        let authenticator = Authenticator::default();
+
+        let valid = parts("GET", "/", &[(API_KEY_HEADER, "valid-key")]);
+        let identity = authenticator
+            .authenticate(&auth, &valid, b"", API_KEY_HEADER)
+            .unwrap();
+        assert_eq!(identity.principal.as_deref(), Some("ci"));
+
+        let wrong = parts("GET", "/", &[(API_KEY_HEADER, "valid-kez")]);
+        let err = authenticator
+            .authenticate(&auth, &wrong, b"", API_KEY_HEADER)
+            .unwrap_err();
+        assert_eq!(err.message, "Invalid API key");
+
+        let missing = parts("GET", "/", &[]);
+        let err = authenticator
+            .authenticate(&auth, &missing, b"", API_KEY_HEADER)
+            .unwrap_err();
+        assert_eq!(err.message, "Missing credentials");
+    }
+
+    #[test]
+    fn jwt_is_verified_against_jwks() {
+        let dir = tempfile::tempdir().unwrap();
+        let auth = AuthConfig {
+            jwt: Some(jwt_config(&jwks_file(&dir, JWK_ONE))),
+            ..Default::default()
+        };
+        let authenticator = Authenticator::default();
+        let issuer = "https://issuer.example.com";
+
+        let bearer = format!("Bearer {}", token("one", SECRET_ONE, issuer, now() + 600));
+        let request = parts("GET", "/", &[("authorization", bearer.as_str())]);
+        let identity = authenticator
+            .authenticate(&auth, &request, b"", "")
+            .unwrap();
+        assert_eq!(identity.principal.as_deref(), Some("user-1"));
+        assert_eq!(identity.claims.get("admin").map(String::as_str), Some("true"));
+
+        for bad in [
+            token("one", SECRET_TWO, issuer, now() + 600),
+            token("one", SECRET_ONE, "https://other.example.com", now() + 600),
+            token("one", SECRET_ONE, issuer, now() - 600),
+            token("unknown", SECRET_ONE, issuer, now() + 600),
+        ] {
+            let bearer = format!("Bearer {}", bad);
+            let request = parts("GET", "/", &[("authorization", bearer.as_str())]);
+            let err = authenticator
+                .authenticate(&auth, &request, b"", "")
+                .unwrap_err();
+            assert!(err.bearer);
+        }
+    }
+
+    #[test]
+    fn rotated_jwks_is_picked_up() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = jwks_file(&dir, JWK_ONE);
+        let auth = AuthConfig {
+            jwt: Some(jwt_config(&path)),
+            ..Default::default()
+        };
+        let authenticator = Authenticator::default();
+        let issuer = "https://issuer.example.com";
+        let old = format!("Bearer {}", token("one", SECRET_ONE, issuer, now() + 600));
+        let new = format!("Bearer {}", token("two-rotated", SECRET_TWO, issuer, now() + 600));
+
+        let request = |bearer: &str| parts("GET", "/", &[("authorization", bearer)]);
+        assert!(authenticator.authenticate(&auth, &request(&old), b"", "").is_ok());
+        assert!(authenticator.authenticate(&auth, &request(&new), b"", "").is_err());
+
+        // A different size changes the stamp even within one mtime tick.
+        fs::write(&path, JWK_TWO).unwrap();
+        assert!(authenticator.authenticate(&auth, &request(&new), b"", "").is_ok());
+        assert!(authenticator.authenticate(&auth, &request(&old), b"", "").is_err());
+    }
+
+    #[test]
+    fn hmac_signature_is_verified() {
+        let auth = AuthConfig {
+            hmac: Some(hmac_config()),
+            ..Default::default()
+        };
+        let authenticator = Authenticator::default();
+        let path = "/rpc/acme/todos/1/items?page=2";
+        let body = br#"{"title":"x"}"#;
+        let timestamp = now();
+
+        let signed = |secret: &str, timestamp: u64, body: &[u8]| {
+            let signature = sign(secret, timestamp, "POST", path, body);
+            let timestamp = timestamp.to_string();
+            parts(
+                "POST",
+                path,
+                &[
+                    (HMAC_KEY_ID_HEADER, "partner"),
+                    (HMAC_TIMESTAMP_HEADER, timestamp.as_str()),
+                    (HMAC_SIGNATURE_HEADER, signature.as_str()),
+                ],
+            )
+        };
+
+        let identity = authenticator
+            .authenticate(&auth, &signed("hmac-secret", timestamp, body), body, "")
+            .unwrap();
+        assert_eq!(identity.principal.as_deref(), Some("partner"));
+
+        let tampered = signed("hmac-secret", timestamp, body);
+        assert!(authenticator.authenticate(&auth, &tampered, b"{}", "").is_err());
+        let wrong_secret = signed("other-secret", timestamp, body);
+        assert!(authenticator.authenticate(&auth, &wrong_secret, body, "").is_err());
+        let expired = signed("hmac-secret", timestamp - 3600, body);
+        let err = authenticator.authenticate(&auth, &expired, body, "").unwrap_err();
+        assert_eq!(err.message, "Request signature expired");
+    }
+
+    #[test]
+    fn hmac_covers_the_target_the_client_sent() {
+        let auth = AuthConfig {
+            hmac: Some(hmac_config()),
+            ..Default::default()
+        };
+        let resolved = crate::FunctionKey {
+            org: "acme".to_string(),
+            func: "todos".to_string(),
+            version: "0.2.0".to_string(),
+        };
+        for sent in ["/rpc/acme/todos/stable/items?page=2", "/todos/items?page=2"] {
+            let timestamp = now();
+            let signature = sign("hmac-secret", timestamp, "GET", sent, b"");
+            let timestamp = timestamp.to_string();
+            let mut req = Request::get(sent)
+                .header(HMAC_KEY_ID_HEADER, "partner")
+                .header(HMAC_TIMESTAMP_HEADER, timestamp.as_str())
+                .header(HMAC_SIGNATURE_HEADER, signature.as_str())
+                .body(axum::body::Body::empty())
+                .unwrap();
+            crate::request_meta::keep_original_uri(&mut req);
+            // What an alias or a host route turns the request into.
+            *req.uri_mut() = "/rpc/acme/todos/stable/items?page=2".parse().unwrap();
+            crate::rewrite_version(&mut req, "rpc", &resolved);
+            assert_eq!(req.uri(), "/rpc/acme/todos/0.2.0/items?page=2");
+
+            let (parts, _) = req.into_parts();
+            let identity = Authenticator::default()
+                .authenticate(&auth, &parts, b"", "")
+                .unwrap();
+            assert_eq!(identity.principal.as_deref(), Some("partner"));
+        }
+    }
+
+    #[test]
+    fn failed_method_falls_through_to_the_next() {
+        let dir = tempfile::tempdir().unwrap();
+        let auth = AuthConfig {
+            api_keys: api_keys(),
+            jwt: Some(jwt_config(&jwks_file(&dir, JWK_ONE))),
+            hmac: Some(hmac_config()),
+        };
+        let authenticator = Authenticator::default();
+        let issuer = "https://issuer.example.com";
+
+        let bearer = format!("Bearer {}", token("one", SECRET_ONE, issuer, now() + 600));
+        let stale_key_with_jwt = parts(
+            "GET",
+            "/",
+            &[(API_KEY_HEADER, "stale-key"), ("authorization", bearer.as_str())],
+        );
+        let identity = authenticator
+            .authenticate(&auth, &stale_key_with_jwt, b"", API_KEY_HEADER)
+            .unwrap();
+        assert_eq!(identity.principal.as_deref(), Some("user-1"));
+
+        let timestamp = now();
+        let signature = sign("hmac-secret", timestamp, "GET", "/", b"");
+        let timestamp = timestamp.to_string();
+        let stale_key_with_hmac = parts(
+            "GET",
+            "/",
+            &[
+                (API_KEY_HEADER, "stale-key"),
+                (HMAC_KEY_ID_HEADER, "partner"),
+                (HMAC_TIMESTAMP_HEADER, timestamp.as_str()),
+                (HMAC_SIGNATURE_HEADER, signature.as_str()),
+            ],
+        );
+        let identity = authenticator
+            .authenticate(&auth, &stale_key_with_hmac, b"", API_KEY_HEADER)
+            .unwrap();
+        assert_eq!(identity.principal.as_deref(), Some("partner"));
+
+        let all_bad = parts(
+            "GET",
+            "/",
+            &[(API_KEY_HEADER, "stale-key"), ("authorization", "Bearer not-a-jwt")],
+        );
+        let err = authenticator
+            .authenticate(&auth, &all_bad, b"", API_KEY_HEADER)
+            .unwrap_err();
+        assert!(err.message.starts_with("Invalid bearer token"));
+    }
+}
@@ -0,0 +1,106 @@
+use axum::{
+    body::{Body, Bytes},
+    http::{header::CONTENT_LENGTH, HeaderMap},
+};
+use http_body_util::{BodyExt, LengthLimitError, Limited};
+use std::fmt;
+
+#[derive(Debug)]
+pub enum BodyError {
+    /// The body is larger than the allowed limit (in bytes).
+    TooLarge(u64),
+    /// A `Content-Encoding` HHRF cannot decode.
+    UnsupportedEncoding(String),
+    Read(String),
+}
+
+impl BodyError {
+    pub fn status(&self) -> u16 {
+        match self {
+            BodyError::TooLarge(_) => 413,
+            BodyError::UnsupportedEncoding(_) => 415,
+            BodyError::Read(_) => 400,
+        }
+    }
+}
+
+impl fmt::Display for BodyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BodyError::TooLarge(limit) => {
+                write!(f, "Request body exceeds the limit of {} bytes", limit)
+            }
+            BodyError::UnsupportedEncoding(encoding) => {
+                write!(f, "Unsupported request Content-Encoding '{}'", encoding)
+            }
+            BodyError::Read(message) => write!(f, "Failed to read request body: {}", message),
+        }
+    }
+}
+
+/// Reads a request body of at most `limit` bytes.
+///
+/// A declared `Content-Length` over the limit is rejected before any byte is
+/// read; otherwise (chunked or lying clients) the stream is cut off as soon as
+/// it crosses the limit, so nothing larger is ever buffered.
+pub async fn read_limited(headers: &HeaderMap, body: Body, limit: u64) -> Result<Bytes, BodyError> {
+    let declared = headers
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<u64>().ok());
+    if declared.is_some_and(|len| len > limit) {
+        return Err(BodyError::TooLarge(limit));
+    }
+
+    let limited = Limited::new(body, usize::try_from(limit).unwrap_or(usize::MAX));
+    match limited.collect().await {
+        Ok(collected) => Ok(collected.to_bytes()),
+        Err(err) if err.downcast_ref::<LengthLimitError>().is_some() => {
+            Err(BodyError::TooLarge(limit))
+        }
+        Err(err) => Err(BodyError::Read(err.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn declaring(len: u64) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_LENGTH, HeaderValue::from(len));
+        headers
+    }
+
+    fn body(len: usize) -> Body {
+        Body::from(vec![b'x'; len])
+    }
+
+    #[tokio::test]
+    async fn declared_length_over_the_limit_is_rejected_up_front() {
+        // The body itself is small: only the header is looked at.
+        let err = read_limited(&declaring(11), Body::from("tiny"), 10).await.unwrap_err();
+        assert!(matches!(err, BodyError::TooLarge(10)));
+        assert_eq!(err.status(), 413);
+    }
+
+    #[tokio::test]
+    async fn streamed_body_over_the_limit_is_cut_off() {
+        // No Content-Length, as with chunked uploads.
+        let err = read_limited(&HeaderMap::new(), body(16), 10).await.unwrap_err();
+        assert!(matches!(err, BodyError::TooLarge(10)));
+
+        // A client understating its Content-Length is caught the same way.
+        let err = read_limited(&declaring(8), body(16), 10).await.unwrap_err();
+        assert!(matches!(err, BodyError::TooLarge(10)));
+    }
+
+    #[tokio::test]
+    async fn body_at_the_limit_is_read() {
+        let bytes = read_limited(&HeaderMap::new(), body(10), 10).await.unwrap();
+        assert_eq!(bytes.len(), 10);
+        let bytes = read_limited(&declaring(10), Body::from("0123456789"), 10).await.unwrap();
+        assert_eq!(&bytes[..], b"0123456789");
+    }
+}
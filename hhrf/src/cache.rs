@@ -0,0 +1,1020 @@
+use crate::{
+    config::CacheConfig, invoke, routes, settings::FunctionSettings, AppState, FunctionKey,
+};
+use axum::{
+    body::{Body, Bytes, HttpBody},
+    http::{
+        header::{
+            AGE, AUTHORIZATION, CACHE_CONTROL, DATE, ETAG, EXPIRES, IF_MATCH,
+            IF_MODIFIED_SINCE, IF_NONE_MATCH, IF_RANGE, IF_UNMODIFIED_SINCE, LAST_MODIFIED,
+            PRAGMA, SET_COOKIE, VARY,
+        },
+        request::Parts,
+        HeaderMap, HeaderName, HeaderValue, Method, StatusCode,
+    },
+    response::Response,
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
+};
+
+/// Response header saying how the cache handled the request: `HIT`,
+/// `STALE`, `REVALIDATED`, `MISS` or `BYPASS`.
+pub const CACHE_STATUS_HEADER: &str = "x-fezz-cache";
+
+/// Statuses stored when the function gives them explicit freshness or a
+/// validator.
+const CACHEABLE_STATUSES: [u16; 8] = [200, 203, 204, 300, 301, 308, 404, 410];
+
+/// Headers a `304` carries that replace the stored ones.
+const REFRESHED_HEADERS: [HeaderName; 5] = [CACHE_CONTROL, DATE, ETAG, EXPIRES, LAST_MODIFIED];
+
+/// Cache key without `Vary`; GET and HEAD share it.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+struct Primary {
+    route: &'static str,
+    key: FunctionKey,
+    /// Normalized request host; `[[routes]]` can serve one function under
+    /// several hosts and the function may answer differently for each.
+    host: Option<String>,
+    /// Relative to the function, e.g. `/items?page=2`.
+    path_and_query: String,
+}
+
+impl Primary {
+    fn new(route: &'static str, key: &FunctionKey, parts: &Parts) -> Self {
+        Self {
+            route,
+            key: key.clone(),
+            host: routes::request_host(&parts.headers, &parts.uri),
+            path_and_query: key.function_path(route, &parts.uri),
+        }
+    }
+}
+
+/// `Cache-Control` directives HHRF acts on.
+#[derive(Debug, Default)]
+struct Directives {
+    no_store: bool,
+    no_cache: bool,
+    private: bool,
+    public: bool,
+    must_revalidate: bool,
+    max_age: Option<u64>,
+    s_maxage: Option<u64>,
+    stale_while_revalidate: Option<u64>,
+}
+
+impl Directives {
+    fn parse(headers: &HeaderMap) -> Self {
+        let mut directives = Self::default();
+        for directive in headers
+            .get_all(CACHE_CONTROL)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+        {
+            let (name, value) = match directive.split_once('=') {
+                Some((name, value)) => (name, Some(value.trim().trim_matches('"'))),
+                None => (directive, None),
+            };
+            let seconds = value.and_then(|value| value.parse::<u64>().ok());
+            match name.trim().to_ascii_lowercase().as_str() {
+                "no-store" => directives.no_store = true,
+                // `no-cache="field"` only restricts fields; treat it as a whole.
+                "no-cache" => directives.no_cache = true,
+                "private" => directives.private = true,
+                "public" => directives.public = true,
+                "must-revalidate" | "proxy-revalidate" => directives.must_revalidate = true,
+                "max-age" => directives.max_age = seconds.or(Some(0)),
+                "s-maxage" => directives.s_maxage = seconds.or(Some(0)),
+                "stale-while-revalidate" => directives.stale_while_revalidate = seconds,
+                _ => {}
+            }
+        }
+        directives
+    }
+}
+
+/// How long a stored response may be used, from its own headers.
+#[derive(Clone, Copy, Debug)]
+struct Policy {
+    /// `Age` the response already had when stored.
+    initial_age: Duration,
+    fresh_for: Duration,
+    stale_while_revalidate: Duration,
+    /// `no-cache`: revalidate before every use.
+    no_cache: bool,
+}
+
+impl Policy {
+    /// `None` when the response must not be stored. `authorized` responses
+    /// need `public`, `s-maxage` or `must-revalidate` to be shared.
+    fn of(headers: &HeaderMap, authorized: bool) -> Option<Self> {
+        let directives = Directives::parse(headers);
+        if directives.no_store || directives.private {
+            return None;
+        }
+        if authorized
+            && !(directives.public || directives.s_maxage.is_some() || directives.must_revalidate)
+        {
+            return None;
+        }
+
+        let fresh_for = match directives.s_maxage.or(directives.max_age) {
+            Some(seconds) => Some(Duration::from_secs(seconds)),
+            None => headers.get(EXPIRES).map(|expires| {
+                let expires = expires.to_str().ok().and_then(http_date);
+                let date = headers
+                    .get(DATE)
+                    .and_then(|date| date.to_str().ok())
+                    .and_then(http_date)
+                    .unwrap_or_else(SystemTime::now);
+                // Invalid dates such as `0` mean "already expired".
+                expires
+                    .and_then(|expires| expires.duration_since(date).ok())
+                    .unwrap_or_default()
+            }),
+        };
+        let has_validator = headers.contains_key(ETAG) || headers.contains_key(LAST_MODIFIED);
+        // No heuristic freshness: without explicit lifetime only `no-cache`
+        // responses with a validator are kept, for revalidation.
+        let fresh_for = match fresh_for {
+            Some(fresh_for) => fresh_for,
+            None if directives.no_cache => Duration::ZERO,
+            None => return None,
+        };
+        if (directives.no_cache || fresh_for.is_zero()) && !has_validator {
+            return None;
+        }
+
+        let initial_age = headers
+            .get(AGE)
+            .and_then(|age| age.to_str().ok())
+            .and_then(|age| age.trim().parse::<u64>().ok())
+            .map(Duration::from_secs)
+            .unwrap_or_default();
+        let stale_while_revalidate = if directives.must_revalidate {
+            Duration::ZERO
+        } else {
+            Duration::from_secs(directives.stale_while_revalidate.unwrap_or(0))
+        };
+        Some(Self {
+            initial_age,
+            fresh_for,
+            stale_while_revalidate,
+            no_cache: directives.no_cache,
+        })
+    }
+}
+
+enum Stored {
+    Memory(Bytes),
+    Disk(PathBuf),
+}
+
+struct Entry {
+    primary: Primary,
+    /// Request header values named by the response's `Vary`.
+    vary: Vec<(HeaderName, Option<HeaderValue>)>,
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Stored,
+    size: u64,
+    stored_at: Instant,
+    policy: Policy,
+    /// Position in [`Store::lru`].
+    last_used: u64,
+    /// A background revalidation is under way.
+    revalidating: bool,
+}
+
+impl Entry {
+    fn age(&self) -> Duration {
+        self.policy.initial_age + self.stored_at.elapsed()
+    }
+
+    fn has_validator(&self) -> bool {
+        self.headers.contains_key(ETAG) || self.headers.contains_key(LAST_MODIFIED)
+    }
+
+    fn matches(&self, headers: &HeaderMap) -> bool {
+        self.vary
+            .iter()
+            .all(|(name, value)| headers.get(name) == value.as_ref())
+    }
+}
+
+#[derive(Default)]
+struct Store {
+    entries: HashMap<u64, Entry>,
+    variants: HashMap<Primary, Vec<u64>>,
+    /// Entry ids by last use, oldest first.
+    lru: BTreeMap<u64, u64>,
+    bytes: u64,
+    next_id: u64,
+    clock: u64,
+}
+
+impl Store {
+    fn find(&self, primary: &Primary, headers: &HeaderMap) -> Option<u64> {
+        self.variants
+            .get(primary)?
+            .iter()
+            .copied()
+            .find(|id| self.entries[id].matches(headers))
+    }
+
+    fn touch(&mut self, id: u64) {
+        self.clock += 1;
+        if let Some(entry) = self.entries.get_mut(&id) {
+            self.lru.remove(&entry.last_used);
+            entry.last_used = self.clock;
+            self.lru.insert(self.clock, id);
+        }
+    }
+
+    fn insert(&mut self, id: u64, mut entry: Entry) {
+        self.clock += 1;
+        entry.last_used = self.clock;
+        self.lru.insert(self.clock, id);
+        self.bytes += entry.size;
+        self.variants
+            .entry(entry.primary.clone())
+            .or_default()
+            .push(id);
+        self.entries.insert(id, entry);
+    }
+
+    fn remove(&mut self, id: u64) -> Option<Entry> {
+        let entry = self.entries.remove(&id)?;
+        self.lru.remove(&entry.last_used);
+        self.bytes -= entry.size;
+        if let Some(ids) = self.variants.get_mut(&entry.primary) {
+            ids.retain(|other| *other != id);
+            if ids.is_empty() {
+                self.variants.remove(&entry.primary);
+            }
+        }
+        Some(entry)
+    }
+
+    /// Drops least recently used entries until `max_bytes` is respected.
+    fn evict(&mut self, max_bytes: u64) -> Vec<Entry> {
+        let mut evicted = Vec::new();
+        while self.bytes > max_bytes {
+            let Some((_, id)) = self.lru.pop_first() else {
+                break;
+            };
+            if let Some(entry) = self.remove(id) {
+                evicted.push(entry);
+            }
+        }
+        evicted
+    }
+}
+
+/// Conditional request headers for revalidating one entry.
+pub struct Validators {
+    id: u64,
+    headers: HeaderMap,
+}
+
+pub enum Lookup {
+    Fresh(Response),
+    /// Usable while the function is asked again in the background
+    /// (`validators` is `None` when someone else is already asking).
+    Stale {
+        resp: Response,
+        validators: Option<Validators>,
+    },
+    /// Must be revalidated before use.
+    Revalidate(Validators),
+    Miss,
+}
+
+/// In-memory (or disk-backed, with `cache.dir`) store of function responses,
+/// keyed by function version, path and query, and the request headers the
+/// response `Vary`s on.
+pub struct ResponseCache {
+    store: Mutex<Store>,
+    dir: Option<PathBuf>,
+}
+
+impl ResponseCache {
+    /// Starts empty; a configured `dir` is wiped, as its files belong to a
+    /// previous process.
+    pub fn new(config: &CacheConfig) -> Result<Self, String> {
+        if let Some(dir) = &config.dir {
+            if dir.exists() {
+                fs::remove_dir_all(dir).map_err(|e| {
+                    format!("Failed to clear cache dir '{}': {}", dir.display(), e)
+                })?;
+            }
+            fs::create_dir_all(dir)
+                .map_err(|e| format!("Failed to create cache dir '{}': {}", dir.display(), e))?;
+        }
+        Ok(Self {
+            store: Mutex::new(Store::default()),
+            dir: config.dir.clone(),
+        })
+    }
+
+    pub async fn lookup(&self, route: &'static str, key: &FunctionKey, parts: &Parts) -> Lookup {
+        let primary = Primary::new(route, key, parts);
+        let (id, status, headers, body, age, usable) = {
+            let mut store = self.store.lock().unwrap();
+            let Some(id) = store.find(&primary, &parts.headers) else {
+                return Lookup::Miss;
+            };
+            store.touch(id);
+            let entry = store.entries.get_mut(&id).unwrap();
+            let age = entry.age();
+            let policy = entry.policy;
+            let usable = if policy.no_cache {
+                Usable::NeedsRevalidation
+            } else if age < policy.fresh_for {
+                Usable::Fresh
+            } else if age < policy.fresh_for + policy.stale_while_revalidate {
+                let revalidate = !std::mem::replace(&mut entry.revalidating, true);
+                Usable::Stale { revalidate }
+            } else {
+                Usable::NeedsRevalidation
+            };
+            if usable == Usable::NeedsRevalidation {
+                if !entry.has_validator() {
+                    return Lookup::Miss;
+                }
+                return Lookup::Revalidate(Validators {
+                    id,
+                    headers: validators(&entry.headers),
+                });
+            }
+            let body = match &entry.body {
+                Stored::Memory(bytes) => Ok(bytes.clone()),
+                Stored::Disk(path) => Err(path.clone()),
+            };
+            (id, entry.status, entry.headers.clone(), body, age, usable)
+        };
+
+        let Some(body) = self.read_body(body).await else {
+            self.remove(id);
+            return Lookup::Miss;
+        };
+        let resp = cached_response(status, headers.clone(), body, age);
+        match usable {
+            Usable::Stale { revalidate } => Lookup::Stale {
+                resp,
+                validators: revalidate.then(|| Validators {
+                    id,
+                    headers: validators(&headers),
+                }),
+            },
+            _ => Lookup::Fresh(resp),
+        }
+    }
+
+    /// Stores `resp` if its headers allow it, and returns it (rebuilt from
+    /// the buffered body when stored).
+    pub async fn store(
+        &self,
+        config: &CacheConfig,
+        route: &'static str,
+        key: &FunctionKey,
+        parts: &Parts,
+        authorized: bool,
+        resp: Response,
+    ) -> Response {
+        if parts.method != Method::GET
+            || !CACHEABLE_STATUSES.contains(&resp.status().as_u16())
+            || resp.headers().contains_key(SET_COOKIE)
+        {
+            return resp;
+        }
+        let Some(policy) = Policy::of(resp.headers(), authorized) else {
+            return resp;
+        };
+        let Some(vary) = vary(resp.headers(), &parts.headers) else {
+            return resp;
+        };
+        // Function responses are fully buffered already, so the size is exact.
+        let fits = resp
+            .body()
+            .size_hint()
+            .exact()
+            .is_some_and(|size| size <= config.max_entry_bytes);
+        if !fits {
+            return resp;
+        }
+
+        let (resp_parts, body) = resp.into_parts();
+        let body = match axum::body::to_bytes(body, usize::MAX).await {
+            Ok(body) => body,
+            Err(err) => {
+                tracing::warn!("Failed to buffer response for the cache: {}", err);
+                return Response::from_parts(resp_parts, Body::empty());
+            }
+        };
+
+        let id = {
+            let mut store = self.store.lock().unwrap();
+            store.next_id += 1;
+            store.next_id
+        };
+        let stored = match &self.dir {
+            Some(dir) => {
+                let path = dir.join(id.to_string());
+                if let Err(err) = tokio::fs::write(&path, &body).await {
+                    tracing::warn!("Failed to write cache file '{}': {}", path.display(), err);
+                    return Response::from_parts(resp_parts, Body::from(body));
+                }
+                Stored::Disk(path)
+            }
+            None => Stored::Memory(body.clone()),
+        };
+        let mut headers = resp_parts.headers.clone();
+        headers.remove(AGE);
+        let entry = Entry {
+            primary: Primary::new(route, key, parts),
+            vary,
+            status: resp_parts.status,
+            headers,
+            body: stored,
+            size: body.len() as u64,
+            stored_at: Instant::now(),
+            policy,
+            last_used: 0,
+            revalidating: false,
+        };
+
+        let discarded = {
+            let mut store = self.store.lock().unwrap();
+            let mut discarded = Vec::new();
+            // A newer response replaces the variant it matches.
+            if let Some(old) = store.find(&entry.primary, &parts.headers) {
+                discarded.extend(store.remove(old));
+            }
+            store.insert(id, entry);
+            discarded.extend(store.evict(config.max_bytes));
+            discarded
+        };
+        discard(discarded);
+        Response::from_parts(resp_parts, Body::from(body))
+    }
+
+    /// Applies a `304` to entry `id` and returns the refreshed response, or
+    /// `None` if the entry is gone.
+    pub async fn refresh(&self, id: u64, not_modified: &HeaderMap) -> Option<Response> {
+        let (status, headers, body) = {
+            let mut store = self.store.lock().unwrap();
+            let entry = store.entries.get_mut(&id)?;
+            for name in REFRESHED_HEADERS {
+                if let Some(value) = not_modified.get(&name) {
+                    entry.headers.insert(name, value.clone());
+                }
+            }
+            // Keep the old policy if the merged headers no longer allow
+            // storing; the entry is replaced on the next full response anyway.
+            if let Some(policy) = Policy::of(&entry.headers, false) {
+                entry.policy = policy;
+            }
+            entry.policy.initial_age = Duration::ZERO;
+            entry.stored_at = Instant::now();
+            entry.revalidating = false;
+            let body = match &entry.body {
+                Stored::Memory(bytes) => Ok(bytes.clone()),
+                Stored::Disk(path) => Err(path.clone()),
+            };
+            (entry.status, entry.headers.clone(), body)
+        };
+        let body = self.read_body(body).await?;
+        Some(cached_response(status, headers, body, Duration::ZERO))
+    }
+
+    /// Lets a failed background revalidation be retried.
+    pub fn revalidation_failed(&self, id: u64) {
+        if let Some(entry) = self.store.lock().unwrap().entries.get_mut(&id) {
+            entry.revalidating = false;
+        }
+    }
+
+    pub fn remove(&self, id: u64) {
+        let removed = self.store.lock().unwrap().remove(id);
+        discard(removed.into_iter().collect());
+    }
+
+    /// Drops the entries of `org/func` (of one `version`, or all versions
+    /// when `None`) whose path starts with `path_prefix`.
+    pub fn purge(
+        &self,
+        org: &str,
+        func: &str,
+        version: Option<&str>,
+        path_prefix: Option<&str>,
+    ) -> usize {
+        let discarded = {
+            let mut store = self.store.lock().unwrap();
+            let ids = store
+                .entries
+                .iter()
+                .filter(|(_, entry)| {
+                    let key = &entry.primary.key;
+                    key.org == org
+                        && key.func == func
+                        && !version.is_some_and(|version| key.version != version)
+                        && !path_prefix.is_some_and(|prefix| {
+                            !entry.primary.path_and_query.starts_with(prefix)
+                        })
+                })
+                .map(|(id, _)| *id)
+                .collect::<Vec<_>>();
+            ids.into_iter()
+                .filter_map(|id| store.remove(id))
+                .collect::<Vec<_>>()
+        };
+        let purged = discarded.len();
+        discard(discarded);
+        purged
+    }
+
+    pub fn clear(&self) -> usize {
+        let discarded = {
+            let mut store = self.store.lock().unwrap();
+            let ids = store.entries.keys().copied().collect::<Vec<_>>();
+            ids.into_iter()
+                .filter_map(|id| store.remove(id))
+                .collect::<Vec<_>>()
+        };
+        let purged = discarded.len();
+        discard(discarded);
+        purged
+    }
+
+    async fn read_body(&self, body: Result<Bytes, PathBuf>) -> Option<Bytes> {
+        match body {
+            Ok(bytes) => Some(bytes),
+            Err(path) => match tokio::fs::read(&path).await {
+                Ok(bytes) => Some(Bytes::from(bytes)),
+                Err(err) => {
+                    tracing::warn!("Failed to read cache file '{}': {}", path.display(), err);
+                    None
+                }
+            },
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Usable {
+    Fresh,
+    Stale { revalidate: bool },
+    NeedsRevalidation,
+}
+
+/// Serves a GET or HEAD through the response cache, invoking the function
+/// on a miss and storing what it returns.
+pub async fn handle(
+    state: Arc<AppState>,
+    route: &'static str,
+    key: FunctionKey,
+    settings: FunctionSettings,
+    parts: Parts,
+    body: Bytes,
+) -> Response {
+    let request = Directives::parse(&parts.headers);
+    let cacheable_method = parts.method == Method::GET || parts.method == Method::HEAD;
+    if !cacheable_method || !body.is_empty() || request.no_store {
+        let resp = invoke(state.clone(), route, key.clone(), settings, parts, body).await;
+        return finish(&state, &key, resp, "BYPASS");
+    }
+    let pragma_no_cache = parts
+        .headers
+        .get(PRAGMA)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.eq_ignore_ascii_case("no-cache"));
+
+    let lookup = if request.no_cache || pragma_no_cache {
+        Lookup::Miss
+    } else {
+        state.response_cache.lookup(route, &key, &parts).await
+    };
+    match lookup {
+        Lookup::Fresh(resp) => {
+            let resp = finish(&state, &key, resp, "HIT");
+            return conditional(resp, &parts);
+        }
+        Lookup::Stale { resp, validators } => {
+            if let Some(validators) = validators {
+                tokio::spawn(revalidate(
+                    state.clone(),
+                    route,
+                    key.clone(),
+                    settings,
+                    parts.clone(),
+                    validators,
+                ));
+            }
+            let resp = finish(&state, &key, resp, "STALE");
+            return conditional(resp, &parts);
+        }
+        Lookup::Revalidate(validators) => {
+            let resp = revalidate(
+                state.clone(),
+                route,
+                key.clone(),
+                settings,
+                parts.clone(),
+                validators,
+            )
+            .await;
+            return conditional(resp, &parts);
+        }
+        Lookup::Miss => {}
+    }
+
+    let authorized = is_authorized(&settings, &parts);
+    let resp = invoke(state.clone(), route, key.clone(), settings, parts.clone(), body).await;
+    let config = state.config();
+    let resp = state
+        .response_cache
+        .store(&config.cache, route, &key, &parts, authorized, resp)
+        .await;
+    finish(&state, &key, resp, "MISS")
+}
+
+/// Asks the function again with the entry's validators. A `304` refreshes
+/// the entry, a server error leaves it alone and anything else replaces it.
+async fn revalidate(
+    state: Arc<AppState>,
+    route: &'static str,
+    key: FunctionKey,
+    settings: FunctionSettings,
+    parts: Parts,
+    validators: Validators,
+) -> Response {
+    let mut conditional_parts = parts.clone();
+    for name in [IF_MATCH, IF_NONE_MATCH, IF_MODIFIED_SINCE, IF_UNMODIFIED_SINCE, IF_RANGE] {
+        conditional_parts.headers.remove(name);
+    }
+    conditional_parts.headers.extend(validators.headers);
+    let authorized = is_authorized(&settings, &parts);
+    let resp = invoke(
+        state.clone(),
+        route,
+        key.clone(),
+        settings.clone(),
+        conditional_parts,
+        Bytes::new(),
+    )
+    .await;
+
+    let resp = if resp.status() == StatusCode::NOT_MODIFIED {
+        if let Some(cached) = state.response_cache.refresh(validators.id, resp.headers()).await {
+            return finish(&state, &key, cached, "REVALIDATED");
+        }
+        // Purged meanwhile; the 304 was for HHRF's validators, not the client's.
+        invoke(state.clone(), route, key.clone(), settings, parts.clone(), Bytes::new()).await
+    } else {
+        resp
+    };
+    if resp.status().is_server_error() {
+        // Keep the old entry; a failing function shouldn't empty the cache.
+        state.response_cache.revalidation_failed(validators.id);
+        return finish(&state, &key, resp, "MISS");
+    }
+    state.response_cache.remove(validators.id);
+    let config = state.config();
+    let resp = state
+        .response_cache
+        .store(&config.cache, route, &key, &parts, authorized, resp)
+        .await;
+    finish(&state, &key, resp, "MISS")
+}
+
+/// Whether the response may contain data for one caller only, unless it
+/// says otherwise.
+fn is_authorized(settings: &FunctionSettings, parts: &Parts) -> bool {
+    !settings.auth.is_public() || parts.headers.contains_key(AUTHORIZATION)
+}
+
+fn finish(
+    state: &AppState,
+    key: &FunctionKey,
+    mut resp: Response,
+    result: &'static str,
+) -> Response {
+    state.metrics.record_cache(key, &result.to_ascii_lowercase());
+    resp.headers_mut()
+        .insert(CACHE_STATUS_HEADER, HeaderValue::from_static(result));
+    resp
+}
+
+/// Answers the client's own `If-None-Match` from a cached response, and
+/// drops the body for `HEAD`.
+fn conditional(resp: Response, parts: &Parts) -> Response {
+    let etag = resp.headers().get(ETAG).and_then(|etag| etag.to_str().ok());
+    let not_modified = resp.status() == StatusCode::OK
+        && parts
+            .headers
+            .get(IF_NONE_MATCH)
+            .and_then(|value| value.to_str().ok())
+            .zip(etag)
+            .is_some_and(|(candidates, etag)| {
+                candidates.split(',').map(str::trim).any(|candidate| {
+                    candidate == "*"
+                        || candidate.trim_start_matches("W/") == etag.trim_start_matches("W/")
+                })
+            });
+
+    let (mut resp_parts, body) = resp.into_parts();
+    if not_modified {
+        resp_parts.status = StatusCode::NOT_MODIFIED;
+        let kept = [CACHE_CONTROL, DATE, ETAG, EXPIRES, LAST_MODIFIED, VARY, AGE];
+        let mut headers = HeaderMap::new();
+        for (name, value) in &resp_parts.headers {
+            if kept.contains(name) || name.as_str() == CACHE_STATUS_HEADER {
+                headers.append(name.clone(), value.clone());
+            }
+        }
+        resp_parts.headers = headers;
+        return Response::from_parts(resp_parts, Body::empty());
+    }
+    if parts.method == Method::HEAD {
+        return Response::from_parts(resp_parts, Body::empty());
+    }
+    Response::from_parts(resp_parts, body)
+}
+
+/// The request header values a response varies on; `None` for `Vary: *`
+/// or unusable field names, which make it uncacheable.
+fn vary(
+    resp_headers: &HeaderMap,
+    req_headers: &HeaderMap,
+) -> Option<Vec<(HeaderName, Option<HeaderValue>)>> {
+    let mut vary = Vec::new();
+    for field in resp_headers
+        .get_all(VARY)
+        .iter()
+        .map(|value| value.to_str().ok())
+        .collect::<Option<Vec<_>>>()?
+        .into_iter()
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|field| !field.is_empty())
+    {
+        if field == "*" {
+            return None;
+        }
+        let name = HeaderName::from_bytes(field.to_ascii_lowercase().as_bytes()).ok()?;
+        let value = req_headers.get(&name).cloned();
+        vary.push((name, value));
+    }
+    Some(vary)
+}
+
+fn validators(headers: &HeaderMap) -> HeaderMap {
+    let mut validators = HeaderMap::new();
+    if let Some(etag) = headers.get(ETAG) {
+        validators.insert(IF_NONE_MATCH, etag.clone());
+    }
+    if let Some(last_modified) = headers.get(LAST_MODIFIED) {
+        validators.insert(IF_MODIFIED_SINCE, last_modified.clone());
+    }
+    validators
+}
+
+fn cached_response(status: StatusCode, headers: HeaderMap, body: Bytes, age: Duration) -> Response {
+    let mut resp = Response::new(Body::from(body));
+    *resp.status_mut() = status;
+    *resp.headers_mut() = headers;
+    resp.headers_mut()
+        .insert(AGE, HeaderValue::from(age.as_secs()));
+    resp
+}
+
+fn http_date(value: &str) -> Option<SystemTime> {
+    httpdate::parse_http_date(value).ok()
+}
+
+/// Deletes the files of entries that left the cache.
+fn discard(entries: Vec<Entry>) {
+    let paths = entries
+        .into_iter()
+        .filter_map(|entry| match entry.body {
+            Stored::Disk(path) => Some(path),
+            Stored::Memory(_) => None,
+        })
+        .collect::<Vec<_>>();
+    if paths.is_empty() {
+        return;
+    }
+    tokio::task::spawn_blocking(move || {
+        for path in paths {
+            if let Err(err) = fs::remove_file(&path) {
+                tracing::warn!("Failed to remove cache file '{}': {}", path.display(), err);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::{
+        header::{ACCEPT_ENCODING, HOST},
+        Request,
+    };
+
+    fn headers(pairs: &[(HeaderName, &str)]) -> HeaderMap {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.clone(), HeaderValue::from_str(value).unwrap()))
+            .collect()
+    }
+
+    fn policy(cache_control: &str) -> Option<Policy> {
+        Policy::of(&headers(&[(CACHE_CONTROL, cache_control)]), false)
+    }
+
+    fn key() -> FunctionKey {
+        FunctionKey {
+            org: "acme".to_string(),
+            func: "todos".to_string(),
+            version: "0.0.1".to_string(),
+        }
+    }
+
+    fn parts(uri: &str, accept_encoding: &str) -> Parts {
+        let req = Request::get(uri)
+            .header(ACCEPT_ENCODING, accept_encoding)
+            .body(())
+            .unwrap();
+        req.into_parts().0
+    }
+
+    fn response(pairs: &[(HeaderName, &str)]) -> Response {
+        let mut resp = Response::new(Body::from("hello"));
+        *resp.headers_mut() = headers(pairs);
+        resp
+    }
+
+    #[test]
+    fn max_age_and_s_maxage_set_freshness() {
+        assert_eq!(policy("max-age=60").unwrap().fresh_for, Duration::from_secs(60));
+        let shared = policy("max-age=10, s-maxage=60").unwrap();
+        assert_eq!(shared.fresh_for, Duration::from_secs(60));
+        assert_eq!(shared.stale_while_revalidate, Duration::ZERO);
+    }
+
+    #[test]
+    fn no_store_private_and_unknown_lifetime_are_not_stored() {
+        assert!(policy("no-store, max-age=60").is_none());
+        assert!(policy("private, max-age=60").is_none());
+        assert!(Policy::of(&HeaderMap::new(), false).is_none());
+        // Already stale and nothing to revalidate with.
+        assert!(policy("max-age=0").is_none());
+    }
+
+    #[test]
+    fn no_cache_needs_a_validator() {
+        assert!(policy("no-cache").is_none());
+        let headers = headers(&[(CACHE_CONTROL, "no-cache"), (ETAG, "\"v1\"")]);
+        let policy = Policy::of(&headers, false).unwrap();
+        assert!(policy.no_cache);
+        assert_eq!(policy.fresh_for, Duration::ZERO);
+    }
+
+    #[test]
+    fn authorized_responses_need_explicit_sharing() {
+        let private = headers(&[(CACHE_CONTROL, "max-age=60")]);
+        assert!(Policy::of(&private, true).is_none());
+        for shared in ["public, max-age=60", "s-maxage=60", "max-age=60, must-revalidate"] {
+            assert!(Policy::of(&headers(&[(CACHE_CONTROL, shared)]), true).is_some());
+        }
+    }
+
+    #[test]
+    fn expires_is_relative_to_date_and_age_counts() {
+        let headers = headers(&[
+            (DATE, "Sun, 06 Nov 1994 08:49:37 GMT"),
+            (EXPIRES, "Sun, 06 Nov 1994 08:50:37 GMT"),
+            (AGE, "15"),
+        ]);
+        let policy = Policy::of(&headers, false).unwrap();
+        assert_eq!(policy.fresh_for, Duration::from_secs(60));
+        assert_eq!(policy.initial_age, Duration::from_secs(15));
+    }
+
+    #[test]
+    fn stale_while_revalidate_unless_must_revalidate() {
+        let policy = policy("max-age=1, stale-while-revalidate=30").unwrap();
+        assert_eq!(policy.stale_while_revalidate, Duration::from_secs(30));
+        let strict = self::policy("max-age=1, stale-while-revalidate=30, must-revalidate");
+        assert_eq!(strict.unwrap().stale_while_revalidate, Duration::ZERO);
+    }
+
+    #[test]
+    fn vary_records_request_values() {
+        let resp = headers(&[(VARY, "Accept-Encoding, x-tenant")]);
+        let req = headers(&[(ACCEPT_ENCODING, "gzip")]);
+        let vary = vary(&resp, &req).unwrap();
+        assert_eq!(vary.len(), 2);
+        assert_eq!(vary[0], (ACCEPT_ENCODING, Some(HeaderValue::from_static("gzip"))));
+        assert_eq!(vary[1].1, None);
+        assert!(super::vary(&headers(&[(VARY, "*")]), &req).is_none());
+    }
+
+    #[test]
+    fn primary_key_is_relative_to_the_function() {
+        let items = parts("/rpc/acme/todos/0.0.1/items?page=2", "gzip");
+        assert_eq!(Primary::new("rpc", &key(), &items).path_and_query, "/items?page=2");
+        let root = parts("/rpc/acme/todos/0.0.1", "gzip");
+        assert_eq!(Primary::new("rpc", &key(), &root).path_and_query, "/");
+    }
+
+    #[tokio::test]
+    async fn entries_are_kept_per_host() {
+        let config = CacheConfig::default();
+        let cache = ResponseCache::new(&config).unwrap();
+        let on = |host: &str| {
+            let mut parts = parts("/rpc/acme/todos/0.0.1/items", "gzip");
+            parts.headers.insert(HOST, HeaderValue::from_str(host).unwrap());
+            parts
+        };
+        let resp = response(&[(CACHE_CONTROL, "max-age=60")]);
+        cache.store(&config, "rpc", &key(), &on("a.acme.example"), false, resp).await;
+
+        let same = on("A.acme.example:443");
+        assert!(matches!(cache.lookup("rpc", &key(), &same).await, Lookup::Fresh(_)));
+        let other = on("b.acme.example");
+        assert!(matches!(cache.lookup("rpc", &key(), &other).await, Lookup::Miss));
+    }
+
+    #[tokio::test]
+    async fn stores_per_vary_variant() {
+        let config = CacheConfig::default();
+        let cache = ResponseCache::new(&config).unwrap();
+        let gzip = parts("/rpc/acme/todos/0.0.1/items", "gzip");
+        let resp = response(&[(CACHE_CONTROL, "max-age=60"), (VARY, "accept-encoding")]);
+        cache.store(&config, "rpc", &key(), &gzip, false, resp).await;
+
+        let Lookup::Fresh(hit) = cache.lookup("rpc", &key(), &gzip).await else {
+            panic!("expected a fresh entry");
+        };
+        assert_eq!(hit.headers().get(AGE).unwrap(), "0");
+        let br = parts("/rpc/acme/todos/0.0.1/items", "br");
+        assert!(matches!(cache.lookup("rpc", &key(), &br).await, Lookup::Miss));
+
+        assert_eq!(cache.purge("acme", "todos", None, Some("/items")), 1);
+        assert!(matches!(cache.lookup("rpc", &key(), &gzip).await, Lookup::Miss));
+    }
+
+    #[tokio::test]
+    async fn stale_entry_is_served_while_one_revalidation_runs() {
+        let config = CacheConfig::default();
+        let cache = ResponseCache::new(&config).unwrap();
+        let parts = parts("/rpc/acme/todos/0.0.1/", "gzip");
+        let resp = response(&[
+            (CACHE_CONTROL, "max-age=0, stale-while-revalidate=60"),
+            (ETAG, "\"v1\""),
+        ]);
+        cache.store(&config, "rpc", &key(), &parts, false, resp).await;
+
+        let Lookup::Stale { validators, .. } = cache.lookup("rpc", &key(), &parts).await else {
+            panic!("expected a stale entry");
+        };
+        let validators = validators.expect("first lookup revalidates");
+        assert_eq!(validators.headers.get(IF_NONE_MATCH).unwrap(), "\"v1\"");
+        assert!(matches!(
+            cache.lookup("rpc", &key(), &parts).await,
+            Lookup::Stale {
+                validators: None,
+                ..
+            }
+        ));
+
+        let refreshed = headers(&[(CACHE_CONTROL, "max-age=60")]);
+        assert!(cache.refresh(validators.id, &refreshed).await.is_some());
+        assert!(matches!(
+            cache.lookup("rpc", &key(), &parts).await,
+            Lookup::Fresh(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn must_revalidate_entry_is_revalidated_before_use() {
+        let config = CacheConfig::default();
+        let cache = ResponseCache::new(&config).unwrap();
+        let parts = parts("/rpc/acme/todos/0.0.1/", "gzip");
+        let resp = response(&[(CACHE_CONTROL, "no-cache"), (ETAG, "\"v1\"")]);
+        cache.store(&config, "rpc", &key(), &parts, false, resp).await;
+        assert!(matches!(
+            cache.lookup("rpc", &key(), &parts).await,
+            Lookup::Revalidate(_)
+        ));
+    }
+}
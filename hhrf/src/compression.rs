@@ -0,0 +1,430 @@
+use crate::{body::BodyError, config::CompressionConfig};
+use axum::{
+    body::{Body, Bytes, HttpBody},
+    http::{
+        header::{CACHE_CONTROL, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, ETAG, VARY},
+        HeaderMap, HeaderValue, StatusCode,
+    },
+    response::Response,
+};
+use std::io::{self, Read, Write};
+
+const GZIP_LEVEL: u32 = 6;
+const BROTLI_QUALITY: u32 = 5;
+const BROTLI_WINDOW: u32 = 22;
+const ZSTD_LEVEL: i32 = 3;
+const BUFFER_SIZE: usize = 8 * 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Encoding {
+    Zstd,
+    Brotli,
+    Gzip,
+}
+
+/// Picked when the client rates several encodings equally.
+const PREFERENCE: [Encoding; 3] = [Encoding::Zstd, Encoding::Brotli, Encoding::Gzip];
+
+impl Encoding {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Zstd => "zstd",
+            Self::Brotli => "br",
+            Self::Gzip => "gzip",
+        }
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "zstd" => Some(Self::Zstd),
+            "br" => Some(Self::Brotli),
+            "gzip" | "x-gzip" => Some(Self::Gzip),
+            _ => None,
+        }
+    }
+
+    fn encode(self, data: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Self::Zstd => zstd::bulk::compress(data, ZSTD_LEVEL),
+            Self::Brotli => {
+                let mut encoder = brotli::CompressorWriter::new(
+                    Vec::new(),
+                    BUFFER_SIZE,
+                    BROTLI_QUALITY,
+                    BROTLI_WINDOW,
+                );
+                encoder.write_all(data)?;
+                Ok(encoder.into_inner())
+            }
+            Self::Gzip => {
+                let level = flate2::Compression::new(GZIP_LEVEL);
+                let mut encoder = flate2::write::GzEncoder::new(Vec::new(), level);
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+        }
+    }
+
+    fn decoder(self, data: Box<dyn Read + Send>) -> io::Result<Box<dyn Read + Send>> {
+        Ok(match self {
+            Self::Zstd => Box::new(zstd::stream::read::Decoder::new(data)?),
+            Self::Brotli => Box::new(brotli::Decompressor::new(data, BUFFER_SIZE)),
+            Self::Gzip => Box::new(flate2::read::MultiGzDecoder::new(data)),
+        })
+    }
+}
+
+/// Decodes a request body sent with `Content-Encoding` (gzip, br, zstd or a
+/// chain of them) and drops the header, so the function sees plain bytes.
+///
+/// The decoded size counts against `limit` like a plain body, which keeps
+/// small compressed bombs from expanding past it.
+pub async fn decompress_request(
+    headers: &mut HeaderMap,
+    body: Bytes,
+    limit: u64,
+) -> Result<Bytes, BodyError> {
+    let mut encodings = Vec::new();
+    for value in headers.get_all(CONTENT_ENCODING) {
+        let value = value
+            .to_str()
+            .map_err(|_| BodyError::UnsupportedEncoding("<invalid>".to_string()))?;
+        for coding in value.split(',').map(|coding| coding.trim().to_ascii_lowercase()) {
+            if coding.is_empty() || coding == "identity" {
+                continue;
+            }
+            match Encoding::parse(&coding) {
+                Some(encoding) => encodings.push(encoding),
+                None => return Err(BodyError::UnsupportedEncoding(coding)),
+            }
+        }
+    }
+    headers.remove(CONTENT_ENCODING);
+    if encodings.is_empty() {
+        return Ok(body);
+    }
+
+    tokio::task::spawn_blocking(move || {
+        // Codings are listed in the order they were applied.
+        let mut reader: Box<dyn Read + Send> = Box::new(io::Cursor::new(body));
+        for encoding in encodings.into_iter().rev() {
+            reader = encoding
+                .decoder(reader)
+                .map_err(|e| BodyError::Read(e.to_string()))?;
+        }
+        let mut decoded = Vec::new();
+        // Read one byte past the limit to tell "exactly at" from "over".
+        reader
+            .take(limit.saturating_add(1))
+            .read_to_end(&mut decoded)
+            .map_err(|e| BodyError::Read(format!("Failed to decode body: {}", e)))?;
+        if decoded.len() as u64 > limit {
+            return Err(BodyError::TooLarge(limit));
+        }
+        Ok(Bytes::from(decoded))
+    })
+    .await
+    .map_err(|e| BodyError::Read(e.to_string()))?
+}
+
+/// Compresses a function response with the best encoding the client
+/// accepts, if its `Content-Type` is compressible and it is at least
+/// `min_bytes` long.
+///
+/// Responses the function already encoded, partial content, bodiless
+/// statuses and `Cache-Control: no-transform` pass through untouched.
+/// Compressible responses get `Vary: Accept-Encoding` even when sent as is,
+/// so caches keep the variants apart.
+pub async fn compress_response(
+    config: &CompressionConfig,
+    accept_encoding: Option<&HeaderValue>,
+    resp: Response,
+) -> Response {
+    let status = resp.status();
+    let headers = resp.headers();
+    if status.is_informational()
+        || status == StatusCode::NO_CONTENT
+        || status == StatusCode::NOT_MODIFIED
+        || status == StatusCode::PARTIAL_CONTENT
+        || headers.contains_key(CONTENT_ENCODING)
+        || !compressible(config, headers)
+        || has_no_transform(headers)
+    {
+        return resp;
+    }
+
+    let (mut parts, body) = resp.into_parts();
+    add_vary(&mut parts.headers);
+    let small = body
+        .size_hint()
+        .exact()
+        .is_some_and(|len| len < config.min_bytes);
+    let encoding = accept_encoding
+        .and_then(|value| value.to_str().ok())
+        .and_then(negotiate);
+    let Some(encoding) = encoding.filter(|_| !small) else {
+        return Response::from_parts(parts, body);
+    };
+
+    let data = match axum::body::to_bytes(body, usize::MAX).await {
+        Ok(data) => data,
+        Err(err) => {
+            tracing::warn!("Failed to buffer response for compression: {}", err);
+            return Response::from_parts(parts, Body::empty());
+        }
+    };
+    if (data.len() as u64) < config.min_bytes {
+        return Response::from_parts(parts, Body::from(data));
+    }
+
+    let span = tracing::info_span!("compress_response", encoding = encoding.as_str());
+    let compressed = tokio::task::spawn_blocking({
+        let data = data.clone();
+        move || span.in_scope(|| encoding.encode(&data))
+    })
+    .await;
+    let compressed = match compressed {
+        Ok(Ok(compressed)) if compressed.len() < data.len() => compressed,
+        Ok(Ok(_)) => return Response::from_parts(parts, Body::from(data)),
+        Ok(Err(err)) => {
+            tracing::warn!("Failed to compress response: {}", err);
+            return Response::from_parts(parts, Body::from(data));
+        }
+        Err(err) => {
+            tracing::warn!("Compression task failed: {}", err);
+            return Response::from_parts(parts, Body::from(data));
+        }
+    };
+
+    parts
+        .headers
+        .insert(CONTENT_ENCODING, HeaderValue::from_static(encoding.as_str()));
+    parts
+        .headers
+        .insert(CONTENT_LENGTH, HeaderValue::from(compressed.len()));
+    // The encoded bytes differ, so a strong validator no longer applies.
+    let weak_etag = parts
+        .headers
+        .get(ETAG)
+        .and_then(|etag| etag.to_str().ok())
+        .filter(|etag| etag.starts_with('"'))
+        .and_then(|etag| HeaderValue::from_str(&format!("W/{}", etag)).ok());
+    if let Some(weak_etag) = weak_etag {
+        parts.headers.insert(ETAG, weak_etag);
+    }
+    Response::from_parts(parts, Body::from(compressed))
+}
+
+/// The preferred encoding with the highest `q` in `Accept-Encoding`; `*`
+/// covers encodings that aren't listed. `None` when nothing is acceptable.
+fn negotiate(accept_encoding: &str) -> Option<Encoding> {
+    let mut wildcard = None;
+    let mut listed = Vec::new();
+    for entry in accept_encoding.split(',') {
+        let mut params = entry.split(';');
+        let coding = params.next().unwrap_or_default().trim().to_ascii_lowercase();
+        let quality = params
+            .filter_map(|param| param.trim().strip_prefix("q="))
+            .filter_map(|quality| quality.trim().parse::<f32>().ok())
+            .next()
+            .unwrap_or(1.0);
+        if coding == "*" {
+            wildcard = Some(quality);
+        } else if let Some(encoding) = Encoding::parse(&coding) {
+            listed.push((encoding, quality));
+        }
+    }
+
+    let mut best: Option<(Encoding, f32)> = None;
+    for encoding in PREFERENCE {
+        let quality = listed
+            .iter()
+            .find(|(listed, _)| *listed == encoding)
+            .map(|(_, quality)| *quality)
+            .or(wildcard)
+            .unwrap_or(0.0);
+        if quality > 0.0 && !best.is_some_and(|(_, best)| quality <= best) {
+            best = Some((encoding, quality));
+        }
+    }
+    best.map(|(encoding, _)| encoding)
+}
+
+fn compressible(config: &CompressionConfig, headers: &HeaderMap) -> bool {
+    let Some(content_type) = headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+    else {
+        return false;
+    };
+    let media_type = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    config.content_types.iter().any(|pattern| {
+        if pattern.ends_with('/') {
+            media_type.starts_with(pattern.as_str())
+        } else if pattern.starts_with('+') {
+            media_type.ends_with(pattern.as_str())
+        } else {
+            media_type == *pattern
+        }
+    })
+}
+
+fn has_no_transform(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(CACHE_CONTROL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|directive| directive.trim().eq_ignore_ascii_case("no-transform"))
+}
+
+fn add_vary(headers: &mut HeaderMap) {
+    let covered = headers
+        .get_all(VARY)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .any(|field| field == "*" || field.eq_ignore_ascii_case("accept-encoding"));
+    if !covered {
+        headers.append(VARY, HeaderValue::from_static("accept-encoding"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderName;
+
+    fn json_response(body: Vec<u8>, extra: &[(HeaderName, &'static str)]) -> Response {
+        let mut resp = Response::new(Body::from(body));
+        let headers = resp.headers_mut();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        headers.insert(ETAG, HeaderValue::from_static("\"v1\""));
+        for (name, value) in extra {
+            headers.insert(name.clone(), HeaderValue::from_static(value));
+        }
+        resp
+    }
+
+    async fn compress(accept_encoding: &'static str, resp: Response) -> Response {
+        let accept_encoding = HeaderValue::from_static(accept_encoding);
+        compress_response(&CompressionConfig::default(), Some(&accept_encoding), resp).await
+    }
+
+    fn encoded(encoding: &str, data: &[u8]) -> (HeaderMap, Bytes) {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_ENCODING, HeaderValue::from_str(encoding).unwrap());
+        let data = Encoding::parse(encoding).unwrap().encode(data).unwrap();
+        (headers, Bytes::from(data))
+    }
+
+    #[test]
+    fn negotiate_honours_quality_values() {
+        assert_eq!(negotiate("gzip, br, zstd"), Some(Encoding::Zstd));
+        assert_eq!(negotiate("gzip;q=1.0, br;q=0.8"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("br;q=0.5, gzip;q=0.5"), Some(Encoding::Brotli));
+        assert_eq!(negotiate("GZIP ; q=0.3"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("x-gzip"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("gzip;q=0"), None);
+        assert_eq!(negotiate("deflate, compress"), None);
+        assert_eq!(negotiate(""), None);
+    }
+
+    #[test]
+    fn negotiate_identity_and_wildcard() {
+        assert_eq!(negotiate("identity;q=0"), None);
+        assert_eq!(negotiate("identity;q=0, gzip"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("*"), Some(Encoding::Zstd));
+        assert_eq!(negotiate("*;q=0.5, br"), Some(Encoding::Brotli));
+        assert_eq!(negotiate("*, zstd;q=0"), Some(Encoding::Brotli));
+        assert_eq!(negotiate("*;q=0, gzip"), Some(Encoding::Gzip));
+    }
+
+    #[tokio::test]
+    async fn compresses_with_negotiated_encoding() {
+        let body = b"{\"todo\":\"write tests\"}".repeat(200);
+        let resp = compress("gzip", json_response(body.clone(), &[])).await;
+        let headers = resp.headers().clone();
+        assert_eq!(headers.get(CONTENT_ENCODING).unwrap(), "gzip");
+        assert_eq!(headers.get(VARY).unwrap(), "accept-encoding");
+        assert_eq!(headers.get(ETAG).unwrap(), "W/\"v1\"");
+
+        let data = axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(headers.get(CONTENT_LENGTH).unwrap(), &data.len().to_string());
+        let mut decoded = Vec::new();
+        flate2::read::GzDecoder::new(&data[..])
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, body);
+    }
+
+    #[tokio::test]
+    async fn skips_small_encoded_and_uncompressible_responses() {
+        let small = compress("gzip", json_response(b"{}".to_vec(), &[])).await;
+        assert!(small.headers().get(CONTENT_ENCODING).is_none());
+        assert_eq!(small.headers().get(VARY).unwrap(), "accept-encoding");
+
+        let body = vec![b'a'; 4096];
+        let already = json_response(body.clone(), &[(CONTENT_ENCODING, "br")]);
+        let already = compress("gzip", already).await;
+        assert_eq!(already.headers().get(CONTENT_ENCODING).unwrap(), "br");
+        assert!(already.headers().get(VARY).is_none());
+
+        let no_transform = json_response(body.clone(), &[(CACHE_CONTROL, "no-transform")]);
+        let no_transform = compress("gzip", no_transform).await;
+        assert!(no_transform.headers().get(CONTENT_ENCODING).is_none());
+
+        let image = json_response(body.clone(), &[(CONTENT_TYPE, "image/png")]);
+        assert!(compress("gzip", image).await.headers().get(CONTENT_ENCODING).is_none());
+
+        let refused = compress("identity;q=0", json_response(body, &[])).await;
+        assert!(refused.headers().get(CONTENT_ENCODING).is_none());
+    }
+
+    #[tokio::test]
+    async fn decompresses_request_chains() {
+        let gzip = Encoding::Gzip.encode(b"hello").unwrap();
+        let chained = Encoding::Zstd.encode(&gzip).unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_ENCODING, HeaderValue::from_static("gzip, zstd"));
+        let body = decompress_request(&mut headers, Bytes::from(chained), 1024)
+            .await
+            .unwrap();
+        assert_eq!(body, "hello");
+        assert!(headers.get(CONTENT_ENCODING).is_none());
+    }
+
+    #[tokio::test]
+    async fn decompression_is_limited_by_decoded_size() {
+        let data = vec![0; 1024 * 1024];
+        for encoding in ["gzip", "br", "zstd"] {
+            let (mut headers, body) = encoded(encoding, &data);
+            assert!(body.len() < 64 * 1024);
+            let err = decompress_request(&mut headers, body, 64 * 1024)
+                .await
+                .unwrap_err();
+            assert!(matches!(err, BodyError::TooLarge(limit) if limit == 64 * 1024), "{encoding}");
+
+            let (mut headers, body) = encoded(encoding, &data);
+            let limit = data.len() as u64;
+            let decoded = decompress_request(&mut headers, body, limit).await.unwrap();
+            assert_eq!(decoded.len(), data.len());
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_unknown_request_encodings() {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_ENCODING, HeaderValue::from_static("deflate"));
+        let err = decompress_request(&mut headers, Bytes::from_static(b"x"), 1024)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, BodyError::UnsupportedEncoding(coding) if coding == "deflate"));
+    }
+}
@@ -0,0 +1,319 @@
+use crate::{config::ServerConfig, settings::FunctionSettings, FunctionKey};
+use axum::http::{header::RETRY_AFTER, HeaderValue};
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Slots held by one invocation: one from its function, one from the global pool.
+///
+/// Dropping it frees both. Executors keep it alive until the work really
+/// ends, so a timed-out in-process call or a JS isolate that is still being
+/// terminated counts against the limits.
+pub struct InvocationPermit {
+    _function: OwnedSemaphorePermit,
+    _global: OwnedSemaphorePermit,
+}
+
+#[derive(Debug)]
+pub enum AdmissionError {
+    /// The wait queue is full; retry later (429).
+    QueueFull { retry_after: Duration },
+    /// Waited for `queue_timeout` without getting a slot (503).
+    QueueTimeout { retry_after: Duration },
+}
+
+impl AdmissionError {
+    pub fn response(&self) -> axum::response::Response {
+        let (status, retry_after) = match self {
+            AdmissionError::QueueFull { retry_after } => (429, retry_after),
+            AdmissionError::QueueTimeout { retry_after } => (503, retry_after),
+        };
+        let mut resp = crate::error_response(status, self.to_string());
+        resp.headers_mut().insert(
+            RETRY_AFTER,
+            HeaderValue::from(retry_after.as_secs().max(1)),
+        );
+        resp
+    }
+}
+
+impl fmt::Display for AdmissionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AdmissionError::QueueFull { .. } => f.write_str("Too many concurrent requests"),
+            AdmissionError::QueueTimeout { .. } => {
+                f.write_str("Timed out waiting for a free execution slot")
+            }
+        }
+    }
+}
+
+struct FunctionSlots {
+    semaphore: Arc<Semaphore>,
+    limit: AtomicUsize,
+    waiting: AtomicUsize,
+}
+
+/// Per org/func/version and global concurrency caps with a bounded wait queue.
+pub struct ConcurrencyLimiter {
+    global: Arc<Semaphore>,
+    global_waiting: AtomicUsize,
+    max_queue: usize,
+    retry_after: Duration,
+    functions: Mutex<HashMap<FunctionKey, Arc<FunctionSlots>>>,
+}
+
+impl ConcurrencyLimiter {
+    pub fn new(server: &ServerConfig) -> Self {
+        Self {
+            global: Arc::new(Semaphore::new(server.max_concurrency)),
+            global_waiting: AtomicUsize::new(0),
+            max_queue: server.max_queue,
+            retry_after: Duration::from_secs(server.retry_after_secs),
+            functions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Waits for a function slot and a global slot.
+    ///
+    /// Callers that cannot run immediately join the wait queue; a full queue
+    /// fails fast, and waiting longer than `wait` gives up.
+    pub async fn acquire(
+        &self,
+        key: &FunctionKey,
+        settings: &FunctionSettings,
+        wait: Duration,
+    ) -> Result<InvocationPermit, AdmissionError> {
+        let slots = self.slots(key, settings.max_concurrency);
+
+        let function_permit = slots.semaphore.clone().try_acquire_owned().ok();
+        if let Some(function_permit) = function_permit {
+            match self.global.clone().try_acquire_owned() {
+                Ok(global_permit) => {
+                    return Ok(InvocationPermit {
+                        _function: function_permit,
+                        _global: global_permit,
+                    });
+                }
+                Err(_) => return self.wait(&slots, Some(function_permit), settings, wait).await,
+            }
+        }
+
+        self.wait(&slots, None, settings, wait).await
+    }
+
+    async fn wait(
+        &self,
+        slots: &FunctionSlots,
+        function_permit: Option<OwnedSemaphorePermit>,
+        settings: &FunctionSettings,
+        wait: Duration,
+    ) -> Result<InvocationPermit, AdmissionError> {
+        let retry_after = self.retry_after;
+        let _queued = match (
+            QueueGuard::join(&slots.waiting, settings.max_queue),
+            QueueGuard::join(&self.global_waiting, self.max_queue),
+        ) {
+            (Some(function), Some(global)) => (function, global),
+            _ => return Err(AdmissionError::QueueFull { retry_after }),
+        };
+
+        let acquire = async {
+            let function_permit = match function_permit {
+                Some(permit) => permit,
+                None => slots.semaphore.clone().acquire_owned().await.ok()?,
+            };
+            let global_permit = self.global.clone().acquire_owned().await.ok()?;
+            Some(InvocationPermit {
+                _function: function_permit,
+                _global: global_permit,
+            })
+        };
+
+        match tokio::time::timeout(wait.min(settings.queue_timeout), acquire).await {
+            Ok(Some(permit)) => Ok(permit),
+            Ok(None) | Err(_) => Err(AdmissionError::QueueTimeout { retry_after }),
+        }
+    }
+
+    /// Returns the slots for `key`, resizing them when the configured limit changed.
+    ///
+    /// The semaphore is kept across reloads so permits held by running
+    /// invocations still count against the new limit.
+    fn slots(&self, key: &FunctionKey, limit: usize) -> Arc<FunctionSlots> {
+        let mut functions = self.functions.lock().unwrap();
+        let slots = match functions.get(key) {
+            Some(slots) => slots.clone(),
+            None => {
+                let slots = Arc::new(FunctionSlots {
+                    semaphore: Arc::new(Semaphore::new(limit)),
+                    limit: AtomicUsize::new(limit),
+                    waiting: AtomicUsize::new(0),
+                });
+                functions.insert(key.clone(), slots.clone());
+                return slots;
+            }
+        };
+        // Resized under the map lock, so two reloads cannot interleave.
+        let previous = slots.limit.swap(limit, Ordering::SeqCst);
+        if limit > previous {
+            slots.semaphore.add_permits(limit - previous);
+        } else if limit < previous {
+            let excess = previous - limit;
+            let forgotten = slots.semaphore.forget_permits(excess);
+            if forgotten < excess {
+                // The rest are held; retire them as they are released. The
+                // semaphore is fair, so no new caller gets ahead of this.
+                let semaphore = slots.semaphore.clone();
+                let held = u32::try_from(excess - forgotten).unwrap_or(u32::MAX);
+                tokio::spawn(async move {
+                    if let Ok(permits) = semaphore.acquire_many_owned(held).await {
+                        permits.forget();
+                    }
+                });
+            }
+        }
+        slots
+    }
+}
+
+/// Counts a waiter for as long as it is alive.
+struct QueueGuard<'a>(&'a AtomicUsize);
+
+impl<'a> QueueGuard<'a> {
+    fn join(counter: &'a AtomicUsize, max: usize) -> Option<Self> {
+        counter
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |waiting| {
+                (waiting < max).then_some(waiting + 1)
+            })
+            .ok()
+            .map(|_| QueueGuard(counter))
+    }
+}
+
+impl Drop for QueueGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    fn key(func: &str) -> FunctionKey {
+        FunctionKey {
+            org: "acme".to_string(),
+            func: func.to_string(),
+            version: "0.1.0".to_string(),
+        }
+    }
+
+    fn settings(max_concurrency: usize, max_queue: usize, timeout_ms: u64) -> FunctionSettings {
+        let mut settings = FunctionSettings::resolve(&Config::default(), &key("todos"));
+        settings.max_concurrency = max_concurrency;
+        settings.max_queue = max_queue;
+        settings.queue_timeout = Duration::from_millis(timeout_ms);
+        settings
+    }
+
+    fn limiter(max_concurrency: usize, max_queue: usize) -> ConcurrencyLimiter {
+        ConcurrencyLimiter::new(&ServerConfig {
+            max_concurrency,
+            max_queue,
+            retry_after_secs: 7,
+            ..ServerConfig::default()
+        })
+    }
+
+    const WAIT: Duration = Duration::from_secs(5);
+
+    #[tokio::test]
+    async fn full_queue_fails_fast_with_429() {
+        let limiter = limiter(8, 8);
+        let settings = settings(1, 0, 1_000);
+        let _running = limiter.acquire(&key("todos"), &settings, WAIT).await.unwrap();
+
+        let err = limiter.acquire(&key("todos"), &settings, WAIT).await.err().unwrap();
+        assert!(matches!(err, AdmissionError::QueueFull { .. }));
+        let resp = err.response();
+        assert_eq!(resp.status(), 429);
+        assert_eq!(resp.headers()[RETRY_AFTER], "7");
+    }
+
+    #[tokio::test]
+    async fn queue_timeout_answers_503_with_retry_after() {
+        let limiter = limiter(8, 8);
+        let settings = settings(1, 1, 20);
+        let running = limiter.acquire(&key("todos"), &settings, WAIT).await.unwrap();
+
+        let err = limiter.acquire(&key("todos"), &settings, WAIT).await.err().unwrap();
+        assert!(matches!(err, AdmissionError::QueueTimeout { .. }));
+        let resp = err.response();
+        assert_eq!(resp.status(), 503);
+        assert_eq!(resp.headers()[RETRY_AFTER], "7");
+
+        drop(running);
+        assert!(limiter.acquire(&key("todos"), &settings, WAIT).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn global_cap_applies_across_functions() {
+        let limiter = limiter(1, 0);
+        let settings = settings(4, 4, 20);
+        let running = limiter.acquire(&key("todos"), &settings, WAIT).await.unwrap();
+
+        // `notes` has free function slots, but the global pool is used up and
+        // its queue holds nobody.
+        let err = limiter.acquire(&key("notes"), &settings, WAIT).await.err().unwrap();
+        assert!(matches!(err, AdmissionError::QueueFull { .. }));
+
+        drop(running);
+        assert!(limiter.acquire(&key("notes"), &settings, WAIT).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn function_cap_applies_below_the_global_one() {
+        let limiter = limiter(8, 8);
+        let settings = settings(1, 1, 20);
+        let _todos = limiter.acquire(&key("todos"), &settings, WAIT).await.unwrap();
+
+        let err = limiter.acquire(&key("todos"), &settings, WAIT).await.err().unwrap();
+        assert!(matches!(err, AdmissionError::QueueTimeout { .. }));
+        assert!(limiter.acquire(&key("notes"), &settings, WAIT).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn reload_keeps_counting_held_permits() {
+        let limiter = limiter(8, 8);
+        let two = settings(2, 0, 20);
+        let first = limiter.acquire(&key("todos"), &two, WAIT).await.unwrap();
+        let second = limiter.acquire(&key("todos"), &two, WAIT).await.unwrap();
+
+        // Shrinking while both run: neither the new nor the old slots admit more.
+        let one = settings(1, 0, 20);
+        assert!(limiter.acquire(&key("todos"), &one, WAIT).await.is_err());
+        drop(first);
+        tokio::task::yield_now().await;
+        assert!(limiter.acquire(&key("todos"), &one, WAIT).await.is_err());
+        drop(second);
+        tokio::task::yield_now().await;
+        let only = limiter.acquire(&key("todos"), &one, WAIT).await.unwrap();
+        assert!(limiter.acquire(&key("todos"), &one, WAIT).await.is_err());
+
+        // Growing again adds slots next to the one still held.
+        let three = settings(3, 0, 20);
+        let _a = limiter.acquire(&key("todos"), &three, WAIT).await.unwrap();
+        let _b = limiter.acquire(&key("todos"), &three, WAIT).await.unwrap();
+        assert!(limiter.acquire(&key("todos"), &three, WAIT).await.is_err());
+        drop(only);
+    }
+}
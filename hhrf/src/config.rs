@@ -0,0 +1,1382 @@
+use crate::{exec::ExecBackend, FunctionKey};
+use axum::http::HeaderName;
+use clap::Parser;
+use serde::Deserialize;
+use std::{collections::BTreeMap, net::SocketAddr, path::PathBuf};
+
+const DEFAULT_JWT_LEEWAY_SECS: u64 = 60;
+const DEFAULT_HMAC_MAX_SKEW_SECS: u64 = 300;
+
+/// Command-line flags. Every flag overrides the matching config value.
+#[derive(Parser, Debug, Clone)]
+#[command(name = "hhrf", about = "Host HTTP Runtime Frontier for Fezz functions")]
+pub struct Cli {
+    /// Path to the TOML config file.
+    #[arg(short, long, env = "HHRF_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// Listen address (repeatable), e.g. `0.0.0.0:3000`.
+    #[arg(long = "listen", value_name = "ADDR")]
+    pub listen: Vec<String>,
+
+    /// Directory containing `functions/{org}/{func}/{version}`.
+    #[arg(long, env = "HHRF_ROOT")]
+    pub root: Option<String>,
+
+    /// Default execution backend: `runner`, `in-process` or `js`.
+    #[arg(long, env = "HHRF_BACKEND")]
+    pub backend: Option<String>,
+
+    /// Default per-function timeout in milliseconds.
+    #[arg(long, env = "HHRF_TIMEOUT_MS")]
+    pub timeout_ms: Option<u64>,
+
+    /// Log level filter (`error`, `warn`, `info`, `debug`, `trace`).
+    #[arg(long)]
+    pub log_level: Option<String>,
+
+    /// Log output format (`pretty`, `compact` or `json`).
+    #[arg(long)]
+    pub log_format: Option<String>,
+
+    /// Bearer token for the admin API; overrides `admin.token`.
+    #[arg(long, env = "HHRF_ADMIN_TOKEN", hide_env_values = true)]
+    pub admin_token: Option<String>,
+}
+
+/// Top-level `hhrf.toml`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub logging: LoggingConfig,
+    pub library_cache: LibraryCacheConfig,
+    pub metrics: MetricsConfig,
+    pub health: HealthConfig,
+    pub discovery: DiscoveryConfig,
+    pub function_logs: FunctionLogsConfig,
+    pub compression: CompressionConfig,
+    pub cache: CacheConfig,
+    pub admin: AdminConfig,
+    pub tls: TlsConfig,
+    /// Settings applied to every function unless overridden in `functions`.
+    pub defaults: FunctionConfig,
+    /// Overrides keyed by `org`, `org/func` or `org/func/version`; more specific keys win.
+    pub functions: BTreeMap<String, FunctionConfig>,
+    /// Version aliases keyed by `org/func`, e.g. `stable = "0.0.1"` or
+    /// `canary = "^0.1"`; take precedence over the function's `aliases.toml`.
+    pub aliases: BTreeMap<String, BTreeMap<String, String>>,
+    /// Weighted splits keyed by `org/func/{alias}`; checked before aliases.
+    pub splits: BTreeMap<String, SplitConfig>,
+    /// Host/path routing table for requests outside `/rpc` and `/js-embed`.
+    pub routes: Vec<RouteConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub listen: Vec<String>,
+    pub root: String,
+    /// IPs/CIDRs whose `X-Forwarded-For` is trusted.
+    pub trusted_proxies: Vec<String>,
+    /// Hard ceiling on request bodies; per-function limits can only lower it.
+    pub max_body_bytes: u64,
+    /// Hard ceiling on function responses; per-function limits can only lower it.
+    pub max_response_bytes: u64,
+    /// Invocations running at once across all functions.
+    pub max_concurrency: usize,
+    /// Invocations waiting for a slot across all functions.
+    pub max_queue: usize,
+    /// `Retry-After` sent with 429/503 when saturated.
+    pub retry_after_secs: u64,
+    /// Header carrying the caller's API key, for `auth.api_keys`.
+    pub api_key_header: String,
+    /// Bind `listen` (and `admin.listen`) with `SO_REUSEPORT`, so a new
+    /// process can take over the ports while the old one drains.
+    pub reuse_port: bool,
+    /// How long in-flight requests may run after SIGTERM/SIGINT before their
+    /// runner processes and JS isolates are killed.
+    pub shutdown_timeout_secs: u64,
+    /// Serve `/rpc` and `/js-embed` on hosts claimed by `[[routes]]`. When
+    /// off, those hosts only reach their routes.
+    pub expose_rpc: bool,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            listen: vec!["0.0.0.0:3000".to_string()],
+            root: "./HHRF_ROOT".to_string(),
+            trusted_proxies: Vec::new(),
+            max_body_bytes: 64 * 1024 * 1024,
+            max_response_bytes: 64 * 1024 * 1024,
+            max_concurrency: 256,
+            max_queue: 1024,
+            retry_after_secs: 1,
+            api_key_header: "x-api-key".to_string(),
+            reuse_port: false,
+            shutdown_timeout_secs: 30,
+            expose_rpc: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LogFormat {
+    #[default]
+    Pretty,
+    Compact,
+    /// One JSON object per event, with the current span's fields.
+    Json,
+}
+
+impl LogFormat {
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "pretty" => Some(Self::Pretty),
+            "compact" => Some(Self::Compact),
+            "json" => Some(Self::Json),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    /// `tracing` filter directive; `RUST_LOG` takes precedence when set.
+    pub level: String,
+    pub format: LogFormat,
+    /// OTLP/gRPC collector for spans, e.g. `http://127.0.0.1:4317`.
+    /// Requires building hhrf with the `otlp` feature.
+    pub otlp_endpoint: Option<String>,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            level: "info".to_string(),
+            format: LogFormat::default(),
+            otlp_endpoint: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LibraryCacheConfig {
+    pub ttl_secs: u64,
+    pub max_entries: usize,
+}
+
+impl Default for LibraryCacheConfig {
+    fn default() -> Self {
+        Self {
+            ttl_secs: 300,
+            max_entries: 64,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsConfig {
+    /// Serve Prometheus metrics on the regular listeners.
+    pub enabled: bool,
+    pub path: String,
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            path: "/metrics".to_string(),
+        }
+    }
+}
+
+/// Liveness and readiness endpoints on the regular listeners.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HealthConfig {
+    pub enabled: bool,
+    /// Answers as long as the process serves requests.
+    pub liveness_path: String,
+    /// Checks the functions root and `functions`; fails while draining.
+    pub readiness_path: String,
+    /// `org/func/version` (aliases and `latest` allowed) that must load,
+    /// resolve their symbols and pass their health hook to be ready.
+    pub functions: Vec<String>,
+    /// Budget of each function check.
+    pub timeout_ms: u64,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            liveness_path: "/healthz".to_string(),
+            readiness_path: "/readyz".to_string(),
+            functions: Vec::new(),
+            timeout_ms: 5000,
+        }
+    }
+}
+
+/// Validation of every `functions/{org}/{func}/{version}` at startup and
+/// whenever its artifacts change.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DiscoveryConfig {
+    pub enabled: bool,
+    /// Refuse to start while any artifact is invalid.
+    pub strict: bool,
+    /// How often the tree is checked for changes; `0` scans only at startup.
+    pub interval_secs: u64,
+    /// Budget of each artifact check.
+    pub timeout_ms: u64,
+}
+
+impl Default for DiscoveryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            strict: false,
+            interval_secs: 10,
+            timeout_ms: 10000,
+        }
+    }
+}
+
+/// Response compression negotiated from `Accept-Encoding` (zstd, br, gzip).
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CompressionConfig {
+    /// Smaller response bodies are sent as they are.
+    pub min_bytes: u64,
+    /// Compressible `Content-Type`s: `text/` matches a whole top-level type,
+    /// `+json` a structured syntax suffix, anything else one media type.
+    pub content_types: Vec<String>,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            min_bytes: 1024,
+            content_types: [
+                "text/",
+                "application/json",
+                "application/javascript",
+                "application/xml",
+                "image/svg+xml",
+                "+json",
+                "+xml",
+            ]
+            .map(String::from)
+            .to_vec(),
+        }
+    }
+}
+
+/// Shared HTTP cache for function responses, driven by the `Cache-Control`,
+/// `Expires` and validators the functions send.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheConfig {
+    pub enabled: bool,
+    /// Total size of the cached bodies; least recently used entries go first.
+    pub max_bytes: u64,
+    /// Larger responses are never cached.
+    pub max_entry_bytes: u64,
+    /// Keep bodies in files here instead of in memory; emptied on startup.
+    pub dir: Option<PathBuf>,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_bytes: 256 * 1024 * 1024,
+            max_entry_bytes: 8 * 1024 * 1024,
+            dir: None,
+        }
+    }
+}
+
+/// Capture of what functions print or log while handling a request.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FunctionLogsConfig {
+    /// Also append each function's lines to `{dir}/{org}/{func}/{version}.log`.
+    pub dir: Option<PathBuf>,
+    /// Longer lines are cut.
+    pub max_line_bytes: usize,
+    /// Lines past this budget are dropped (and counted).
+    pub max_bytes_per_invocation: usize,
+    /// Lines kept in memory per function for the admin API; `0` disables.
+    pub recent_lines: usize,
+}
+
+impl Default for FunctionLogsConfig {
+    fn default() -> Self {
+        Self {
+            dir: None,
+            max_line_bytes: 4 * 1024,
+            max_bytes_per_invocation: 64 * 1024,
+            recent_lines: 200,
+        }
+    }
+}
+
+/// Admin API on its own listeners.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdminConfig {
+    /// Listen addresses; empty disables the admin API.
+    pub listen: Vec<String>,
+    /// Bearer token required on every admin request.
+    pub token: String,
+    /// Largest artifact accepted by an upload.
+    pub max_upload_bytes: u64,
+}
+
+impl Default for AdminConfig {
+    fn default() -> Self {
+        Self {
+            listen: Vec::new(),
+            token: String::new(),
+            max_upload_bytes: 256 * 1024 * 1024,
+        }
+    }
+}
+
+/// HTTPS listeners terminated in HHRF with rustls.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsConfig {
+    /// HTTPS listen addresses; empty disables TLS.
+    pub listen: Vec<String>,
+    /// Picked by SNI; the first one also serves clients without a matching name.
+    pub certificates: Vec<TlsCertificate>,
+}
+
+/// One PEM certificate chain and its private key, reloaded when either changes.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsCertificate {
+    pub cert: PathBuf,
+    /// PKCS#8, PKCS#1 or SEC1 private key.
+    pub key: PathBuf,
+    /// Names served with this certificate, e.g. `api.acme.example` or
+    /// `*.acme.example`.
+    #[serde(default)]
+    pub hosts: Vec<String>,
+}
+
+/// Serves `function` for requests whose host and path match.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RouteConfig {
+    /// `api.acme.example` or `*.acme.example`; any host when unset.
+    pub host: Option<String>,
+    #[serde(default = "default_path_prefix")]
+    pub path_prefix: String,
+    /// `org/func/version`; the version may be an alias, `latest` or a
+    /// semver requirement.
+    pub function: String,
+    /// Hand the function the path below `path_prefix` rather than the full path.
+    #[serde(default = "default_strip_prefix")]
+    pub strip_prefix: bool,
+}
+
+fn default_path_prefix() -> String {
+    "/".to_string()
+}
+
+fn default_strip_prefix() -> bool {
+    true
+}
+
+/// Traffic for one alias, divided between versions by weight.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SplitConfig {
+    /// The first target is the baseline; the others are canaries.
+    pub targets: Vec<SplitTarget>,
+    /// Header whose value pins a caller to one target.
+    pub sticky_header: Option<String>,
+    /// Cookie whose value pins a caller to one target, if the header is absent.
+    pub sticky_cookie: Option<String>,
+    /// Stop sending traffic to a canary whose 5xx rate gets too high.
+    pub rollback: Option<RollbackConfig>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SplitTarget {
+    /// A version, or anything the version segment accepts (`^0.1`, `latest`).
+    pub version: String,
+    pub weight: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RollbackConfig {
+    /// Fraction of 5xx responses (`0.1` = 10%) above which a canary is rolled back.
+    pub max_error_rate: f64,
+    /// Requests a canary must have served in the window before it is judged.
+    #[serde(default = "default_rollback_min_requests")]
+    pub min_requests: u64,
+    /// Length of the counting window.
+    #[serde(default = "default_rollback_window_secs")]
+    pub window_secs: u64,
+}
+
+fn default_rollback_min_requests() -> u64 {
+    20
+}
+
+fn default_rollback_window_secs() -> u64 {
+    60
+}
+
+/// Per-function knobs. Unset fields inherit from the less specific level.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FunctionConfig {
+    pub backend: Option<ExecBackend>,
+    pub timeout_ms: Option<u64>,
+    pub max_body_bytes: Option<u64>,
+    pub max_response_bytes: Option<u64>,
+    pub max_concurrency: Option<usize>,
+    /// Requests allowed to wait for a slot once `max_concurrency` is reached.
+    pub max_queue: Option<usize>,
+    pub queue_timeout_ms: Option<u64>,
+    /// Replaces (not extends) the rules of less specific levels; `[]` disables them.
+    pub rate_limits: Option<Vec<RateLimitConfig>>,
+    /// Replaces the `auth` of less specific levels; an empty table makes the function public.
+    pub auth: Option<AuthConfig>,
+    /// Compress responses per `[compression]` when the client accepts it (default true).
+    pub compress_responses: Option<bool>,
+    /// Decode gzip/br/zstd request bodies before the function sees them
+    /// (default true); `false` passes them on with their `Content-Encoding`.
+    pub decompress_requests: Option<bool>,
+    /// Let `[cache]` store this function's responses (default true).
+    pub cache: Option<bool>,
+    /// Replaces the `cors` of less specific levels. Unset leaves CORS,
+    /// including `OPTIONS` preflights, to the function.
+    pub cors: Option<CorsConfig>,
+}
+
+/// What a rate limit bucket is keyed on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RateLimitScope {
+    /// One bucket for every function of the org.
+    Org,
+    /// One bucket per org/func/version.
+    Function,
+    /// One bucket per function and client IP.
+    ClientIp,
+    /// One bucket per function and authenticated principal; anonymous
+    /// callers fall back to their client IP.
+    ApiKey,
+}
+
+/// A token bucket rule.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimitConfig {
+    pub scope: RateLimitScope,
+    /// Sustained refill rate; fractions allowed (`0.5` = one request every 2s).
+    pub requests_per_second: f64,
+    /// Bucket size, i.e. the largest burst; defaults to one second's worth.
+    pub burst: Option<u32>,
+}
+
+impl RateLimitConfig {
+    pub fn capacity(&self) -> f64 {
+        match self.burst {
+            Some(burst) => f64::from(burst),
+            None => self.requests_per_second.ceil().max(1.0),
+        }
+    }
+}
+
+/// Accepted credentials. A request passes with any one of them.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    /// Static keys presented in `server.api_key_header`.
+    pub api_keys: Vec<ApiKeyConfig>,
+    /// `Authorization: Bearer` tokens.
+    pub jwt: Option<JwtConfig>,
+    /// Requests signed with `x-fezz-key-id`/`x-fezz-timestamp`/`x-fezz-signature`.
+    pub hmac: Option<HmacConfig>,
+}
+
+impl AuthConfig {
+    pub fn is_public(&self) -> bool {
+        self.api_keys.is_empty() && self.jwt.is_none() && self.hmac.is_none()
+    }
+
+    fn validate(&self, section: &str, errors: &mut Vec<String>) {
+        for (index, api_key) in self.api_keys.iter().enumerate() {
+            if api_key.key.is_empty() {
+                errors.push(format!("{section}.auth.api_keys[{index}].key must not be empty"));
+            }
+            if api_key.principal.is_empty() {
+                errors.push(format!(
+                    "{section}.auth.api_keys[{index}].principal must not be empty"
+                ));
+            }
+        }
+        if let Some(jwt) = &self.jwt {
+            if let Err(err) = crate::auth::load_jwks(&jwt.jwks_file) {
+                errors.push(format!("{section}.auth.jwt.jwks_file: {err}"));
+            }
+        }
+        if let Some(hmac) = &self.hmac {
+            if hmac.keys.is_empty() {
+                errors.push(format!("{section}.auth.hmac.keys must not be empty"));
+            }
+            for (index, key) in hmac.keys.iter().enumerate() {
+                if key.key_id.is_empty() || key.secret.is_empty() {
+                    errors.push(format!(
+                        "{section}.auth.hmac.keys[{index}] needs a key_id and a secret"
+                    ));
+                }
+            }
+        }
+    }
+}
+
+/// Cross-origin access HHRF grants on the function's behalf.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CorsConfig {
+    /// `https://app.example.com`, `https://*.example.com` (any subdomain)
+    /// or `*` for every origin.
+    pub allowed_origins: Vec<String>,
+    /// `*` allows whatever method a preflight asks for.
+    pub allowed_methods: Vec<String>,
+    /// Request headers beyond the CORS-safelisted ones; `*` allows any.
+    pub allowed_headers: Vec<String>,
+    /// Response headers scripts may read beyond the safelisted ones.
+    pub exposed_headers: Vec<String>,
+    pub allow_credentials: bool,
+    /// How long browsers may cache a preflight answer.
+    pub max_age_secs: Option<u64>,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        Self {
+            allowed_origins: Vec::new(),
+            allowed_methods: ["GET", "HEAD", "POST"].map(String::from).to_vec(),
+            allowed_headers: Vec::new(),
+            exposed_headers: Vec::new(),
+            allow_credentials: false,
+            max_age_secs: None,
+        }
+    }
+}
+
+impl CorsConfig {
+    fn validate(&self, section: &str, errors: &mut Vec<String>) {
+        if self.allowed_origins.is_empty() {
+            errors.push(format!("{section}.cors.allowed_origins must not be empty"));
+        }
+        for origin in &self.allowed_origins {
+            if !is_origin_pattern(origin) {
+                errors.push(format!(
+                    "{section}.cors.allowed_origins: '{origin}' is not '*', an origin or \
+                     'scheme://*.domain'"
+                ));
+            }
+        }
+        if self.allow_credentials && self.allowed_origins.iter().any(|origin| origin == "*") {
+            errors.push(format!(
+                "{section}.cors.allow_credentials cannot be combined with origin '*'"
+            ));
+        }
+        for method in &self.allowed_methods {
+            if method != "*" && axum::http::Method::from_bytes(method.as_bytes()).is_err() {
+                errors.push(format!("{section}.cors.allowed_methods: invalid method '{method}'"));
+            }
+        }
+        for (field, headers) in [
+            ("allowed_headers", &self.allowed_headers),
+            ("exposed_headers", &self.exposed_headers),
+        ] {
+            for header in headers {
+                if header != "*"
+                    && axum::http::HeaderName::from_bytes(header.as_bytes()).is_err()
+                {
+                    errors.push(format!("{section}.cors.{field}: invalid header '{header}'"));
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ApiKeyConfig {
+    /// Forwarded to the function as `FezzWireMeta.principal`.
+    pub principal: String,
+    pub key: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JwtConfig {
+    /// Local JWKS (`{"keys": [...]}`) with HS256 (`oct`), RS256 or ES256 keys.
+    pub jwks_file: PathBuf,
+    /// Required `iss`, if set.
+    pub issuer: Option<String>,
+    /// Required `aud`, if set.
+    pub audience: Option<String>,
+    /// Clock skew tolerated for `exp`/`nbf`.
+    #[serde(default = "default_jwt_leeway_secs")]
+    pub leeway_secs: u64,
+}
+
+fn default_jwt_leeway_secs() -> u64 {
+    DEFAULT_JWT_LEEWAY_SECS
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HmacConfig {
+    pub keys: Vec<HmacKeyConfig>,
+    /// How far `x-fezz-timestamp` may be from the server clock.
+    #[serde(default = "default_hmac_max_skew_secs")]
+    pub max_skew_secs: u64,
+}
+
+fn default_hmac_max_skew_secs() -> u64 {
+    DEFAULT_HMAC_MAX_SKEW_SECS
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HmacKeyConfig {
+    /// Matched against `x-fezz-key-id`; forwarded as the principal.
+    pub key_id: String,
+    pub secret: String,
+}
+
+impl FunctionConfig {
+    /// Applies the fields set in `other` on top of `self`.
+    fn overlay(&mut self, other: &FunctionConfig) {
+        if other.backend.is_some() {
+            self.backend = other.backend;
+        }
+        if other.timeout_ms.is_some() {
+            self.timeout_ms = other.timeout_ms;
+        }
+        if other.max_body_bytes.is_some() {
+            self.max_body_bytes = other.max_body_bytes;
+        }
+        if other.max_response_bytes.is_some() {
+            self.max_response_bytes = other.max_response_bytes;
+        }
+        if other.max_concurrency.is_some() {
+            self.max_concurrency = other.max_concurrency;
+        }
+        if other.max_queue.is_some() {
+            self.max_queue = other.max_queue;
+        }
+        if other.queue_timeout_ms.is_some() {
+            self.queue_timeout_ms = other.queue_timeout_ms;
+        }
+        if other.rate_limits.is_some() {
+            self.rate_limits = other.rate_limits.clone();
+        }
+        if other.auth.is_some() {
+            self.auth = other.auth.clone();
+        }
+        if other.compress_responses.is_some() {
+            self.compress_responses = other.compress_responses;
+        }
+        if other.decompress_requests.is_some() {
+            self.decompress_requests = other.decompress_requests;
+        }
+        if other.cache.is_some() {
+            self.cache = other.cache;
+        }
+        if other.cors.is_some() {
+            self.cors = other.cors.clone();
+        }
+    }
+
+    fn validate(&self, section: &str, errors: &mut Vec<String>) {
+        if self.timeout_ms == Some(0) {
+            errors.push(format!("{section}.timeout_ms must be greater than 0"));
+        }
+        if self.max_body_bytes == Some(0) {
+            errors.push(format!("{section}.max_body_bytes must be greater than 0"));
+        }
+        if self.max_response_bytes == Some(0) {
+            errors.push(format!("{section}.max_response_bytes must be greater than 0"));
+        }
+        if self.max_concurrency == Some(0) {
+            errors.push(format!("{section}.max_concurrency must be greater than 0"));
+        }
+        if self.queue_timeout_ms == Some(0) {
+            errors.push(format!("{section}.queue_timeout_ms must be greater than 0"));
+        }
+        for (index, rule) in self.rate_limits.iter().flatten().enumerate() {
+            if !rule.requests_per_second.is_finite() || rule.requests_per_second <= 0.0 {
+                errors.push(format!(
+                    "{section}.rate_limits[{index}].requests_per_second must be greater than 0"
+                ));
+            }
+            if rule.burst == Some(0) {
+                errors.push(format!(
+                    "{section}.rate_limits[{index}].burst must be greater than 0"
+                ));
+            }
+        }
+        if let Some(auth) = &self.auth {
+            auth.validate(section, errors);
+        }
+        if let Some(cors) = &self.cors {
+            cors.validate(section, errors);
+        }
+    }
+}
+
+impl Config {
+    /// Loads the config file named by `--config`/`HHRF_CONFIG` (built-in
+    /// defaults otherwise), applies command-line overrides and validates.
+    pub fn load(cli: &Cli) -> Result<Self, String> {
+        let mut config = match &cli.config {
+            Some(path) => {
+                let contents = std::fs::read_to_string(path)
+                    .map_err(|e| format!("Failed to read config '{}': {}", path.display(), e))?;
+                toml::from_str::<Config>(&contents)
+                    .map_err(|e| format!("Invalid config '{}': {}", path.display(), e))?
+            }
+            None => Config::default(),
+        };
+
+        config.apply_cli(cli)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn apply_cli(&mut self, cli: &Cli) -> Result<(), String> {
+        if !cli.listen.is_empty() {
+            self.server.listen = cli.listen.clone();
+        }
+        if let Some(root) = &cli.root {
+            self.server.root = root.clone();
+        }
+        if let Some(backend) = &cli.backend {
+            let backend = ExecBackend::parse(backend).ok_or_else(|| {
+                format!("--backend: unknown backend '{backend}' (expected runner, in-process or js)")
+            })?;
+            self.defaults.backend = Some(backend);
+        }
+        if let Some(timeout_ms) = cli.timeout_ms {
+            self.defaults.timeout_ms = Some(timeout_ms);
+        }
+        if let Some(level) = &cli.log_level {
+            self.logging.level = level.clone();
+        }
+        if let Some(token) = &cli.admin_token {
+            self.admin.token = token.clone();
+        }
+        if let Some(format) = &cli.log_format {
+            self.logging.format = LogFormat::parse(format).ok_or_else(|| {
+                format!("--log-format: unknown format '{format}' (expected pretty, compact or json)")
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Collects every problem so operators can fix the file in one pass.
+    fn validate(&self) -> Result<(), String> {
+        let mut errors = Vec::new();
+
+        if self.server.listen.is_empty() && self.tls.listen.is_empty() {
+            errors.push(
+                "server.listen or tls.listen must contain at least one address".to_string(),
+            );
+        }
+        for addr in &self.server.listen {
+            if addr.parse::<SocketAddr>().is_err() {
+                errors.push(format!("server.listen: '{addr}' is not a valid socket address"));
+            }
+        }
+        if self.server.root.trim().is_empty() {
+            errors.push("server.root must not be empty".to_string());
+        }
+        if self.server.max_body_bytes == 0 {
+            errors.push("server.max_body_bytes must be greater than 0".to_string());
+        }
+        if self.server.max_response_bytes == 0 {
+            errors.push("server.max_response_bytes must be greater than 0".to_string());
+        }
+        if self.server.max_concurrency == 0 {
+            errors.push("server.max_concurrency must be greater than 0".to_string());
+        }
+        if HeaderName::from_bytes(self.server.api_key_header.as_bytes()).is_err() {
+            errors.push(format!(
+                "server.api_key_header: '{}' is not a valid header name",
+                self.server.api_key_header
+            ));
+        }
+        for proxy in &self.server.trusted_proxies {
+            if crate::request_meta::parse_net(proxy).is_none() {
+                errors.push(format!(
+                    "server.trusted_proxies: '{proxy}' is not an IP address or CIDR"
+                ));
+            }
+        }
+
+        if tracing_subscriber::EnvFilter::try_new(&self.logging.level).is_err() {
+            errors.push(format!(
+                "logging.level: '{}' is not a valid filter",
+                self.logging.level
+            ));
+        }
+
+        if self.logging.otlp_endpoint.is_some() && !cfg!(feature = "otlp") {
+            errors.push(
+                "logging.otlp_endpoint requires hhrf to be built with the `otlp` feature"
+                    .to_string(),
+            );
+        }
+
+        if self.library_cache.max_entries == 0 {
+            errors.push("library_cache.max_entries must be greater than 0".to_string());
+        }
+        if self.library_cache.ttl_secs == 0 {
+            errors.push("library_cache.ttl_secs must be greater than 0".to_string());
+        }
+
+        if !self.metrics.path.starts_with('/')
+            || self.metrics.path.starts_with("/rpc/")
+            || self.metrics.path.starts_with("/js-embed/")
+        {
+            errors.push(format!(
+                "metrics.path: '{}' must start with '/' and not overlap function routes",
+                self.metrics.path
+            ));
+        }
+
+        for (field, path) in [
+            ("liveness_path", &self.health.liveness_path),
+            ("readiness_path", &self.health.readiness_path),
+        ] {
+            if !path.starts_with('/')
+                || path.starts_with("/rpc/")
+                || path.starts_with("/js-embed/")
+                || (self.metrics.enabled && *path == self.metrics.path)
+            {
+                errors.push(format!(
+                    "health.{field}: '{path}' must start with '/' and not overlap function \
+                     routes or metrics.path"
+                ));
+            }
+        }
+        if self.health.liveness_path == self.health.readiness_path {
+            errors.push("health.liveness_path and health.readiness_path must differ".to_string());
+        }
+        for function in &self.health.functions {
+            let segments = function.split('/').collect::<Vec<_>>();
+            if segments.len() != 3 || segments.iter().any(|segment| segment.is_empty()) {
+                errors.push(format!("health.functions: '{function}' must be 'org/func/version'"));
+            }
+        }
+        if self.health.timeout_ms == 0 {
+            errors.push("health.timeout_ms must be greater than 0".to_string());
+        }
+        if self.discovery.timeout_ms == 0 {
+            errors.push("discovery.timeout_ms must be greater than 0".to_string());
+        }
+
+        if self.function_logs.max_line_bytes == 0 {
+            errors.push("function_logs.max_line_bytes must be greater than 0".to_string());
+        }
+        if self.function_logs.max_bytes_per_invocation == 0 {
+            errors.push(
+                "function_logs.max_bytes_per_invocation must be greater than 0".to_string(),
+            );
+        }
+
+        for content_type in &self.compression.content_types {
+            let lowercase = *content_type == content_type.to_ascii_lowercase();
+            if content_type.trim().is_empty() || !lowercase {
+                errors.push(format!(
+                    "compression.content_types: '{content_type}' must be a non-empty lowercase \
+                     media type, 'type/' or '+suffix'"
+                ));
+            }
+        }
+
+        if self.cache.max_bytes == 0 || self.cache.max_entry_bytes == 0 {
+            errors.push(
+                "cache.max_bytes and cache.max_entry_bytes must be greater than 0".to_string(),
+            );
+        }
+
+        for addr in &self.admin.listen {
+            if addr.parse::<SocketAddr>().is_err() {
+                errors.push(format!("admin.listen: '{addr}' is not a valid socket address"));
+            }
+            if self.server.listen.contains(addr) {
+                errors.push(format!("admin.listen: '{addr}' is also in server.listen"));
+            }
+        }
+        if !self.admin.listen.is_empty() && self.admin.token.trim().is_empty() {
+            errors.push(
+                "admin.token (or HHRF_ADMIN_TOKEN) is required when admin.listen is set"
+                    .to_string(),
+            );
+        }
+        if self.admin.max_upload_bytes == 0 {
+            errors.push("admin.max_upload_bytes must be greater than 0".to_string());
+        }
+
+        for addr in &self.tls.listen {
+            if addr.parse::<SocketAddr>().is_err() {
+                errors.push(format!("tls.listen: '{addr}' is not a valid socket address"));
+            }
+            if self.server.listen.contains(addr) || self.admin.listen.contains(addr) {
+                errors.push(format!(
+                    "tls.listen: '{addr}' is also in server.listen or admin.listen"
+                ));
+            }
+        }
+        if !self.tls.listen.is_empty() && self.tls.certificates.is_empty() {
+            errors.push("tls.certificates is required when tls.listen is set".to_string());
+        }
+        for (index, certificate) in self.tls.certificates.iter().enumerate() {
+            for host in &certificate.hosts {
+                if !is_host_pattern(host) {
+                    errors.push(format!(
+                        "tls.certificates[{index}].hosts: '{host}' must be a lowercase host name \
+                         or '*.domain'"
+                    ));
+                }
+            }
+        }
+
+        self.defaults.validate("defaults", &mut errors);
+        for (key, function) in &self.functions {
+            let segments = key.split('/').collect::<Vec<_>>();
+            let valid_key = segments.len() <= 3
+                && segments
+                    .iter()
+                    .all(|segment| !segment.is_empty() && *segment != "." && *segment != "..");
+            if !valid_key {
+                errors.push(format!(
+                    "functions.\"{key}\": key must be 'org', 'org/func' or 'org/func/version'"
+                ));
+            }
+            function.validate(&format!("functions.\"{key}\""), &mut errors);
+        }
+
+        for (key, aliases) in &self.aliases {
+            let segments = key.split('/').collect::<Vec<_>>();
+            if segments.len() != 2 || segments.iter().any(|segment| segment.is_empty()) {
+                errors.push(format!("aliases.\"{key}\": key must be 'org/func'"));
+            }
+            for (name, target) in aliases {
+                if name == "latest" {
+                    errors.push(format!("aliases.\"{key}\": 'latest' is reserved"));
+                }
+                if target.trim().is_empty() {
+                    errors.push(format!("aliases.\"{key}\".{name} must not be empty"));
+                }
+            }
+        }
+
+        for (index, route) in self.routes.iter().enumerate() {
+            let section = format!("routes[{index}]");
+            if let Some(host) = &route.host {
+                if !is_host_pattern(host) {
+                    errors.push(format!(
+                        "{section}.host: '{host}' must be a lowercase host name or '*.domain'"
+                    ));
+                }
+            }
+            if !route.path_prefix.starts_with('/') {
+                errors.push(format!("{section}.path_prefix must start with '/'"));
+            }
+            let segments = route.function.split('/').collect::<Vec<_>>();
+            let valid_function = segments.len() == 3
+                && segments.iter().all(|segment| !segment.trim().is_empty())
+                && segments[..2].iter().all(|segment| {
+                    *segment != "."
+                        && *segment != ".."
+                        && segment.bytes().all(|b| {
+                            b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.')
+                        })
+                });
+            if !valid_function {
+                errors.push(format!(
+                    "{section}.function: '{}' must be 'org/func/version'",
+                    route.function
+                ));
+            }
+        }
+
+        for (key, split) in &self.splits {
+            let section = format!("splits.\"{key}\"");
+            let segments = key.split('/').collect::<Vec<_>>();
+            if segments.len() != 3 || segments.iter().any(|segment| segment.is_empty()) {
+                errors.push(format!("{section}: key must be 'org/func/alias'"));
+            }
+            if split.targets.is_empty() {
+                errors.push(format!("{section}.targets must not be empty"));
+            }
+            if split.targets.iter().all(|target| target.weight == 0) {
+                errors.push(format!("{section}.targets need a weight greater than 0"));
+            }
+            for (index, target) in split.targets.iter().enumerate() {
+                if target.version.trim().is_empty() {
+                    errors.push(format!("{section}.targets[{index}].version must not be empty"));
+                }
+            }
+            if let Some(header) = &split.sticky_header {
+                if HeaderName::from_bytes(header.as_bytes()).is_err() {
+                    errors.push(format!(
+                        "{section}.sticky_header: '{header}' is not a valid header name"
+                    ));
+                }
+            }
+            if let Some(rollback) = &split.rollback {
+                if !(rollback.max_error_rate > 0.0 && rollback.max_error_rate < 1.0) {
+                    errors.push(format!(
+                        "{section}.rollback.max_error_rate must be between 0 and 1"
+                    ));
+                }
+                if rollback.window_secs == 0 {
+                    errors.push(format!("{section}.rollback.window_secs must be greater than 0"));
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(format!("Invalid configuration:\n  - {}", errors.join("\n  - ")))
+        }
+    }
+
+    pub fn listen_addrs(&self) -> Vec<SocketAddr> {
+        self.server
+            .listen
+            .iter()
+            .filter_map(|addr| addr.parse().ok())
+            .collect()
+    }
+
+    pub fn tls_listen_addrs(&self) -> Vec<SocketAddr> {
+        self.tls
+            .listen
+            .iter()
+            .filter_map(|addr| addr.parse().ok())
+            .collect()
+    }
+
+    pub fn admin_listen_addrs(&self) -> Vec<SocketAddr> {
+        self.admin
+            .listen
+            .iter()
+            .filter_map(|addr| addr.parse().ok())
+            .collect()
+    }
+
+    /// Effective settings for `key`: defaults, then `org`, `org/func`, `org/func/version`.
+    pub fn function(&self, key: &FunctionKey) -> FunctionConfig {
+        let mut resolved = self.defaults.clone();
+        for section in [
+            key.org.clone(),
+            format!("{}/{}", key.org, key.func),
+            key.to_string(),
+        ] {
+            if let Some(function) = self.functions.get(&section) {
+                resolved.overlay(function);
+            }
+        }
+        resolved
+    }
+}
+
+/// `api.acme.example` or `*.acme.example`, lowercase.
+fn is_host_pattern(host: &str) -> bool {
+    let name = host.strip_prefix("*.").unwrap_or(host);
+    !name.is_empty()
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'.')
+        && host == host.to_ascii_lowercase()
+}
+
+/// `*`, `https://app.example.com[:port]` or `https://*.example.com[:port]`,
+/// lowercase and without a path.
+fn is_origin_pattern(origin: &str) -> bool {
+    if origin == "*" {
+        return true;
+    }
+    let Some((scheme, authority)) = origin.split_once("://") else {
+        return false;
+    };
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) => (host, Some(port)),
+        None => (authority, None),
+    };
+    !scheme.is_empty()
+        && scheme.bytes().all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'+')
+        && is_host_pattern(host)
+        && !port.is_some_and(|port| port.is_empty() || !port.bytes().all(|b| b.is_ascii_digit()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(contents: &str) -> Config {
+        toml::from_str(contents).unwrap()
+    }
+
+    fn errors(contents: &str) -> String {
+        parse(contents).validate().unwrap_err()
+    }
+
+    #[test]
+    fn defaults_are_valid() {
+        Config::default().validate().unwrap();
+    }
+
+    #[test]
+    fn validate_reports_every_error_at_once() {
+        let err = errors(
+            r#"
+            [server]
+            max_body_bytes = 0
+            max_concurrency = 0
+
+            [library_cache]
+            max_entries = 0
+
+            [metrics]
+            path = "metrics"
+            "#,
+        );
+        assert!(err.starts_with("Invalid configuration:"));
+        assert!(err.contains("server.max_body_bytes must be greater than 0"));
+        assert!(err.contains("server.max_concurrency must be greater than 0"));
+        assert!(err.contains("library_cache.max_entries must be greater than 0"));
+        assert!(err.contains("metrics.path: 'metrics'"));
+        assert_eq!(err.matches("\n  - ").count(), 4);
+    }
+
+    #[test]
+    fn unknown_fields_are_rejected() {
+        assert!(toml::from_str::<Config>("[server]\nlisten_on = []").is_err());
+    }
+
+    #[test]
+    fn cli_overrides_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("hhrf.toml");
+        std::fs::write(
+            &path,
+            r#"
+            [server]
+            listen = ["0.0.0.0:3000"]
+            root = "/srv/from-file"
+
+            [logging]
+            level = "warn"
+
+            [defaults]
+            backend = "runner"
+            timeout_ms = 1000
+            "#,
+        )
+        .unwrap();
+        let path = path.to_str().unwrap();
+
+        let cli = Cli::parse_from(["hhrf", "--config", path]);
+        let config = Config::load(&cli).unwrap();
+        assert_eq!(config.server.root, "/srv/from-file");
+        assert_eq!(config.defaults.backend, Some(ExecBackend::Runner));
+
+        let cli = Cli::parse_from([
+            "hhrf",
+            "--config",
+            path,
+            "--listen",
+            "127.0.0.1:9000",
+            "--root",
+            "/srv/from-cli",
+            "--backend",
+            "js",
+            "--timeout-ms",
+            "250",
+            "--log-level",
+            "debug",
+            "--log-format",
+            "json",
+        ]);
+        let config = Config::load(&cli).unwrap();
+        assert_eq!(config.server.listen, ["127.0.0.1:9000"]);
+        assert_eq!(config.server.root, "/srv/from-cli");
+        assert_eq!(config.defaults.backend, Some(ExecBackend::Js));
+        assert_eq!(config.defaults.timeout_ms, Some(250));
+        assert_eq!(config.logging.level, "debug");
+        assert_eq!(config.logging.format, LogFormat::Json);
+    }
+
+    #[test]
+    fn invalid_cli_values_are_rejected() {
+        let cli = Cli::parse_from(["hhrf", "--backend", "wasm"]);
+        assert!(Config::load(&cli).unwrap_err().contains("--backend"));
+        let cli = Cli::parse_from(["hhrf", "--listen", "not-an-address"]);
+        assert!(Config::load(&cli).unwrap_err().contains("server.listen"));
+    }
+
+    #[test]
+    fn rejects_bad_limits() {
+        let err = errors(
+            r#"
+            [defaults]
+            timeout_ms = 0
+            max_response_bytes = 0
+
+            [functions."acme/todos"]
+            max_concurrency = 0
+            rate_limits = [{ scope = "client-ip", requests_per_second = 0.0, burst = 0 }]
+
+            [functions."acme/../etc"]
+            timeout_ms = 100
+            "#,
+        );
+        assert!(err.contains("defaults.timeout_ms must be greater than 0"));
+        assert!(err.contains("defaults.max_response_bytes must be greater than 0"));
+        assert!(err.contains("functions.\"acme/todos\".max_concurrency must be greater than 0"));
+        assert!(err.contains("rate_limits[0].requests_per_second must be greater than 0"));
+        assert!(err.contains("rate_limits[0].burst must be greater than 0"));
+        assert!(err.contains("functions.\"acme/../etc\": key must be"));
+    }
+
+    #[test]
+    fn rejects_bad_routes() {
+        let err = errors(
+            r#"
+            [[routes]]
+            host = "API.example.com"
+            path_prefix = "api"
+            function = "acme/todos"
+
+            [[routes]]
+            host = "*.example.com"
+            function = "acme/../stable"
+            "#,
+        );
+        assert!(err.contains("routes[0].host: 'API.example.com'"));
+        assert!(err.contains("routes[0].path_prefix must start with '/'"));
+        assert!(err.contains("routes[0].function: 'acme/todos'"));
+        assert!(!err.contains("routes[1].host"));
+        assert!(err.contains("routes[1].function: 'acme/../stable'"));
+
+        parse(
+            r#"
+            [[routes]]
+            host = "*.example.com"
+            path_prefix = "/api"
+            function = "acme/todos/stable"
+            "#,
+        )
+        .validate()
+        .unwrap();
+    }
+
+    #[test]
+    fn rejects_bad_cors() {
+        let err = errors(
+            r#"
+            [defaults.cors]
+            allowed_origins = ["*", "https://app.example.com/path", "HTTPS://APP.example.com"]
+            allowed_methods = ["GET", "BAD METHOD"]
+            allowed_headers = ["x-ok", "bad header"]
+            allow_credentials = true
+            "#,
+        );
+        assert!(err.contains("defaults.cors.allow_credentials cannot be combined with origin '*'"));
+        assert!(err.contains("'https://app.example.com/path' is not"));
+        assert!(err.contains("'HTTPS://APP.example.com' is not"));
+        assert!(err.contains("invalid method 'BAD METHOD'"));
+        assert!(err.contains("allowed_headers: invalid header 'bad header'"));
+
+        parse(
+            r#"
+            [defaults.cors]
+            allowed_origins = ["https://app.example.com", "https://*.example.com:8443"]
+            allow_credentials = true
+            "#,
+        )
+        .validate()
+        .unwrap();
+        assert!(errors("[defaults.cors]\nallowed_origins = []").contains("must not be empty"));
+    }
+
+    #[test]
+    fn rejects_bad_tls() {
+        let err = errors(
+            r#"
+            [server]
+            listen = ["0.0.0.0:3000"]
+
+            [tls]
+            listen = ["0.0.0.0:3000"]
+            "#,
+        );
+        assert!(err.contains("tls.listen: '0.0.0.0:3000' is also in server.listen"));
+        assert!(err.contains("tls.certificates is required when tls.listen is set"));
+
+        let err = errors(
+            r#"
+            [tls]
+            listen = ["0.0.0.0:3443"]
+            certificates = [{ cert = "a.crt", key = "a.key", hosts = ["API.example.com", "*."] }]
+            "#,
+        );
+        assert!(err.contains("tls.certificates[0].hosts: 'API.example.com'"));
+        assert!(err.contains("tls.certificates[0].hosts: '*.'"));
+    }
+
+    #[test]
+    fn more_specific_function_sections_win() {
+        let config = parse(
+            r#"
+            [defaults]
+            timeout_ms = 1000
+            max_body_bytes = 10
+
+            [functions."acme"]
+            timeout_ms = 2000
+
+            [functions."acme/todos/0.0.1"]
+            timeout_ms = 3000
+            "#,
+        );
+        let key = |version: &str| FunctionKey {
+            org: "acme".to_string(),
+            func: "todos".to_string(),
+            version: version.to_string(),
+        };
+        assert_eq!(config.function(&key("0.0.1")).timeout_ms, Some(3000));
+        assert_eq!(config.function(&key("0.0.2")).timeout_ms, Some(2000));
+        assert_eq!(config.function(&key("0.0.2")).max_body_bytes, Some(10));
+    }
+}
@@ -0,0 +1,297 @@
+use crate::{config::CorsConfig, error_response};
+use axum::{
+    body::Body,
+    http::{
+        header::{
+            ACCESS_CONTROL_ALLOW_CREDENTIALS, ACCESS_CONTROL_ALLOW_HEADERS,
+            ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN,
+            ACCESS_CONTROL_EXPOSE_HEADERS, ACCESS_CONTROL_MAX_AGE,
+            ACCESS_CONTROL_REQUEST_HEADERS, ACCESS_CONTROL_REQUEST_METHOD, ORIGIN, VARY,
+        },
+        HeaderMap, HeaderValue, Method, StatusCode,
+    },
+    response::Response,
+};
+
+/// Answers a CORS preflight (`OPTIONS` with `Origin` and
+/// `Access-Control-Request-Method`) from the policy, without invoking the
+/// function. `None` for every other request.
+///
+/// Refused preflights get a `403`; the browser then blocks the real request.
+pub fn preflight(cors: &CorsConfig, method: &Method, headers: &HeaderMap) -> Option<Response> {
+    if method != Method::OPTIONS || !headers.contains_key(ORIGIN) {
+        return None;
+    }
+    let requested_method = headers.get(ACCESS_CONTROL_REQUEST_METHOD)?;
+
+    let origin = headers.get(ORIGIN).and_then(|value| value.to_str().ok());
+    if !origin.is_some_and(|origin| allows_origin(cors, origin)) {
+        return Some(error_response(403, "CORS origin not allowed".to_string()));
+    }
+    let method_allowed = requested_method.to_str().is_ok_and(|requested| {
+        cors.allowed_methods
+            .iter()
+            .any(|allowed| allowed == "*" || allowed.eq_ignore_ascii_case(requested))
+    });
+    if !method_allowed {
+        return Some(error_response(403, "CORS method not allowed".to_string()));
+    }
+    let requested_headers = headers
+        .get_all(ACCESS_CONTROL_REQUEST_HEADERS)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|header| !header.is_empty())
+        .collect::<Vec<_>>();
+    let any_header = cors.allowed_headers.iter().any(|allowed| allowed == "*");
+    let headers_allowed = any_header
+        || requested_headers.iter().all(|requested| {
+            cors.allowed_headers
+                .iter()
+                .any(|allowed| allowed.eq_ignore_ascii_case(requested))
+        });
+    if !headers_allowed {
+        return Some(error_response(403, "CORS header not allowed".to_string()));
+    }
+
+    let mut resp = Response::new(Body::empty());
+    *resp.status_mut() = StatusCode::NO_CONTENT;
+    let resp_headers = resp.headers_mut();
+    // Wildcards are echoed: `*` is ignored for credentialed requests.
+    let methods = if cors.allowed_methods.iter().any(|allowed| allowed == "*") {
+        Some(requested_method.clone())
+    } else {
+        join(&cors.allowed_methods)
+    };
+    if let Some(methods) = methods {
+        resp_headers.insert(ACCESS_CONTROL_ALLOW_METHODS, methods);
+    }
+    let allowed_headers = if any_header {
+        join(&requested_headers)
+    } else {
+        join(&cors.allowed_headers)
+    };
+    if let Some(allowed_headers) = allowed_headers {
+        resp_headers.insert(ACCESS_CONTROL_ALLOW_HEADERS, allowed_headers);
+    }
+    if let Some(max_age) = cors.max_age_secs {
+        resp_headers.insert(ACCESS_CONTROL_MAX_AGE, HeaderValue::from(max_age));
+    }
+    add_vary(resp_headers, "access-control-request-method");
+    add_vary(resp_headers, "access-control-request-headers");
+    Some(resp)
+}
+
+/// Sets the CORS response headers for a request from `origin`, replacing any
+/// the function sent itself. Responses vary on `Origin` unless every origin
+/// gets the same `*`.
+pub fn apply(cors: &CorsConfig, origin: Option<&HeaderValue>, resp: &mut Response) {
+    let headers = resp.headers_mut();
+    for name in [
+        ACCESS_CONTROL_ALLOW_ORIGIN,
+        ACCESS_CONTROL_ALLOW_CREDENTIALS,
+        ACCESS_CONTROL_EXPOSE_HEADERS,
+    ] {
+        headers.remove(name);
+    }
+
+    let any_origin = cors.allowed_origins.iter().any(|allowed| allowed == "*");
+    if any_origin && !cors.allow_credentials {
+        headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, HeaderValue::from_static("*"));
+    } else {
+        add_vary(headers, "origin");
+        let Some(origin) = origin.filter(|origin| {
+            origin
+                .to_str()
+                .is_ok_and(|origin| allows_origin(cors, origin))
+        }) else {
+            return;
+        };
+        headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, origin.clone());
+        if cors.allow_credentials {
+            headers.insert(
+                ACCESS_CONTROL_ALLOW_CREDENTIALS,
+                HeaderValue::from_static("true"),
+            );
+        }
+    }
+    if let Some(exposed) = join(&cors.exposed_headers) {
+        headers.insert(ACCESS_CONTROL_EXPOSE_HEADERS, exposed);
+    }
+}
+
+fn allows_origin(cors: &CorsConfig, origin: &str) -> bool {
+    cors.allowed_origins
+        .iter()
+        .any(|allowed| origin_matches(allowed, origin))
+}
+
+/// `https://*.example.com` matches any subdomain of `example.com`, at any
+/// depth, but not `example.com` itself.
+fn origin_matches(pattern: &str, origin: &str) -> bool {
+    if pattern == "*" {
+        return true;
+    }
+    let origin = origin.to_ascii_lowercase();
+    let Some((prefix, suffix)) = pattern.split_once('*') else {
+        return origin == pattern;
+    };
+    let Some(subdomain) = origin
+        .strip_prefix(prefix)
+        .and_then(|rest| rest.strip_suffix(suffix))
+    else {
+        return false;
+    };
+    !subdomain.is_empty()
+        && !subdomain.starts_with('.')
+        && subdomain
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'.')
+}
+
+fn join<S: AsRef<str>>(values: &[S]) -> Option<HeaderValue> {
+    if values.is_empty() {
+        return None;
+    }
+    let joined = values
+        .iter()
+        .map(AsRef::as_ref)
+        .collect::<Vec<_>>()
+        .join(", ");
+    HeaderValue::from_str(&joined).ok()
+}
+
+fn add_vary(headers: &mut HeaderMap, field: &'static str) {
+    let covered = headers
+        .get_all(VARY)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .any(|listed| listed == "*" || listed.eq_ignore_ascii_case(field));
+    if !covered {
+        headers.append(VARY, HeaderValue::from_static(field));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cors(origins: &[&str], allow_credentials: bool) -> CorsConfig {
+        CorsConfig {
+            allowed_origins: origins.iter().map(|origin| origin.to_string()).collect(),
+            allowed_headers: vec!["content-type".to_string()],
+            exposed_headers: vec!["x-request-id".to_string()],
+            allow_credentials,
+            max_age_secs: Some(600),
+            ..CorsConfig::default()
+        }
+    }
+
+    fn applied(cors: &CorsConfig, origin: Option<&'static str>) -> HeaderMap {
+        let mut resp = Response::new(Body::empty());
+        resp.headers_mut()
+            .insert(ACCESS_CONTROL_ALLOW_ORIGIN, HeaderValue::from_static("https://evil.example"));
+        let origin = origin.map(HeaderValue::from_static);
+        apply(cors, origin.as_ref(), &mut resp);
+        resp.headers().clone()
+    }
+
+    fn preflight_headers(origin: &'static str, method: &'static str, headers: &str) -> HeaderMap {
+        let mut map = HeaderMap::new();
+        map.insert(ORIGIN, HeaderValue::from_static(origin));
+        map.insert(ACCESS_CONTROL_REQUEST_METHOD, HeaderValue::from_static(method));
+        if !headers.is_empty() {
+            map.insert(ACCESS_CONTROL_REQUEST_HEADERS, HeaderValue::from_str(headers).unwrap());
+        }
+        map
+    }
+
+    #[test]
+    fn wildcard_subdomains_match_at_any_depth() {
+        let pattern = "https://*.example.com";
+        assert!(origin_matches(pattern, "https://app.example.com"));
+        assert!(origin_matches(pattern, "https://a.b.example.com"));
+        assert!(origin_matches(pattern, "HTTPS://APP.EXAMPLE.COM"));
+        assert!(!origin_matches(pattern, "https://example.com"));
+        assert!(!origin_matches(pattern, "https://.example.com"));
+        assert!(!origin_matches(pattern, "http://app.example.com"));
+        assert!(!origin_matches(pattern, "https://app.example.com.evil.net"));
+        assert!(!origin_matches(pattern, "https://evil.net/.example.com"));
+        assert!(origin_matches("https://app.example.com", "https://app.example.com"));
+        assert!(!origin_matches("https://app.example.com", "https://app.example.com:8443"));
+    }
+
+    #[test]
+    fn any_origin_without_credentials_is_a_plain_star() {
+        let headers = applied(&cors(&["*"], false), Some("https://app.example.com"));
+        assert_eq!(headers.get(ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(), "*");
+        assert!(headers.get(ACCESS_CONTROL_ALLOW_CREDENTIALS).is_none());
+        assert!(headers.get(VARY).is_none());
+        assert_eq!(headers.get(ACCESS_CONTROL_EXPOSE_HEADERS).unwrap(), "x-request-id");
+    }
+
+    #[test]
+    fn credentialed_origins_are_echoed_and_vary() {
+        let cors = cors(&["https://*.example.com"], true);
+        let headers = applied(&cors, Some("https://app.example.com"));
+        assert_eq!(
+            headers.get(ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(),
+            "https://app.example.com"
+        );
+        assert_eq!(headers.get(ACCESS_CONTROL_ALLOW_CREDENTIALS).unwrap(), "true");
+        assert_eq!(headers.get(VARY).unwrap(), "origin");
+
+        let refused = applied(&cors, Some("https://evil.example"));
+        assert!(refused.get(ACCESS_CONTROL_ALLOW_ORIGIN).is_none());
+        assert!(refused.get(ACCESS_CONTROL_ALLOW_CREDENTIALS).is_none());
+        assert_eq!(refused.get(VARY).unwrap(), "origin");
+        assert!(applied(&cors, None).get(ACCESS_CONTROL_ALLOW_ORIGIN).is_none());
+    }
+
+    #[test]
+    fn preflight_checks_origin_method_and_headers() {
+        let cors = cors(&["https://app.example.com"], true);
+        let ok = preflight_headers("https://app.example.com", "POST", "Content-Type");
+        let resp = preflight(&cors, &Method::OPTIONS, &ok).unwrap();
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        let headers = resp.headers();
+        assert_eq!(headers.get(ACCESS_CONTROL_ALLOW_METHODS).unwrap(), "GET, HEAD, POST");
+        assert_eq!(headers.get(ACCESS_CONTROL_ALLOW_HEADERS).unwrap(), "content-type");
+        assert_eq!(headers.get(ACCESS_CONTROL_MAX_AGE).unwrap(), "600");
+
+        for refused in [
+            preflight_headers("https://evil.example", "POST", ""),
+            preflight_headers("https://app.example.com", "DELETE", ""),
+            preflight_headers("https://app.example.com", "POST", "x-secret"),
+        ] {
+            let resp = preflight(&cors, &Method::OPTIONS, &refused).unwrap();
+            assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        }
+    }
+
+    #[test]
+    fn only_options_with_request_method_is_a_preflight() {
+        let cors = cors(&["*"], false);
+        let headers = preflight_headers("https://app.example.com", "POST", "");
+        assert!(preflight(&cors, &Method::POST, &headers).is_none());
+        let mut plain = headers.clone();
+        plain.remove(ACCESS_CONTROL_REQUEST_METHOD);
+        assert!(preflight(&cors, &Method::OPTIONS, &plain).is_none());
+    }
+
+    #[test]
+    fn wildcard_methods_and_headers_echo_the_request() {
+        let cors = CorsConfig {
+            allowed_methods: vec!["*".to_string()],
+            allowed_headers: vec!["*".to_string()],
+            ..cors(&["*"], false)
+        };
+        let headers = preflight_headers("https://app.example.com", "PATCH", "x-a, x-b");
+        let resp = preflight(&cors, &Method::OPTIONS, &headers).unwrap();
+        assert_eq!(resp.headers().get(ACCESS_CONTROL_ALLOW_METHODS).unwrap(), "PATCH");
+        assert_eq!(resp.headers().get(ACCESS_CONTROL_ALLOW_HEADERS).unwrap(), "x-a, x-b");
+    }
+}
@@ -0,0 +1,202 @@
+use crate::{
+    admin::{self, Artifact},
+    config::Config,
+    exec::{self, ExecBackend},
+    library_cache,
+    reload::file_stamp,
+    settings::FunctionSettings,
+    AppState, FunctionKey,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+/// What was found for one version the last time its artifacts changed.
+struct Scanned {
+    stamps: Vec<Option<(SystemTime, u64)>>,
+    valid: bool,
+}
+
+/// Versions seen by previous scans, so unchanged ones are not checked again.
+#[derive(Default)]
+struct Discovery {
+    known: HashMap<FunctionKey, Scanned>,
+}
+
+/// Scans `functions/{org}/{func}/{version}` and validates every artifact:
+/// `fezz.so` must export `fezz_handle_v2` and `fezz_free_v2` (checked by a
+/// runner, or from the symbol table for the in-process backend, so nothing
+/// is loaded into HHRF), `fezz.js` must evaluate and export `fetch`. With `discovery.strict` an
+/// invalid artifact (or an unreadable tree) stops HHRF before it listens.
+///
+/// Afterwards the tree is rescanned every `discovery.interval_secs`; only
+/// new and changed versions are validated and logged.
+pub async fn start(state: Arc<AppState>) {
+    let config = state.config();
+    if !config.discovery.enabled {
+        return;
+    }
+
+    let mut discovery = Discovery::default();
+    let invalid = discovery.scan(&state).await;
+    match &invalid {
+        Ok(0) => tracing::info!("Discovered {} function versions", discovery.known.len()),
+        Ok(invalid) => tracing::warn!(
+            "Discovered {} function versions, {} invalid",
+            discovery.known.len(),
+            invalid
+        ),
+        Err(err) => tracing::error!("Function discovery failed: {}", err),
+    }
+    if config.discovery.strict && !matches!(invalid, Ok(0)) {
+        tracing::error!("Refusing to start with invalid functions (discovery.strict)");
+        std::process::exit(2);
+    }
+
+    if config.discovery.interval_secs == 0 {
+        return;
+    }
+    let period = Duration::from_secs(config.discovery.interval_secs);
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+        loop {
+            interval.tick().await;
+            if let Err(err) = discovery.scan(&state).await {
+                tracing::error!("Function discovery failed: {}", err);
+            }
+        }
+    });
+}
+
+impl Discovery {
+    /// Validates new and changed versions, forgets removed ones and returns
+    /// how many known versions are invalid.
+    async fn scan(&mut self, state: &Arc<AppState>) -> Result<usize, String> {
+        let config = state.config();
+        let timeout = Duration::from_millis(config.discovery.timeout_ms);
+        let functions_dir = PathBuf::from(format!("{}/functions", config.server.root));
+        let versions = tokio::task::spawn_blocking(move || list(&functions_dir))
+            .await
+            .map_err(|e| format!("Failed to join discovery: {}", e))??;
+
+        let mut present = HashSet::new();
+        for (key, dir) in versions {
+            let stamps = [Artifact::Native, Artifact::Js]
+                .iter()
+                .map(|artifact| file_stamp(&dir.join(artifact.file_name())))
+                .collect::<Vec<_>>();
+            present.insert(key.clone());
+            if self
+                .known
+                .get(&key)
+                .is_some_and(|scanned| scanned.stamps == stamps)
+            {
+                continue;
+            }
+
+            let valid = match validate(state, &config, &key, &dir, timeout).await {
+                Ok(()) => {
+                    tracing::info!("Function {} is valid", key);
+                    true
+                }
+                Err(err) => {
+                    tracing::error!("Function {} is invalid: {}", key, err);
+                    false
+                }
+            };
+            self.known.insert(key, Scanned { stamps, valid });
+        }
+
+        self.known.retain(|key, _| {
+            let kept = present.contains(key);
+            if !kept {
+                tracing::info!("Function {} was removed", key);
+            }
+            kept
+        });
+        Ok(self.known.values().filter(|scanned| !scanned.valid).count())
+    }
+}
+
+/// Every visible version directory under `functions_dir`.
+fn list(functions_dir: &Path) -> Result<Vec<(FunctionKey, PathBuf)>, String> {
+    let mut versions = Vec::new();
+    for org in admin::visible_dirs(functions_dir)? {
+        for func in admin::visible_dirs(&functions_dir.join(&org))? {
+            for version in admin::visible_dirs(&functions_dir.join(&org).join(&func))? {
+                let dir = functions_dir.join(&org).join(&func).join(&version);
+                let key = FunctionKey {
+                    org: org.clone(),
+                    func: func.clone(),
+                    version,
+                };
+                versions.push((key, dir));
+            }
+        }
+    }
+    Ok(versions)
+}
+
+/// Checks the artifact the function's backend runs and any other artifact
+/// present in `dir`.
+async fn validate(
+    state: &Arc<AppState>,
+    config: &Config,
+    key: &FunctionKey,
+    dir: &Path,
+    timeout: Duration,
+) -> Result<(), String> {
+    let settings = FunctionSettings::resolve(config, key);
+    let needed = match settings.backend {
+        ExecBackend::Js => Artifact::Js,
+        ExecBackend::InProcess | ExecBackend::Runner => Artifact::Native,
+    };
+
+    let mut errors = Vec::new();
+    for artifact in [Artifact::Native, Artifact::Js] {
+        let path = dir.join(artifact.file_name());
+        if !path.is_file() {
+            if artifact == needed {
+                errors.push(format!(
+                    "{} backend needs {}",
+                    settings.backend.as_str(),
+                    artifact.file_name()
+                ));
+            }
+            continue;
+        }
+
+        let path = path.to_string_lossy().into_owned();
+        let checked = match artifact {
+            Artifact::Js => state
+                .js_runtime_manager
+                .validate(&path, timeout)
+                .await
+                .map_err(|e| format!("{:#}", e)),
+            Artifact::Native if settings.backend == ExecBackend::InProcess => {
+                // Never dlopen here: a library's initializers would run in HHRF
+                // for every deploy. It is loaded on its first request.
+                let checked =
+                    tokio::task::spawn_blocking(move || library_cache::check_exports(&path));
+                match tokio::time::timeout(timeout, checked).await {
+                    Ok(Ok(checked)) => checked,
+                    Ok(Err(err)) => Err(format!("Failed to join check: {}", err)),
+                    Err(_) => Err(format!("Checking timed out after {:?}", timeout)),
+                }
+            }
+            Artifact::Native => exec::validate_in_runner(&path, timeout).await,
+        };
+        if let Err(err) = checked {
+            errors.push(format!("{}: {}", artifact.file_name(), err));
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors.join("; "))
+    }
+}
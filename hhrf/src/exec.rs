@@ -0,0 +1,602 @@
+use crate::{
+    concurrency::InvocationPermit,
+    function_logs::Capture,
+    library_cache::LibraryCache,
+    settings::FunctionSettings,
+    shutdown::{self, Phase},
+    FunctionKey,
+};
+use fezz_sdk::{FezzWireHealth, FezzWireHealthCheck};
+use serde::Deserialize;
+use std::{
+    fmt,
+    os::{
+        fd::{AsRawFd, RawFd},
+        unix::process::ExitStatusExt,
+    },
+    path::Path as FsPath,
+    process::Stdio,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::unix::pipe,
+    process::{Child, ChildStdin, ChildStdout, Command},
+    sync::watch,
+    task::JoinHandle,
+};
+use tracing::Instrument;
+
+/// How long to wait for a runner's output to drain after the child is gone.
+const OUTPUT_DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Where the runner finds the pipe for its own `tracing` events.
+const RUNNER_LOG_FD: RawFd = 3;
+
+/// How a function is executed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ExecBackend {
+    /// `dlopen` the library inside the HHRF process and call it on the blocking pool.
+    InProcess,
+    /// Spawn `FEZZ_RUNNER` per call and exchange CBOR over stdin/stdout.
+    Runner,
+    /// Run the function's `fezz.js` in a V8 isolate instead of a native library.
+    Js,
+}
+
+impl ExecBackend {
+    /// Config spelling, also used as the `backend` metrics label.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::InProcess => "in-process",
+            Self::Runner => "runner",
+            Self::Js => "js",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim() {
+            "in-process" | "inprocess" => Some(Self::InProcess),
+            "runner" => Some(Self::Runner),
+            "js" => Some(Self::Js),
+            _ => None,
+        }
+    }
+}
+
+/// Why a native invocation did not produce response bytes.
+#[derive(Debug)]
+pub enum ExecError {
+    /// The function exceeded its execution budget.
+    Timeout(Duration),
+    /// The function returned more than the allowed number of bytes.
+    ResponseTooLarge(u64),
+    /// The runner child died from a signal or exited unsuccessfully.
+    Crashed(String),
+    /// HHRF shut down before the function finished.
+    ShuttingDown,
+    Failed(String),
+}
+
+impl ExecError {
+    /// HTTP status reported to the client.
+    pub fn status(&self) -> u16 {
+        match self {
+            ExecError::Timeout(_) => 504,
+            ExecError::ResponseTooLarge(_) => 502,
+            ExecError::ShuttingDown => 503,
+            ExecError::Crashed(_) | ExecError::Failed(_) => 500,
+        }
+    }
+}
+
+impl fmt::Display for ExecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExecError::Timeout(budget) => write!(f, "Function timed out after {:?}", budget),
+            ExecError::ResponseTooLarge(limit) => {
+                write!(f, "Function response exceeds the limit of {} bytes", limit)
+            }
+            ExecError::ShuttingDown => f.write_str("Server is shutting down"),
+            ExecError::Crashed(message) | ExecError::Failed(message) => f.write_str(message),
+        }
+    }
+}
+
+impl From<String> for ExecError {
+    fn from(message: String) -> Self {
+        ExecError::Failed(message)
+    }
+}
+
+/// One native invocation.
+pub struct NativeCall {
+    pub key: FunctionKey,
+    pub so_path: String,
+    /// Encoded `FezzWireRequest`.
+    pub req_bytes: Vec<u8>,
+    pub env_vars: Vec<(String, String)>,
+    /// Remaining execution budget.
+    pub timeout: Duration,
+    /// Concurrency slots, released only when the work has really finished.
+    pub permit: InvocationPermit,
+    /// Receives the runner child's stdout/stderr output.
+    pub logs: Capture,
+    /// A runner child is killed once this reaches [`Phase::Aborting`].
+    pub shutdown: watch::Receiver<Phase>,
+}
+
+/// Response bytes of a successful native invocation.
+pub struct Executed {
+    pub resp_bytes: Vec<u8>,
+    /// Time spent getting the function ready: resolving (and on a miss,
+    /// loading) the cached library, or spawning the runner.
+    pub load_time: Duration,
+}
+
+/// Executes a native Fezz function with the backend from `settings`.
+///
+/// The function env always travels inside the request bytes. `call.env_vars` is
+/// additionally exported to runner children, which own their whole process;
+/// the in-process backend never touches the host environment.
+///
+/// `call.timeout` is the remaining budget. A runner child is killed when it runs
+/// out; an in-process call cannot be interrupted, so its blocking thread runs
+/// to completion in the background while the caller gets the timeout. The
+/// same holds when HHRF gives up draining on shutdown.
+///
+/// Responses larger than `settings.max_response_bytes` are rejected without
+/// ever being buffered in full.
+pub async fn execute(
+    settings: &FunctionSettings,
+    library_cache: &Arc<LibraryCache>,
+    call: NativeCall,
+) -> Result<Executed, ExecError> {
+    let max_response_bytes = settings.max_response_bytes;
+    let timeout = call.timeout;
+    match settings.backend {
+        ExecBackend::InProcess => {
+            let run = execute_in_process(library_cache.clone(), call, max_response_bytes)
+                .instrument(tracing::info_span!("invoke"));
+            match tokio::time::timeout(timeout, run).await {
+                Ok(result) => result,
+                Err(_) => Err(ExecError::Timeout(timeout)),
+            }
+        }
+        ExecBackend::Runner => {
+            let NativeCall {
+                so_path,
+                req_bytes,
+                env_vars,
+                permit,
+                logs,
+                shutdown,
+                ..
+            } = call;
+            let result = execute_in_runner(
+                &so_path,
+                req_bytes,
+                env_vars,
+                timeout,
+                max_response_bytes,
+                logs,
+                shutdown,
+            )
+            .instrument(tracing::info_span!("invoke"))
+            .await;
+            // The child is gone (exited or killed) by now.
+            drop(permit);
+            result
+        }
+        ExecBackend::Js => Err(format!(
+            "'{}' is a JS function, not a native library",
+            call.key
+        )
+        .into()),
+    }
+}
+
+/// Execute a Fezz function in an isolated `fezz-runner` child process.
+///
+/// `FEZZ_RUNNER` may name a wrapper (e.g. a jailer script) plus its own
+/// arguments; the library path is appended as the last argument. A crash in
+/// the plugin only kills the child, never the server. The runner writes its
+/// own diagnostics to a dedicated pipe at [`RUNNER_LOG_FD`]; they are
+/// re-emitted as `tracing` events inside the current span. The plugin's
+/// stdout and stderr end up on the runner's stderr and go to `logs`.
+async fn execute_in_runner(
+    so_path: &str,
+    req_bytes: Vec<u8>,
+    env_vars: Vec<(String, String)>,
+    timeout: Duration,
+    max_response_bytes: u64,
+    logs: Capture,
+    shutdown: watch::Receiver<Phase>,
+) -> Result<Executed, ExecError> {
+    if !FsPath::new(so_path).exists() {
+        return Err(format!("Library not found at {}", so_path).into());
+    }
+
+    let runner = std::env::var("FEZZ_RUNNER").unwrap_or_else(|_| "fezz-runner".into());
+    let mut command = runner_command(&runner, so_path, env_vars)?;
+    let (log_writer, log_reader) =
+        pipe::pipe().map_err(|e| format!("Failed to create runner log pipe: {}", e))?;
+    let log_writer = log_writer
+        .into_blocking_fd()
+        .map_err(|e| format!("Failed to create runner log pipe: {}", e))?;
+    pass_log_fd(&mut command, log_writer.as_raw_fd());
+
+    let load_start = Instant::now();
+    let spawned = tracing::info_span!("spawn_runner").in_scope(|| {
+        command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+    });
+    // Only the child may hold the write end, so the reader sees EOF on exit.
+    drop(log_writer);
+    let mut child = spawned.map_err(|e| format!("Failed to spawn runner '{}': {}", runner, e))?;
+    let load_time = load_start.elapsed();
+
+    let stdin = child
+        .stdin
+        .take()
+        .ok_or_else(|| "Runner stdin is not piped".to_string())?;
+    let stdout = child
+        .stdout
+        .take()
+        .ok_or_else(|| "Runner stdout is not piped".to_string())?;
+    let mut output = vec![tokio::spawn(
+        crate::telemetry::forward_runner_log(log_reader).in_current_span(),
+    )];
+    if let Some(stderr) = child.stderr.take() {
+        output.push(tokio::spawn(
+            crate::telemetry::forward_stdio(stderr, logs).in_current_span(),
+        ));
+    }
+
+    let result = communicate(
+        child,
+        stdin,
+        stdout,
+        req_bytes,
+        timeout,
+        max_response_bytes,
+        shutdown,
+    )
+    .await;
+    drain_output(output).await;
+
+    Ok(Executed {
+        resp_bytes: result?,
+        load_time,
+    })
+}
+
+/// Makes `fd` the runner's [`RUNNER_LOG_FD`] and tells the runner about it
+/// through `FEZZ_RUNNER_LOG_FD`.
+fn pass_log_fd(command: &mut Command, fd: RawFd) {
+    command.env("FEZZ_RUNNER_LOG_FD", RUNNER_LOG_FD.to_string());
+    // SAFETY: only async-signal-safe calls between fork and exec.
+    unsafe {
+        command.pre_exec(move || {
+            // `dup2` onto itself would keep close-on-exec set.
+            let result = if fd == RUNNER_LOG_FD {
+                libc::fcntl(fd, libc::F_SETFD, 0)
+            } else {
+                libc::dup2(fd, RUNNER_LOG_FD)
+            };
+            if result < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+}
+
+/// Runs `FEZZ_RUNNER` in health mode (`FEZZ_RUNNER_HEALTH=1`): the runner
+/// loads the library, resolves the V2 symbols and reports its
+/// `fezz_health_v1`, which gets the function env on stdin, without a request.
+pub async fn health_in_runner(
+    so_path: &str,
+    env_vars: Vec<(String, String)>,
+    timeout: Duration,
+) -> Result<FezzWireHealth, String> {
+    let check_bytes = health_check_bytes(&env_vars)?;
+    check_in_runner(so_path, env_vars, timeout, "1", Some(check_bytes)).await
+}
+
+/// Like [`health_in_runner`] with `FEZZ_RUNNER_HEALTH=symbols`: only checks
+/// that the library loads and exports the V2 symbols.
+pub async fn validate_in_runner(so_path: &str, timeout: Duration) -> Result<(), String> {
+    check_in_runner(so_path, Vec::new(), timeout, "symbols", None)
+        .await
+        .map(|_| ())
+}
+
+/// The CBOR `FezzWireHealthCheck` passed to `fezz_health_v1`.
+pub fn health_check_bytes(env_vars: &[(String, String)]) -> Result<Vec<u8>, String> {
+    let check = FezzWireHealthCheck {
+        env: env_vars.iter().cloned().collect(),
+    };
+    fezz_sdk::encode_health_check(&check)
+        .map_err(|e| format!("Failed to encode health check: {}", e))
+}
+
+async fn check_in_runner(
+    so_path: &str,
+    env_vars: Vec<(String, String)>,
+    timeout: Duration,
+    mode: &str,
+    check_bytes: Option<Vec<u8>>,
+) -> Result<FezzWireHealth, String> {
+    if !FsPath::new(so_path).exists() {
+        return Err(format!("Library not found at {}", so_path));
+    }
+
+    let runner = std::env::var("FEZZ_RUNNER").unwrap_or_else(|_| "fezz-runner".into());
+    let mut command = runner_command(&runner, so_path, env_vars)?;
+    let stdin = if check_bytes.is_some() {
+        Stdio::piped()
+    } else {
+        Stdio::null()
+    };
+    let mut child = command
+        .env("FEZZ_RUNNER_HEALTH", mode)
+        .stdin(stdin)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| format!("Failed to spawn runner '{}': {}", runner, e))?;
+    let stdin = child.stdin.take();
+    let output = async move {
+        if let (Some(mut stdin), Some(check_bytes)) = (stdin, check_bytes) {
+            // A runner that exits without reading says why through its status.
+            let _ = stdin.write_all(&check_bytes).await;
+        }
+        child.wait_with_output().await
+    };
+    let output = match tokio::time::timeout(timeout, output).await {
+        Ok(output) => output.map_err(|e| format!("Failed to wait for runner '{}': {}", runner, e))?,
+        Err(_) => return Err(format!("Health check timed out after {:?}", timeout)),
+    };
+
+    if !output.status.success() {
+        // The runner logs why as JSON lines; the last one is the error.
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let reason = stderr.lines().rev().find(|line| !line.trim().is_empty());
+        return Err(match (output.status.signal(), reason) {
+            (Some(signal), _) => format!("Runner crashed with signal {}", signal),
+            (None, Some(reason)) => format!("Runner failed: {}", reason.trim()),
+            (None, None) => format!(
+                "Runner exited with status {}",
+                output.status.code().unwrap_or(-1)
+            ),
+        });
+    }
+    fezz_sdk::decode_health(&output.stdout)
+        .map_err(|e| format!("Invalid health response from runner: {}", e))
+}
+
+/// `runner` (a program plus its own arguments) with `so_path` appended.
+///
+/// The child starts from an empty environment and only gets `PATH`, the
+/// `FEZZ_*` variables and the function's `.env`, so HHRF's own secrets
+/// (`HHRF_ADMIN_TOKEN`, cloud credentials, OTLP settings) never reach
+/// untrusted code.
+fn runner_command(
+    runner: &str,
+    so_path: &str,
+    env_vars: Vec<(String, String)>,
+) -> Result<Command, String> {
+    let mut runner_args = runner.split_whitespace();
+    let program = runner_args
+        .next()
+        .ok_or_else(|| "FEZZ_RUNNER is empty".to_string())?;
+    let inherited = std::env::vars_os().filter(|(name, _)| {
+        name == "PATH" || name.to_str().is_some_and(|name| name.starts_with("FEZZ_"))
+    });
+
+    let mut command = Command::new(program);
+    command
+        .args(runner_args)
+        .arg(so_path)
+        .env_clear()
+        .envs(inherited)
+        .envs(env_vars)
+        .env_remove("FEZZ_RUNNER_LOG_FD");
+    Ok(command)
+}
+
+/// Feeds the request to a runner child and collects its response.
+async fn communicate(
+    mut child: Child,
+    mut stdin: ChildStdin,
+    mut stdout: ChildStdout,
+    req_bytes: Vec<u8>,
+    timeout: Duration,
+    max_response_bytes: u64,
+    shutdown: watch::Receiver<Phase>,
+) -> Result<Vec<u8>, ExecError> {
+    let writer = tokio::spawn(async move {
+        stdin.write_all(&req_bytes).await?;
+        // Dropping stdin closes the pipe so the runner sees EOF.
+        stdin.shutdown().await
+    });
+
+    let run = async {
+        let mut resp_bytes = Vec::new();
+        // Read one byte past the limit to tell "exactly at" from "over".
+        (&mut stdout)
+            .take(max_response_bytes.saturating_add(1))
+            .read_to_end(&mut resp_bytes)
+            .await
+            .map_err(|e| format!("Failed to read runner stdout: {}", e))?;
+        if resp_bytes.len() as u64 > max_response_bytes {
+            return Ok(None);
+        }
+        let status = child
+            .wait()
+            .await
+            .map_err(|e| format!("Failed to wait for runner: {}", e))?;
+        Ok::<_, String>(Some((status, resp_bytes)))
+    };
+    let result = tokio::select! {
+        result = tokio::time::timeout(timeout, run) => result,
+        _ = shutdown::reached(shutdown, Phase::Aborting) => {
+            writer.abort();
+            if let Err(err) = child.kill().await {
+                tracing::warn!("Failed to kill runner on shutdown: {}", err);
+            }
+            return Err(ExecError::ShuttingDown);
+        }
+    };
+
+    let (status, resp_bytes) = match result {
+        Ok(Ok(Some(output))) => output,
+        Ok(Ok(None)) => {
+            writer.abort();
+            if let Err(err) = child.kill().await {
+                tracing::warn!("Failed to kill runner with oversized response: {}", err);
+            }
+            return Err(ExecError::ResponseTooLarge(max_response_bytes));
+        }
+        Ok(Err(err)) => return Err(err.into()),
+        Err(_) => {
+            writer.abort();
+            if let Err(err) = child.kill().await {
+                tracing::warn!("Failed to kill timed out runner: {}", err);
+            }
+            return Err(ExecError::Timeout(timeout));
+        }
+    };
+
+    if !status.success() {
+        return Err(ExecError::Crashed(match status.signal() {
+            Some(signal) => format!("Runner crashed with signal {}", signal),
+            None => format!("Runner exited with status {}", status.code().unwrap_or(-1)),
+        }));
+    }
+
+    match writer.await {
+        Ok(Ok(())) => Ok(resp_bytes),
+        Ok(Err(e)) => Err(format!("Failed to write request to runner: {}", e).into()),
+        Err(e) => Err(format!("Failed to join runner writer: {}", e).into()),
+    }
+}
+
+/// Waits for the log and stderr readers so the last lines are captured.
+/// A wrapper that leaves a pipe open in a grandchild only costs
+/// [`OUTPUT_DRAIN_TIMEOUT`].
+async fn drain_output(tasks: Vec<JoinHandle<()>>) {
+    let drained = async {
+        for task in tasks {
+            let _ = task.await;
+        }
+    };
+    if tokio::time::timeout(OUTPUT_DRAIN_TIMEOUT, drained).await.is_err() {
+        tracing::debug!("Runner output still open after the child exited");
+    }
+}
+
+/// Execute a Fezz function in-process via the library cache.
+///
+/// `call.so_path` is the path to the dynamic library containing `fezz_handle_v2`.
+/// `call.req_bytes` is the FezzWireRequest bytes passed to the plugin. The
+/// library stays resident after the call so warm state (e.g. `OnceLock`
+/// clients) survives between requests.
+async fn execute_in_process(
+    library_cache: Arc<LibraryCache>,
+    call: NativeCall,
+    max_response_bytes: u64,
+) -> Result<Executed, ExecError> {
+    let span = tracing::Span::current();
+    tokio::task::spawn_blocking(move || {
+        let _span = span.enter();
+        let NativeCall {
+            key,
+            so_path,
+            req_bytes,
+            permit,
+            ..
+        } = call;
+        let load_start = Instant::now();
+        let library = tracing::info_span!("load_library")
+            .in_scope(|| library_cache.get(&key, &so_path))?;
+        let load_time = load_start.elapsed();
+        let result = tracing::info_span!("call_library")
+            .in_scope(|| unsafe { library.invoke(&req_bytes, max_response_bytes) });
+        drop(permit);
+        Ok(Executed {
+            resp_bytes: result?,
+            load_time,
+        })
+    })
+    .await
+    .map_err(|e| ExecError::Failed(format!("Failed to join blocking task: {}", e)))?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runner_gets_only_allowlisted_env() {
+        let env_vars = vec![("DATABASE_URL".to_string(), "postgres://db".to_string())];
+        let command = runner_command("jail --quiet", "/srv/fezz.so", env_vars).unwrap();
+        let command = command.as_std();
+
+        assert_eq!(command.get_program(), "jail");
+        let args = command.get_args().collect::<Vec<_>>();
+        assert_eq!(args, ["--quiet", "/srv/fezz.so"]);
+
+        let envs = command
+            .get_envs()
+            .map(|(name, _)| name.to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        assert!(envs.iter().any(|name| name == "DATABASE_URL"));
+        for name in &envs {
+            assert!(
+                name == "PATH" || name == "DATABASE_URL" || name.starts_with("FEZZ_"),
+                "{} leaked into the runner",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn empty_runner_is_rejected() {
+        assert!(runner_command("  ", "/srv/fezz.so", Vec::new()).is_err());
+    }
+
+    /// Runs `communicate` against a child that writes `len` bytes and exits.
+    async fn respond_with(len: usize, max_response_bytes: u64) -> Result<Vec<u8>, ExecError> {
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(format!("cat >/dev/null; head -c {} /dev/zero", len))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .unwrap();
+        let stdin = child.stdin.take().unwrap();
+        let stdout = child.stdout.take().unwrap();
+        let (_phase, shutdown) = watch::channel(Phase::Running);
+        let timeout = Duration::from_secs(10);
+        communicate(child, stdin, stdout, b"req".to_vec(), timeout, max_response_bytes, shutdown)
+            .await
+    }
+
+    #[tokio::test]
+    async fn runner_response_is_capped() {
+        assert_eq!(respond_with(64, 64).await.unwrap().len(), 64);
+        let err = respond_with(65, 64).await.unwrap_err();
+        assert!(matches!(err, ExecError::ResponseTooLarge(64)));
+        assert_eq!(err.status(), 502);
+    }
+}
@@ -0,0 +1,231 @@
+use crate::{config::FunctionLogsConfig, FunctionKey};
+use serde_json::Value;
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::OpenOptions,
+    io::Write,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Where a captured line came from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stream {
+    /// `fezz_sdk::log` in a native function, or `console` in a JS function.
+    Log,
+    /// Raw stdout/stderr of a runner child, e.g. `println!`.
+    Stdio,
+}
+
+impl Stream {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Stream::Log => "log",
+            Stream::Stdio => "stdio",
+        }
+    }
+}
+
+struct Line {
+    stream: Stream,
+    level: &'static str,
+    message: String,
+}
+
+#[derive(Default)]
+struct Buffer {
+    lines: Vec<Line>,
+    bytes: usize,
+    dropped: usize,
+}
+
+/// Output of one invocation, collected from every source before [`flush`].
+///
+/// Clones share the same buffer, so the runner's stderr reader can fill it
+/// while the handler waits for the response.
+#[derive(Clone)]
+pub struct Capture {
+    buffer: Arc<Mutex<Buffer>>,
+    max_line_bytes: usize,
+    max_bytes: usize,
+}
+
+impl Capture {
+    pub fn new(config: &FunctionLogsConfig) -> Self {
+        Self {
+            buffer: Arc::default(),
+            max_line_bytes: config.max_line_bytes,
+            max_bytes: config.max_bytes_per_invocation,
+        }
+    }
+
+    /// Records one line; long lines are cut, and lines past the
+    /// per-invocation budget are only counted.
+    pub fn push(&self, stream: Stream, level: &str, message: &str) {
+        let message = message.trim_end();
+        if message.is_empty() {
+            return;
+        }
+        let message = truncate(message, self.max_line_bytes);
+
+        let mut buffer = self.buffer.lock().unwrap();
+        if buffer.bytes + message.len() > self.max_bytes {
+            buffer.dropped += 1;
+            return;
+        }
+        buffer.bytes += message.len();
+        buffer.lines.push(Line {
+            stream,
+            level: normalize_level(level),
+            message,
+        });
+    }
+
+    fn take(&self) -> Buffer {
+        std::mem::take(&mut *self.buffer.lock().unwrap())
+    }
+}
+
+/// The last `function_logs.recent_lines` lines of every function, served by
+/// the admin API.
+#[derive(Default)]
+pub struct RecentLogs {
+    lines: Mutex<HashMap<FunctionKey, VecDeque<Value>>>,
+}
+
+impl RecentLogs {
+    /// Oldest first.
+    pub fn get(&self, key: &FunctionKey) -> Vec<Value> {
+        self.lines
+            .lock()
+            .unwrap()
+            .get(key)
+            .map(|lines| lines.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    fn record(&self, key: &FunctionKey, entries: &[Value], capacity: usize) {
+        if capacity == 0 {
+            return;
+        }
+        let mut lines = self.lines.lock().unwrap();
+        let recent = lines.entry(key.clone()).or_default();
+        for entry in entries {
+            if recent.len() == capacity {
+                recent.pop_front();
+            }
+            recent.push_back(entry.clone());
+        }
+    }
+}
+
+/// Emits the captured lines as `fezz_function` events in the current
+/// (request) span, keeps them in `recent` and, with `function_logs.dir` set,
+/// appends them as JSON lines to `{dir}/{org}/{func}/{version}.log`.
+pub fn flush(
+    config: &FunctionLogsConfig,
+    recent: &RecentLogs,
+    key: &FunctionKey,
+    trace_id: &str,
+    capture: &Capture,
+) {
+    let buffer = capture.take();
+    if buffer.dropped > 0 {
+        tracing::warn!(
+            target: "fezz_function",
+            "Dropped {} log lines over the limit of {} bytes per invocation",
+            buffer.dropped,
+            config.max_bytes_per_invocation
+        );
+    }
+    if buffer.lines.is_empty() {
+        return;
+    }
+
+    for line in &buffer.lines {
+        let stream = line.stream.as_str();
+        match line.level {
+            "error" => tracing::error!(target: "fezz_function", stream, "{}", line.message),
+            "warn" => tracing::warn!(target: "fezz_function", stream, "{}", line.message),
+            "debug" => tracing::debug!(target: "fezz_function", stream, "{}", line.message),
+            _ => tracing::info!(target: "fezz_function", stream, "{}", line.message),
+        }
+    }
+
+    let timestamp_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+    let entries = buffer
+        .lines
+        .iter()
+        .map(|line| {
+            serde_json::json!({
+                "timestamp_ms": timestamp_ms,
+                "org": key.org,
+                "func": key.func,
+                "version": key.version,
+                "trace_id": trace_id,
+                "stream": line.stream.as_str(),
+                "level": line.level,
+                "message": line.message,
+            })
+        })
+        .collect::<Vec<_>>();
+    recent.record(key, &entries, config.recent_lines);
+
+    let Some(dir) = &config.dir else {
+        return;
+    };
+    if !key.is_valid() {
+        return;
+    }
+    let mut contents = String::new();
+    for entry in &entries {
+        contents.push_str(&entry.to_string());
+        contents.push('\n');
+    }
+
+    let path = dir
+        .join(&key.org)
+        .join(&key.func)
+        .join(format!("{}.log", key.version));
+    tokio::task::spawn_blocking(move || {
+        if let Err(err) = append(&path, contents.as_bytes()) {
+            tracing::warn!("Failed to write function log '{}': {}", path.display(), err);
+        }
+    });
+}
+
+/// One `write` per invocation keeps concurrent invocations from interleaving.
+fn append(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?
+        .write_all(contents)
+}
+
+fn normalize_level(level: &str) -> &'static str {
+    match level.trim().to_ascii_lowercase().as_str() {
+        "error" => "error",
+        "warn" | "warning" => "warn",
+        "debug" | "trace" => "debug",
+        _ => "info",
+    }
+}
+
+fn truncate(message: &str, max_bytes: usize) -> String {
+    if message.len() <= max_bytes {
+        return message.to_string();
+    }
+    let mut end = max_bytes;
+    while !message.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}…", &message[..end])
+}
@@ -0,0 +1,312 @@
+use axum::http::{
+    header::{CONNECTION, CONTENT_LENGTH, HOST, TRANSFER_ENCODING},
+    HeaderMap, HeaderName, HeaderValue, StatusCode,
+};
+
+/// Connection-specific headers (RFC 9110 §7.6.1, plus the legacy
+/// `keep-alive` and `proxy-connection`). They describe one hop and never
+/// cross the HTTP ⇄ wire boundary in either direction.
+const HOP_BY_HOP: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// Refuses requests whose framing headers could be read differently by
+/// HHRF and a function (or anything behind it): conflicting or malformed
+/// `Content-Length`, `Content-Length` together with `Transfer-Encoding`,
+/// a `Transfer-Encoding` that doesn't end in `chunked`, and repeated `Host`.
+pub fn check_request(headers: &HeaderMap) -> Result<(), String> {
+    if headers.get_all(HOST).iter().count() > 1 {
+        return Err("Multiple Host headers".to_string());
+    }
+
+    let has_length = headers.contains_key(CONTENT_LENGTH);
+    if has_length && content_length(headers).is_none() {
+        return Err("Invalid or conflicting Content-Length headers".to_string());
+    }
+
+    let codings = headers
+        .get_all(TRANSFER_ENCODING)
+        .iter()
+        .map(|value| value.to_str())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| "Invalid Transfer-Encoding header".to_string())?;
+    if codings.is_empty() {
+        return Ok(());
+    }
+    if has_length {
+        return Err("Content-Length and Transfer-Encoding must not be combined".to_string());
+    }
+    let last = codings
+        .iter()
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .rev()
+        .find(|coding| !coding.is_empty());
+    if !last.is_some_and(|coding| coding.eq_ignore_ascii_case("chunked")) {
+        return Err("Transfer-Encoding must end with 'chunked'".to_string());
+    }
+    Ok(())
+}
+
+/// Headers handed to a function for a request whose body has been read in
+/// full: connection-specific headers are gone and `Content-Length` states
+/// the buffered body's size.
+pub fn sanitize_request(headers: &HeaderMap, body_len: usize) -> HeaderMap {
+    let mut sanitized = end_to_end(headers.iter());
+    if body_len > 0 || headers.contains_key(CONTENT_LENGTH) {
+        sanitized.insert(CONTENT_LENGTH, HeaderValue::from(body_len));
+    }
+    sanitized
+}
+
+/// Headers sent to the client for a function's response.
+///
+/// Invalid names and values are skipped, connection-specific headers are
+/// dropped and `Content-Length` is recomputed from the body, except for
+/// statuses without a body and for `HEAD`, where the function's own
+/// (single, valid) value describes the body it would have sent.
+pub fn sanitize_response<'a>(
+    headers: impl IntoIterator<Item = (&'a [u8], &'a [u8])>,
+    status: StatusCode,
+    head: bool,
+    body_len: usize,
+) -> HeaderMap {
+    let parsed = headers
+        .into_iter()
+        .filter_map(|(name, value)| {
+            let Ok(name) = HeaderName::from_bytes(name) else {
+                tracing::warn!("Skipping invalid header name");
+                return None;
+            };
+            let Ok(value) = HeaderValue::from_bytes(value) else {
+                tracing::warn!("Skipping invalid header value");
+                return None;
+            };
+            Some((name, value))
+        })
+        .collect::<HeaderMap>();
+
+    let mut sanitized = end_to_end(parsed.iter());
+    let declared = content_length(&parsed);
+    if status.is_informational()
+        || status == StatusCode::NO_CONTENT
+        || status == StatusCode::NOT_MODIFIED
+    {
+        return sanitized;
+    }
+    match declared {
+        Some(declared) if head && body_len == 0 => {
+            sanitized.insert(CONTENT_LENGTH, HeaderValue::from(declared));
+        }
+        _ => {
+            sanitized.insert(CONTENT_LENGTH, HeaderValue::from(body_len));
+        }
+    }
+    sanitized
+}
+
+/// Copies `headers` without hop-by-hop headers, headers named in
+/// `Connection` (except `Host`) and `Content-Length`, which callers set.
+fn end_to_end<'a>(
+    headers: impl Iterator<Item = (&'a HeaderName, &'a HeaderValue)>,
+) -> HeaderMap {
+    let headers = headers.collect::<Vec<_>>();
+    let nominated = headers
+        .iter()
+        .filter(|(name, _)| **name == CONNECTION)
+        .filter_map(|(_, value)| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|option| option.trim().to_ascii_lowercase())
+        .filter(|option| !option.is_empty() && option != HOST.as_str())
+        .collect::<Vec<_>>();
+
+    let mut kept = HeaderMap::new();
+    for (name, value) in headers {
+        let name_str = name.as_str();
+        if HOP_BY_HOP.contains(&name_str)
+            || *name == CONTENT_LENGTH
+            || nominated.iter().any(|option| option == name_str)
+        {
+            continue;
+        }
+        kept.append(name.clone(), value.clone());
+    }
+    kept
+}
+
+/// The `Content-Length`, if every value (and every comma-separated entry)
+/// is the same plain decimal number.
+fn content_length(headers: &HeaderMap) -> Option<u64> {
+    let mut length = None;
+    for value in headers.get_all(CONTENT_LENGTH) {
+        for entry in value.to_str().ok()?.split(',') {
+            let entry = entry.trim();
+            if entry.is_empty() || !entry.bytes().all(|b| b.is_ascii_digit()) {
+                return None;
+            }
+            let parsed = entry.parse::<u64>().ok()?;
+            if length.is_some_and(|length| length != parsed) {
+                return None;
+            }
+            length = Some(parsed);
+        }
+    }
+    length
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(headers: &[(&str, &str)]) -> HeaderMap {
+        let mut map = HeaderMap::new();
+        for (name, value) in headers {
+            map.append(
+                HeaderName::from_bytes(name.as_bytes()).unwrap(),
+                HeaderValue::from_str(value).unwrap(),
+            );
+        }
+        map
+    }
+
+    fn response(
+        headers: &[(&str, &str)],
+        status: StatusCode,
+        head: bool,
+        body_len: usize,
+    ) -> HeaderMap {
+        let headers = headers
+            .iter()
+            .map(|(name, value)| (name.as_bytes(), value.as_bytes()));
+        sanitize_response(headers, status, head, body_len)
+    }
+
+    #[test]
+    fn strips_hop_by_hop_and_connection_nominated_headers() {
+        let headers = request(&[
+            ("connection", "keep-alive, X-Internal, host"),
+            ("keep-alive", "timeout=5"),
+            ("x-internal", "secret"),
+            ("te", "trailers"),
+            ("upgrade", "websocket"),
+            ("host", "example.com"),
+            ("x-kept", "1"),
+        ]);
+        let sanitized = sanitize_request(&headers, 0);
+        assert!(sanitized.get("x-internal").is_none());
+        assert!(sanitized.get(CONNECTION).is_none());
+        assert!(sanitized.get("keep-alive").is_none());
+        assert!(sanitized.get("te").is_none());
+        assert!(sanitized.get("upgrade").is_none());
+        assert_eq!(sanitized.get(HOST).unwrap(), "example.com");
+        assert_eq!(sanitized.get("x-kept").unwrap(), "1");
+        assert!(sanitized.get(CONTENT_LENGTH).is_none());
+    }
+
+    #[test]
+    fn request_content_length_describes_buffered_body() {
+        let headers = request(&[("content-length", "3"), ("transfer-encoding", "chunked")]);
+        let sanitized = sanitize_request(&headers, 5);
+        assert_eq!(sanitized.get(CONTENT_LENGTH).unwrap(), "5");
+        assert!(sanitized.get(TRANSFER_ENCODING).is_none());
+    }
+
+    #[test]
+    fn accepts_repeated_identical_content_length() {
+        assert!(check_request(&request(&[("content-length", "5, 5")])).is_ok());
+        let repeated = request(&[("content-length", "5"), ("content-length", "5")]);
+        assert!(check_request(&repeated).is_ok());
+    }
+
+    #[test]
+    fn rejects_conflicting_or_malformed_content_length() {
+        let conflicting = request(&[("content-length", "5"), ("content-length", "6")]);
+        assert!(check_request(&conflicting).is_err());
+        assert!(check_request(&request(&[("content-length", "5, 6")])).is_err());
+        assert!(check_request(&request(&[("content-length", "+5")])).is_err());
+        assert!(check_request(&request(&[("content-length", "")])).is_err());
+        assert!(check_request(&request(&[("content-length", "0x10")])).is_err());
+    }
+
+    #[test]
+    fn rejects_content_length_with_transfer_encoding() {
+        let headers = request(&[("content-length", "5"), ("transfer-encoding", "chunked")]);
+        assert!(check_request(&headers).is_err());
+    }
+
+    #[test]
+    fn transfer_encoding_must_end_in_chunked() {
+        assert!(check_request(&request(&[("transfer-encoding", "chunked")])).is_ok());
+        assert!(check_request(&request(&[("transfer-encoding", "gzip, Chunked")])).is_ok());
+        let split = request(&[("transfer-encoding", "gzip"), ("transfer-encoding", "chunked")]);
+        assert!(check_request(&split).is_ok());
+        assert!(check_request(&request(&[("transfer-encoding", "gzip")])).is_err());
+        assert!(check_request(&request(&[("transfer-encoding", "chunked, gzip")])).is_err());
+        assert!(check_request(&request(&[("transfer-encoding", " , ")])).is_err());
+    }
+
+    #[test]
+    fn rejects_multiple_host_headers() {
+        let headers = request(&[("host", "a.example.com"), ("host", "b.example.com")]);
+        assert!(check_request(&headers).is_err());
+        assert!(check_request(&request(&[("host", "a.example.com")])).is_ok());
+    }
+
+    #[test]
+    fn response_content_length_is_recomputed_from_body() {
+        let headers = response(
+            &[("content-length", "999"), ("connection", "close")],
+            StatusCode::OK,
+            false,
+            12,
+        );
+        assert_eq!(headers.get(CONTENT_LENGTH).unwrap(), "12");
+        assert!(headers.get(CONNECTION).is_none());
+    }
+
+    #[test]
+    fn head_response_keeps_declared_content_length() {
+        let headers = response(&[("content-length", "42")], StatusCode::OK, true, 0);
+        assert_eq!(headers.get(CONTENT_LENGTH).unwrap(), "42");
+
+        let conflicting = response(
+            &[("content-length", "42"), ("content-length", "43")],
+            StatusCode::OK,
+            true,
+            0,
+        );
+        assert_eq!(conflicting.get(CONTENT_LENGTH).unwrap(), "0");
+    }
+
+    #[test]
+    fn bodiless_statuses_get_no_content_length() {
+        for status in [StatusCode::NO_CONTENT, StatusCode::NOT_MODIFIED] {
+            let headers = response(&[("content-length", "42")], status, false, 0);
+            assert!(headers.get(CONTENT_LENGTH).is_none(), "{}", status);
+        }
+    }
+
+    #[test]
+    fn skips_invalid_response_headers() {
+        let headers = sanitize_response(
+            [
+                (&b"bad name"[..], &b"value"[..]),
+                (&b"x-bad-value"[..], &b"line\nbreak"[..]),
+                (&b"x-ok"[..], &b"yes"[..]),
+            ],
+            StatusCode::OK,
+            false,
+            0,
+        );
+        assert!(headers.get("x-bad-value").is_none());
+        assert_eq!(headers.get("x-ok").unwrap(), "yes");
+    }
+}
@@ -0,0 +1,187 @@
+use crate::{
+    exec::{self, ExecBackend},
+    load_env_vars,
+    settings::FunctionSettings,
+    shutdown::Phase,
+    versions, AppState, FunctionKey,
+};
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use fezz_js::JsHealth;
+use fezz_sdk::FezzWireHealth;
+use serde::Serialize;
+use std::{sync::Arc, time::Duration};
+
+/// One readiness check as reported by the readiness endpoint.
+#[derive(Debug, Serialize)]
+struct Check {
+    name: String,
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl Check {
+    fn new(name: impl Into<String>, result: Result<(), String>) -> Self {
+        Self {
+            name: name.into(),
+            ok: result.is_ok(),
+            error: result.err(),
+        }
+    }
+}
+
+/// `200` for as long as the process answers at all.
+pub async fn liveness() -> Response {
+    Json(serde_json::json!({ "status": "ok" })).into_response()
+}
+
+/// `200` when HHRF is not draining, the functions root is readable and every
+/// `health.functions` entry loads and passes its health hook; `503` with the
+/// failed checks otherwise.
+pub async fn readiness(state: Arc<AppState>) -> Response {
+    let config = state.config();
+    let timeout = Duration::from_millis(config.health.timeout_ms);
+
+    let running = *state.shutdown.subscribe().borrow() == Phase::Running;
+    let mut checks = vec![Check::new(
+        "shutdown",
+        if running {
+            Ok(())
+        } else {
+            Err("Server is shutting down".to_string())
+        },
+    )];
+
+    let functions_dir = format!("{}/functions", config.server.root);
+    let root = tokio::fs::read_dir(&functions_dir)
+        .await
+        .map(|_| ())
+        .map_err(|e| format!("Failed to read '{}': {}", functions_dir, e));
+    checks.push(Check::new("functions_root", root));
+
+    let probes = config
+        .health
+        .functions
+        .iter()
+        .map(|function| {
+            let state = state.clone();
+            let function = function.clone();
+            tokio::spawn(async move {
+                let mut segments = function.splitn(3, '/');
+                let (org, func, version) = (
+                    segments.next().unwrap_or_default(),
+                    segments.next().unwrap_or_default(),
+                    segments.next().unwrap_or_default(),
+                );
+                let result = probe(&state, org, func, version, timeout).await;
+                Check::new(function.as_str(), result)
+            })
+        })
+        .collect::<Vec<_>>();
+    for probe in probes {
+        checks.push(match probe.await {
+            Ok(check) => check,
+            Err(err) => Check::new("function", Err(format!("Check task failed: {}", err))),
+        });
+    }
+
+    let ready = checks.iter().all(|check| check.ok);
+    for check in checks.iter().filter(|check| !check.ok) {
+        tracing::warn!(
+            "Readiness check '{}' failed: {}",
+            check.name,
+            check.error.as_deref().unwrap_or_default()
+        );
+    }
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    let body = serde_json::json!({
+        "status": if ready { "ready" } else { "not_ready" },
+        "checks": checks,
+    });
+    (status, Json(body)).into_response()
+}
+
+/// Resolves `version` (aliases and `latest` included) and checks that the
+/// function is usable: its library loads with `fezz_handle_v2` and
+/// `fezz_free_v2` (or its `fezz.js` evaluates) and its optional health hook,
+/// `fezz_health_v1` or a JS `health` export, reports healthy.
+pub async fn probe(
+    state: &Arc<AppState>,
+    org: &str,
+    func: &str,
+    version: &str,
+    timeout: Duration,
+) -> Result<(), String> {
+    let config = state.config();
+    let requested = FunctionKey {
+        org: org.to_string(),
+        func: func.to_string(),
+        version: version.to_string(),
+    };
+    if !requested.is_valid() {
+        return Err(format!("Invalid function path '{}'", requested));
+    }
+    let key = FunctionKey {
+        version: versions::resolve(&config.server.root, &config, org, func, version)?,
+        ..requested
+    };
+    if !key.is_valid() {
+        return Err(format!("Invalid function path '{}'", key));
+    }
+    let settings = FunctionSettings::resolve(&config, &key);
+    let function_root = format!(
+        "{}/functions/{}/{}/{}",
+        config.server.root, key.org, key.func, key.version
+    );
+    let env_vars = load_env_vars(&format!("{function_root}/.env"));
+    let so_path = format!("{function_root}/fezz.so");
+
+    match settings.backend {
+        ExecBackend::Js => {
+            let script_path = format!("{function_root}/fezz.js");
+            let health = state
+                .js_runtime_manager
+                .health(&script_path, env_vars, timeout)
+                .await
+                .map_err(|e| e.to_string())?;
+            match health {
+                JsHealth::Missing | JsHealth::Healthy => Ok(()),
+                JsHealth::Unhealthy(message) => Err(message),
+            }
+        }
+        ExecBackend::InProcess => {
+            // The env travels in the check, as it does in request bytes.
+            let check_bytes = exec::health_check_bytes(&env_vars)?;
+            let library_cache = state.library_cache.clone();
+            let checked = tokio::task::spawn_blocking(move || {
+                let library = library_cache.get(&key, &so_path)?;
+                match unsafe { library.health(&check_bytes) } {
+                    Some(health) => verdict(health?),
+                    None => Ok(()),
+                }
+            });
+            match tokio::time::timeout(timeout, checked).await {
+                Ok(joined) => joined.map_err(|e| format!("Failed to join health check: {}", e))?,
+                Err(_) => Err(format!("Health check timed out after {:?}", timeout)),
+            }
+        }
+        ExecBackend::Runner => verdict(exec::health_in_runner(&so_path, env_vars, timeout).await?),
+    }
+}
+
+fn verdict(health: FezzWireHealth) -> Result<(), String> {
+    if health.healthy {
+        return Ok(());
+    }
+    Err(health
+        .message
+        .unwrap_or_else(|| "fezz_health_v1 reported unhealthy".to_string()))
+}
@@ -0,0 +1,528 @@
+use crate::{exec::ExecError, FunctionKey};
+use fezz_sdk::{FezzOwned, FezzSlice, FezzWireHealth};
+use libloading::{Library, Symbol};
+use object::{Object, ObjectKind};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path as FsPath, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant, SystemTime},
+};
+
+type FezzHandleV2Fn = unsafe extern "C" fn(FezzSlice) -> FezzOwned;
+type FezzFreeV2Fn = unsafe extern "C" fn(FezzOwned);
+type FezzHealthV1Fn = unsafe extern "C" fn(FezzSlice) -> FezzOwned;
+
+/// A `fezz.so` kept resident between requests.
+///
+/// The library is loaded from a private shadow copy so that replacing the
+/// deployed file (even in place) never aliases the already-mapped image.
+pub struct CachedLibrary {
+    fezz_handle_v2: FezzHandleV2Fn,
+    fezz_free_v2: FezzFreeV2Fn,
+    /// Optional self-check, see [`CachedLibrary::health`].
+    fezz_health_v1: Option<FezzHealthV1Fn>,
+    // Keeps the symbols above valid; dropped after the shadow file is unlinked.
+    _library: Library,
+    shadow_path: PathBuf,
+    hash: String,
+    stat: Mutex<(SystemTime, u64)>,
+    last_used: Mutex<Instant>,
+}
+
+impl CachedLibrary {
+    /// Calls `fezz_handle_v2` and copies the response out before `fezz_free_v2`.
+    ///
+    /// Responses over `max_response_bytes` are freed without being copied.
+    ///
+    /// # Safety
+    ///
+    /// The library must honour the V2 ABI contract.
+    pub unsafe fn invoke(
+        &self,
+        req_bytes: &[u8],
+        max_response_bytes: u64,
+    ) -> Result<Vec<u8>, ExecError> {
+        let owned = (self.fezz_handle_v2)(FezzSlice {
+            ptr: req_bytes.as_ptr(),
+            len: req_bytes.len(),
+        });
+
+        if owned.ptr.is_null() && owned.len != 0 {
+            return Err(ExecError::Failed(
+                "fezz_handle_v2 returned null pointer".to_string(),
+            ));
+        }
+
+        if owned.len as u64 > max_response_bytes {
+            (self.fezz_free_v2)(owned);
+            return Err(ExecError::ResponseTooLarge(max_response_bytes));
+        }
+
+        let resp_bytes = if owned.len == 0 {
+            Vec::new()
+        } else {
+            std::slice::from_raw_parts(owned.ptr, owned.len).to_vec()
+        };
+
+        (self.fezz_free_v2)(owned);
+
+        Ok(resp_bytes)
+    }
+
+    /// Calls `fezz_health_v1` with the CBOR `FezzWireHealthCheck` in
+    /// `check_bytes`, if the library exports it.
+    ///
+    /// # Safety
+    ///
+    /// The library must honour the V2 ABI contract.
+    pub unsafe fn health(&self, check_bytes: &[u8]) -> Option<Result<FezzWireHealth, String>> {
+        let fezz_health_v1 = self.fezz_health_v1?;
+        let owned = fezz_health_v1(FezzSlice {
+            ptr: check_bytes.as_ptr(),
+            len: check_bytes.len(),
+        });
+        if owned.ptr.is_null() {
+            return Some(Err("fezz_health_v1 returned null pointer".to_string()));
+        }
+        let health_bytes = std::slice::from_raw_parts(owned.ptr, owned.len).to_vec();
+        (self.fezz_free_v2)(owned);
+        Some(
+            fezz_sdk::decode_health(&health_bytes)
+                .map_err(|e| format!("Invalid fezz_health_v1 response: {}", e)),
+        )
+    }
+
+    fn touch(&self) {
+        *self.last_used.lock().unwrap() = Instant::now();
+    }
+
+    fn idle_for(&self) -> Duration {
+        self.last_used.lock().unwrap().elapsed()
+    }
+}
+
+impl Drop for CachedLibrary {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.shadow_path);
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    pub resident: usize,
+}
+
+/// In-memory cache of loaded native libraries keyed by org/func/version.
+///
+/// Entries are invalidated when the artifact's mtime/size changes and its
+/// SHA-256 differs, evicted after `ttl` of inactivity, and capped at
+/// `max_entries` (least recently used goes first).
+pub struct LibraryCache {
+    entries: Mutex<HashMap<FunctionKey, Arc<CachedLibrary>>>,
+    ttl: Duration,
+    max_entries: usize,
+    shadow_dir: PathBuf,
+    shadow_seq: AtomicU64,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+}
+
+impl LibraryCache {
+    pub fn new(ttl: Duration, max_entries: usize) -> Self {
+        let shadow_dir = std::env::temp_dir().join(format!("hhrf-libs-{}", std::process::id()));
+        if let Err(err) = fs::create_dir_all(&shadow_dir) {
+            tracing::error!(
+                "Failed to create library shadow dir '{}': {}",
+                shadow_dir.display(),
+                err
+            );
+        }
+
+        Self {
+            entries: Mutex::new(HashMap::new()),
+            ttl,
+            max_entries: max_entries.max(1),
+            shadow_dir,
+            shadow_seq: AtomicU64::new(0),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+        }
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    /// Returns the resident library for `key`, loading or reloading it if needed.
+    ///
+    /// Blocking: stats, hashes and `dlopen`s on a miss. Call from the blocking pool.
+    pub fn get(&self, key: &FunctionKey, so_path: &str) -> Result<Arc<CachedLibrary>, String> {
+        self.get_with(key, so_path, |shadow_path| unsafe { load_symbols(shadow_path) })
+    }
+
+    /// [`LibraryCache::get`] with the `dlopen` step passed in, so the
+    /// invalidation and eviction bookkeeping can be tested without a library.
+    fn get_with(
+        &self,
+        key: &FunctionKey,
+        so_path: &str,
+        load_symbols: impl FnOnce(&FsPath) -> Result<Symbols, String>,
+    ) -> Result<Arc<CachedLibrary>, String> {
+        let metadata = match fs::metadata(so_path) {
+            Ok(metadata) => metadata,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                self.remove(key);
+                return Err(format!("Library not found at {}", so_path));
+            }
+            Err(err) => return Err(format!("Failed to stat '{}': {}", so_path, err)),
+        };
+        let stat = (
+            metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+            metadata.len(),
+        );
+
+        let existing = self.entries.lock().unwrap().get(key).cloned();
+        let mut known_hash = None;
+        if let Some(entry) = existing {
+            if *entry.stat.lock().unwrap() == stat {
+                return Ok(self.hit(key, entry));
+            }
+
+            // mtime or size changed: only reload if the content really differs.
+            let hash = hash_file(so_path)?;
+            if hash == entry.hash {
+                *entry.stat.lock().unwrap() = stat;
+                return Ok(self.hit(key, entry));
+            }
+            tracing::info!("Library for '{}' changed on disk, reloading", key);
+            known_hash = Some(hash);
+        }
+
+        let misses = self.misses.fetch_add(1, Ordering::Relaxed) + 1;
+        let load_start = Instant::now();
+        let entry = Arc::new(self.load(key, so_path, stat, known_hash, load_symbols)?);
+        tracing::info!(
+            "Library cache miss for '{}' (loaded in {:?}, hits={}, misses={})",
+            key,
+            load_start.elapsed(),
+            self.hits.load(Ordering::Relaxed),
+            misses
+        );
+
+        let mut entries = self.entries.lock().unwrap();
+        entries.insert(key.clone(), entry.clone());
+        while entries.len() > self.max_entries {
+            let lru = entries
+                .iter()
+                .filter(|(other, _)| *other != key)
+                .max_by_key(|(_, other)| other.idle_for())
+                .map(|(other, _)| other.clone());
+            match lru {
+                Some(lru) => {
+                    entries.remove(&lru);
+                    self.evictions.fetch_add(1, Ordering::Relaxed);
+                    tracing::info!("Library cache full, evicted '{}'", lru);
+                }
+                None => break,
+            }
+        }
+
+        Ok(entry)
+    }
+
+    /// Drops entries idle for longer than the TTL. In-flight calls keep their
+    /// `Arc` so the library is only unloaded once they finish.
+    pub fn evict_expired(&self) -> usize {
+        let mut entries = self.entries.lock().unwrap();
+        let before = entries.len();
+        entries.retain(|_, entry| entry.idle_for() < self.ttl);
+        let evicted = before - entries.len();
+        self.evictions.fetch_add(evicted as u64, Ordering::Relaxed);
+        evicted
+    }
+
+    /// Drops every entry, e.g. on request from the admin API.
+    pub fn clear(&self) -> usize {
+        let mut entries = self.entries.lock().unwrap();
+        let evicted = entries.len();
+        entries.clear();
+        self.evictions.fetch_add(evicted as u64, Ordering::Relaxed);
+        evicted
+    }
+
+    pub fn contains(&self, key: &FunctionKey) -> bool {
+        self.entries.lock().unwrap().contains_key(key)
+    }
+
+    pub fn remove(&self, key: &FunctionKey) -> bool {
+        let removed = self.entries.lock().unwrap().remove(key).is_some();
+        if removed {
+            self.evictions.fetch_add(1, Ordering::Relaxed);
+        }
+        removed
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            resident: self.entries.lock().unwrap().len(),
+        }
+    }
+
+    fn hit(&self, key: &FunctionKey, entry: Arc<CachedLibrary>) -> Arc<CachedLibrary> {
+        entry.touch();
+        let hits = self.hits.fetch_add(1, Ordering::Relaxed) + 1;
+        tracing::debug!(
+            "Library cache hit for '{}' (hits={}, misses={})",
+            key,
+            hits,
+            self.misses.load(Ordering::Relaxed)
+        );
+        entry
+    }
+
+    fn load(
+        &self,
+        key: &FunctionKey,
+        so_path: &str,
+        stat: (SystemTime, u64),
+        known_hash: Option<String>,
+        load_symbols: impl FnOnce(&FsPath) -> Result<Symbols, String>,
+    ) -> Result<CachedLibrary, String> {
+        let hash = match known_hash {
+            Some(hash) => hash,
+            None => hash_file(so_path)?,
+        };
+        let seq = self.shadow_seq.fetch_add(1, Ordering::Relaxed);
+        let shadow_path = self.shadow_dir.join(format!(
+            "{}_{}_{}_{}_{}.so",
+            key.org,
+            key.func,
+            key.version,
+            &hash[..16],
+            seq
+        ));
+        fs::copy(so_path, &shadow_path).map_err(|e| {
+            format!(
+                "Failed to copy '{}' to '{}': {}",
+                so_path,
+                shadow_path.display(),
+                e
+            )
+        })?;
+
+        let loaded = load_symbols(&shadow_path);
+        let (library, fezz_handle_v2, fezz_free_v2, fezz_health_v1) = match loaded {
+            Ok(loaded) => loaded,
+            Err(err) => {
+                let _ = fs::remove_file(&shadow_path);
+                return Err(err.replace(&shadow_path.display().to_string(), so_path));
+            }
+        };
+
+        Ok(CachedLibrary {
+            fezz_handle_v2,
+            fezz_free_v2,
+            fezz_health_v1,
+            _library: library,
+            shadow_path,
+            hash,
+            stat: Mutex::new(stat),
+            last_used: Mutex::new(Instant::now()),
+        })
+    }
+}
+
+type Symbols = (Library, FezzHandleV2Fn, FezzFreeV2Fn, Option<FezzHealthV1Fn>);
+
+unsafe fn load_symbols(path: &FsPath) -> Result<Symbols, String> {
+    let library = Library::new(path)
+        .map_err(|e| format!("Failed to load library '{}': {}", path.display(), e))?;
+
+    let fezz_handle_v2: Symbol<FezzHandleV2Fn> = library
+        .get(b"fezz_handle_v2")
+        .map_err(|e| format!("Failed to resolve fezz_handle_v2: {}", e))?;
+    let fezz_handle_v2 = *fezz_handle_v2;
+
+    let fezz_free_v2: Symbol<FezzFreeV2Fn> = library
+        .get(b"fezz_free_v2")
+        .map_err(|e| format!("Failed to resolve fezz_free_v2: {}", e))?;
+    let fezz_free_v2 = *fezz_free_v2;
+
+    let fezz_health_v1 = library
+        .get::<FezzHealthV1Fn>(b"fezz_health_v1")
+        .ok()
+        .map(|symbol| *symbol);
+
+    Ok((library, fezz_handle_v2, fezz_free_v2, fezz_health_v1))
+}
+
+/// Checks from its dynamic symbol table, without loading it, that `path` is
+/// a shared library exporting `fezz_handle_v2` and `fezz_free_v2`. Nothing
+/// in the library runs, not even its initializers.
+pub fn check_exports(path: &str) -> Result<(), String> {
+    let bytes = fs::read(path).map_err(|e| format!("Failed to read '{}': {}", path, e))?;
+    let file = object::File::parse(&*bytes)
+        .map_err(|e| format!("Failed to parse library '{}': {}", path, e))?;
+    if file.kind() != ObjectKind::Dynamic {
+        return Err(format!("'{}' is not a shared library", path));
+    }
+    let exports = file
+        .exports()
+        .map_err(|e| format!("Failed to read exports of '{}': {}", path, e))?;
+    for symbol in ["fezz_handle_v2", "fezz_free_v2"] {
+        // Mach-O prefixes C symbols with an underscore.
+        let exported = exports.iter().any(|export| {
+            let name = export.name();
+            name == symbol.as_bytes() || name.strip_prefix(b"_") == Some(symbol.as_bytes())
+        });
+        if !exported {
+            return Err(format!("Missing symbol {}", symbol));
+        }
+    }
+    Ok(())
+}
+
+/// Hex-encoded SHA-256 of a file.
+pub fn hash_file(path: &str) -> Result<String, String> {
+    let bytes = fs::read(path).map_err(|e| format!("Failed to read '{}': {}", path, e))?;
+    let digest = Sha256::digest(&bytes);
+    Ok(digest.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+
+    unsafe extern "C" fn handle(_: FezzSlice) -> FezzOwned {
+        FezzOwned {
+            ptr: std::ptr::null_mut(),
+            len: 0,
+        }
+    }
+
+    unsafe extern "C" fn free(_: FezzOwned) {}
+
+    /// Stands in for `dlopen`: the process itself is the "library".
+    fn fake_symbols(_: &FsPath) -> Result<Symbols, String> {
+        let library = libloading::os::unix::Library::this().into();
+        Ok((library, handle as FezzHandleV2Fn, free as FezzFreeV2Fn, None))
+    }
+
+    fn key(func: &str) -> FunctionKey {
+        FunctionKey {
+            org: "acme".to_string(),
+            func: func.to_string(),
+            version: "0.1.0".to_string(),
+        }
+    }
+
+    fn get(cache: &LibraryCache, key: &FunctionKey, path: &FsPath) -> Arc<CachedLibrary> {
+        cache.get_with(key, path.to_str().unwrap(), fake_symbols).unwrap()
+    }
+
+    fn set_mtime(path: &FsPath, mtime: SystemTime) {
+        File::options().write(true).open(path).unwrap().set_modified(mtime).unwrap();
+    }
+
+    #[test]
+    fn counts_hits_and_misses() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("fezz.so");
+        fs::write(&path, b"v1").unwrap();
+        let cache = LibraryCache::new(Duration::from_secs(60), 8);
+
+        let first = get(&cache, &key("stats"), &path);
+        let second = get(&cache, &key("stats"), &path);
+        assert!(Arc::ptr_eq(&first, &second));
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.resident), (1, 1, 1));
+    }
+
+    #[test]
+    fn reloads_only_when_the_content_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("fezz.so");
+        fs::write(&path, b"v1").unwrap();
+        let cache = LibraryCache::new(Duration::from_secs(60), 8);
+        let original = get(&cache, &key("reload"), &path);
+
+        // New mtime, same bytes: the hash matches and the entry stays.
+        set_mtime(&path, SystemTime::now() + Duration::from_secs(10));
+        assert!(Arc::ptr_eq(&original, &get(&cache, &key("reload"), &path)));
+        assert_eq!(cache.stats().misses, 1);
+
+        fs::write(&path, b"v2").unwrap();
+        set_mtime(&path, SystemTime::now() + Duration::from_secs(20));
+        let reloaded = get(&cache, &key("reload"), &path);
+        assert!(!Arc::ptr_eq(&original, &reloaded));
+        assert_ne!(original.hash, reloaded.hash);
+        assert_eq!(cache.stats().misses, 2);
+
+        fs::remove_file(&path).unwrap();
+        let missing = cache.get_with(&key("reload"), path.to_str().unwrap(), fake_symbols);
+        assert!(missing.is_err());
+        assert!(!cache.contains(&key("reload")));
+    }
+
+    #[test]
+    fn evicts_entries_idle_past_the_ttl() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("fezz.so");
+        fs::write(&path, b"v1").unwrap();
+
+        let cache = LibraryCache::new(Duration::from_secs(3600), 8);
+        get(&cache, &key("ttl"), &path);
+        assert_eq!(cache.evict_expired(), 0);
+
+        let cache = LibraryCache::new(Duration::ZERO, 8);
+        get(&cache, &key("ttl"), &path);
+        assert_eq!(cache.evict_expired(), 1);
+        let stats = cache.stats();
+        assert_eq!((stats.evictions, stats.resident), (1, 0));
+    }
+
+    #[test]
+    fn evicts_the_least_recently_used_over_the_cap() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("fezz.so");
+        fs::write(&path, b"v1").unwrap();
+        let cache = LibraryCache::new(Duration::from_secs(60), 2);
+
+        for func in ["lru-a", "lru-b", "lru-a", "lru-c"] {
+            get(&cache, &key(func), &path);
+            std::thread::sleep(Duration::from_millis(2));
+        }
+        assert!(cache.contains(&key("lru-a")));
+        assert!(!cache.contains(&key("lru-b")));
+        assert!(cache.contains(&key("lru-c")));
+        assert_eq!(cache.stats().evictions, 1);
+    }
+
+    #[test]
+    fn check_exports_rejects_non_libraries() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("fezz.so");
+        fs::write(&path, b"#!/bin/sh\necho hi\n").unwrap();
+        let path = path.to_str().unwrap();
+        assert!(check_exports(path).unwrap_err().contains("Failed to parse library"));
+
+        let missing = dir.path().join("missing.so");
+        let missing = missing.to_str().unwrap();
+        assert!(check_exports(missing).unwrap_err().contains("Failed to read"));
+    }
+}
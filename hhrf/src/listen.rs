@@ -0,0 +1,121 @@
+use crate::config::Config;
+use std::{
+    net::SocketAddr,
+    os::fd::{FromRawFd, RawFd},
+};
+use tokio::net::{TcpListener, TcpSocket};
+
+/// First file descriptor passed by systemd socket activation.
+const SD_LISTEN_FDS_START: RawFd = 3;
+
+/// `LISTEN_FDNAMES` entries (`FileDescriptorName=` in the socket unit) that
+/// mark a socket for the admin API or for HTTPS.
+const ADMIN_FD_NAME: &str = "admin";
+const TLS_FD_NAME: &str = "https";
+
+const LISTEN_BACKLOG: u32 = 1024;
+
+pub struct Listeners {
+    pub server: Vec<TcpListener>,
+    pub tls: Vec<TcpListener>,
+    pub admin: Vec<TcpListener>,
+}
+
+/// Opens the function and admin listeners.
+///
+/// Sockets inherited through systemd socket activation (`LISTEN_FDS`) take
+/// the place of `server.listen` (and of `tls.listen` or `admin.listen` for
+/// sockets named `https` or `admin`), so connections queue in the kernel
+/// while HHRF restarts.
+/// Otherwise the configured addresses are bound, with `SO_REUSEPORT` when
+/// `server.reuse_port` is set so a new process can bind them before the old
+/// one has drained.
+pub fn open(config: &Config) -> Result<Listeners, String> {
+    let (mut server, mut tls, mut admin) = (Vec::new(), Vec::new(), Vec::new());
+    for (name, listener) in inherited()? {
+        match name.as_str() {
+            ADMIN_FD_NAME => admin.push(listener),
+            TLS_FD_NAME => tls.push(listener),
+            _ => server.push(listener),
+        }
+    }
+
+    if server.is_empty() {
+        for addr in config.listen_addrs() {
+            server.push(bind(addr, config.server.reuse_port)?);
+        }
+    }
+    if tls.is_empty() {
+        for addr in config.tls_listen_addrs() {
+            tls.push(bind(addr, config.server.reuse_port)?);
+        }
+    }
+    if admin.is_empty() {
+        for addr in config.admin_listen_addrs() {
+            admin.push(bind(addr, config.server.reuse_port)?);
+        }
+    }
+    Ok(Listeners { server, tls, admin })
+}
+
+fn bind(addr: SocketAddr, reuse_port: bool) -> Result<TcpListener, String> {
+    let socket = if addr.is_ipv4() {
+        TcpSocket::new_v4()
+    } else {
+        TcpSocket::new_v6()
+    }
+    .map_err(|e| format!("Failed to create socket for {}: {}", addr, e))?;
+    socket
+        .set_reuseaddr(true)
+        .and_then(|()| if reuse_port { socket.set_reuseport(true) } else { Ok(()) })
+        .and_then(|()| socket.bind(addr))
+        .map_err(|e| format!("Failed to bind {}: {}", addr, e))?;
+    socket
+        .listen(LISTEN_BACKLOG)
+        .map_err(|e| format!("Failed to listen on {}: {}", addr, e))
+}
+
+/// Takes the sockets systemd passed to this process, with their names.
+///
+/// The variables are only read: mutating the environment is unsound once the
+/// runtime's threads run. Runners start from an allowlisted environment and
+/// never see them.
+fn inherited() -> Result<Vec<(String, TcpListener)>, String> {
+    let for_us = std::env::var("LISTEN_PID")
+        .ok()
+        .and_then(|pid| pid.parse::<u32>().ok())
+        .is_some_and(|pid| pid == std::process::id());
+    if !for_us {
+        return Ok(Vec::new());
+    }
+    let count = std::env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|count| count.parse::<RawFd>().ok())
+        .unwrap_or(0);
+    let names = std::env::var("LISTEN_FDNAMES").unwrap_or_default();
+    let mut names = names.split(':');
+
+    let mut listeners = Vec::new();
+    for fd in SD_LISTEN_FDS_START..SD_LISTEN_FDS_START + count {
+        let name = names.next().unwrap_or_default().to_string();
+        // systemd passes the sockets without FD_CLOEXEC; runner children
+        // (tenant code) must not inherit them and accept() on them.
+        if unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) } < 0 {
+            return Err(format!(
+                "Failed to set FD_CLOEXEC on inherited socket {}: {}",
+                fd,
+                std::io::Error::last_os_error()
+            ));
+        }
+        // Ownership moves to us; systemd keeps its own copy for the next start.
+        let listener = unsafe { std::net::TcpListener::from_raw_fd(fd) };
+        listener
+            .set_nonblocking(true)
+            .map_err(|e| format!("Inherited socket {} is unusable: {}", fd, e))?;
+        let listener = TcpListener::from_std(listener)
+            .map_err(|e| format!("Inherited socket {} is unusable: {}", fd, e))?;
+        tracing::info!("Using inherited socket {} ({})", fd, name);
+        listeners.push((name, listener));
+    }
+    Ok(listeners)
+}
@@ -1,105 +1,684 @@
+use auth::Authenticator;
 use axum::{
+    body::Bytes,
     extract::Path,
-    http::{HeaderName, HeaderValue, Request},
-    routing::any,
+    http::{
+        header::{ACCEPT_ENCODING, CONTENT_TYPE, ORIGIN},
+        request::Parts,
+        HeaderValue, Method, Request, StatusCode, Uri,
+    },
+    routing::{any, get},
     Router,
 };
-use fezz_js::{JsInvoke, JsKey, JsRuntimeManager};
+use fezz_js::{JsInvoke, JsKey, JsLogs, JsRuntimeManager, JsTimeout};
 use fezz_sdk::{ByteBuf, FezzWireHeader, FezzWireRequest, FezzWireResponse};
-use http_body_util::BodyExt;
-use libloading::{Library, Symbol};
+use clap::Parser;
+use cache::ResponseCache;
+use concurrency::ConcurrencyLimiter;
+use config::{Cli, Config, RateLimitScope};
+use exec::{ExecBackend, ExecError};
+use function_logs::{Capture, RecentLogs, Stream};
+use ipnet::IpNet;
+use library_cache::LibraryCache;
+use metrics::{Metrics, Phase};
+use rate_limit::RateLimiter;
+use request_meta::RequestMeta;
+use settings::FunctionSettings;
+use shutdown::Shutdown;
 use std::{
-    path::Path as FsPath,
-    sync::Arc,
-    time::Instant,
+    fmt,
+    net::SocketAddr,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
 };
-use tokio::net::TcpListener;
+use tls::CertResolver;
+use tokio::task::JoinSet;
+use tokio_rustls::TlsAcceptor;
+use traffic::{FunctionStatus, TrafficSplitter};
+use tracing::Instrument;
+
+mod admin;
+mod auth;
+mod body;
+mod cache;
+mod compression;
+mod concurrency;
+mod config;
+mod cors;
+mod discovery;
+mod exec;
+mod function_logs;
+mod headers;
+mod health;
+mod library_cache;
+mod listen;
+mod metrics;
+mod rate_limit;
+mod reload;
+mod request_meta;
+mod routes;
+mod settings;
+mod shutdown;
+mod telemetry;
+mod tls;
+mod traffic;
+mod versions;
+
+/// How long aborted requests get to send their 503 before the process exits.
+const ABORT_GRACE: Duration = Duration::from_secs(2);
+
+/// Identifies one deployed function version (`functions/{org}/{func}/{version}`).
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct FunctionKey {
+    pub org: String,
+    pub func: String,
+    pub version: String,
+}
+
+impl FunctionKey {
+    /// Whether every part is usable as a single path segment.
+    pub fn is_valid(&self) -> bool {
+        [&self.org, &self.func, &self.version].iter().all(|segment| {
+            !segment.is_empty()
+                && segment.as_str() != "."
+                && segment.as_str() != ".."
+                && !segment.contains(['/', '\\'])
+        })
+    }
+
+    /// Path and query the function sees for `uri`, served under
+    /// `/{route}/{org}/{func}/{version}`: the prefix is dropped and what
+    /// remains always starts with `/`.
+    pub fn function_path(&self, route: &str, uri: &Uri) -> String {
+        let prefix = format!("/{route}/{}/{}/{}", self.org, self.func, self.version);
+        let path = uri.path().strip_prefix(&prefix).unwrap_or(uri.path());
+        let path = if path.starts_with('/') {
+            path.to_string()
+        } else {
+            format!("/{path}")
+        };
+        match uri.query() {
+            Some(query) => format!("{path}?{query}"),
+            None => path,
+        }
+    }
+}
+
+impl fmt::Display for FunctionKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}/{}", self.org, self.func, self.version)
+    }
+}
+
+/// State shared by all route handlers.
+pub struct AppState {
+    /// Swapped wholesale on reload; handlers take a snapshot per request.
+    pub config: RwLock<Arc<Config>>,
+    /// Re-applied on top of the file on every reload.
+    pub cli: Cli,
+    pub js_runtime_manager: JsRuntimeManager,
+    pub library_cache: Arc<LibraryCache>,
+    pub concurrency: ConcurrencyLimiter,
+    pub rate_limiter: RateLimiter,
+    pub authenticator: Authenticator,
+    pub metrics: Metrics,
+    /// Latest function log lines, served by the admin API.
+    pub recent_logs: RecentLogs,
+    pub traffic: TrafficSplitter,
+    pub shutdown: Shutdown,
+    pub tls: Arc<CertResolver>,
+    pub response_cache: ResponseCache,
+    /// Peers whose `X-Forwarded-For` is trusted when resolving the client IP.
+    pub trusted_proxies: Vec<IpNet>,
+}
+
+impl AppState {
+    pub fn config(&self) -> Arc<Config> {
+        self.config.read().unwrap().clone()
+    }
+
+    pub fn root(&self) -> String {
+        self.config().server.root.clone()
+    }
+}
 
 #[tokio::main]
 async fn main() {
-    // HHRF_ROOT env'den gelsin
-    let root = std::env::var("HHRF_ROOT").unwrap_or_else(|_| "./HHRF_ROOT".into());
-    let shared_root = Arc::new(root);
-    let js_runtime_manager = Arc::new(JsRuntimeManager::new());
+    let cli = Cli::parse();
+    let config = match Config::load(&cli) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(2);
+        }
+    };
+    if let Err(err) = telemetry::init(&config.logging) {
+        eprintln!("{}", err);
+        std::process::exit(2);
+    }
+
+    let response_cache = match ResponseCache::new(&config.cache) {
+        Ok(response_cache) => response_cache,
+        Err(err) => {
+            tracing::error!("{}", err);
+            std::process::exit(2);
+        }
+    };
+    let library_cache = Arc::new(LibraryCache::new(
+        Duration::from_secs(config.library_cache.ttl_secs),
+        config.library_cache.max_entries,
+    ));
+    let state = Arc::new(AppState {
+        metrics: Metrics::new(library_cache.clone()),
+        library_cache,
+        trusted_proxies: config
+            .server
+            .trusted_proxies
+            .iter()
+            .filter_map(|proxy| request_meta::parse_net(proxy))
+            .collect(),
+        js_runtime_manager: JsRuntimeManager::new(),
+        concurrency: ConcurrencyLimiter::new(&config.server),
+        rate_limiter: RateLimiter::default(),
+        authenticator: Authenticator::default(),
+        recent_logs: RecentLogs::default(),
+        traffic: TrafficSplitter::default(),
+        shutdown: Shutdown::default(),
+        tls: Arc::new(CertResolver::default()),
+        response_cache,
+        config: RwLock::new(Arc::new(config)),
+        cli,
+    });
+
+    if let Err(err) = state.tls.load(&state.config().tls) {
+        tracing::error!("{}", err);
+        std::process::exit(2);
+    }
 
-    let app = Router::new().route(
+    spawn_library_eviction(state.library_cache.clone());
+    spawn_rate_limit_pruning(state.clone());
+    reload::spawn_config_reload(state.clone());
+    discovery::start(state.clone()).await;
+
+    let mut app = Router::new().route(
         "/rpc/:org/:func/:version/*tail",
         any({
-            let root = shared_root.clone();
+            let state = state.clone();
             move |Path((org, func, version, _tail)): Path<(String, String, String, String)>,
                   req: Request<axum::body::Body>| {
-                handle_rpc(root.clone(), org, func, version, req)
+                dispatch_raw(state.clone(), "rpc", org, func, version, req)
             }
         }),
     )
     .route(
         "/js-embed/:org/:func/:version/*tail",
         any({
-            let root = shared_root.clone();
-            let js_runtime_manager = js_runtime_manager.clone();
+            let state = state.clone();
             move |Path((org, func, version, _tail)): Path<(String, String, String, String)>,
                   req: Request<axum::body::Body>| {
-                handle_js(
-                    root.clone(),
-                    js_runtime_manager.clone(),
-                    org,
-                    func,
-                    version,
-                    req,
-                )
+                dispatch_raw(state.clone(), "js-embed", org, func, version, req)
             }
         }),
     );
+    let metrics_config = state.config().metrics.clone();
+    if metrics_config.enabled {
+        app = app.route(
+            &metrics_config.path,
+            get({
+                let state = state.clone();
+                move || metrics_handler(state.clone())
+            }),
+        );
+    }
+    let health_config = state.config().health.clone();
+    if health_config.enabled {
+        app = app
+            .route(&health_config.liveness_path, get(health::liveness))
+            .route(
+                &health_config.readiness_path,
+                get({
+                    let state = state.clone();
+                    move || health::readiness(state.clone())
+                }),
+            );
+    }
+    let app = app
+        .fallback({
+            let state = state.clone();
+            move |req: Request<axum::body::Body>| route_by_host(state.clone(), req)
+        })
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            request_meta::layer,
+        ));
 
-    let listener = TcpListener::bind("0.0.0.0:3000").await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+    let listeners = match listen::open(&state.config()) {
+        Ok(listeners) => listeners,
+        Err(err) => {
+            tracing::error!("{}", err);
+            std::process::exit(1);
+        }
+    };
+
+    let mut servers = JoinSet::new();
+    let admin_app = admin::router(state.clone());
+    let server_listeners = listeners
+        .server
+        .into_iter()
+        .map(|listener| ("Listening", listener, app.clone()));
+    let admin_listeners = listeners
+        .admin
+        .into_iter()
+        .map(|listener| ("Admin API listening", listener, admin_app.clone()));
+    for (label, listener, app) in server_listeners.chain(admin_listeners) {
+        if let Ok(addr) = listener.local_addr() {
+            tracing::info!("{} on http://{}", label, addr);
+        }
+        let draining = shutdown::reached(state.shutdown.subscribe(), shutdown::Phase::Draining);
+        servers.spawn(async move {
+            axum::serve(
+                listener,
+                app.into_make_service_with_connect_info::<SocketAddr>(),
+            )
+            .with_graceful_shutdown(draining)
+            .await
+        });
+    }
+
+    if !listeners.tls.is_empty() {
+        let acceptor = match tls::server_config(state.tls.clone()) {
+            Ok(config) => TlsAcceptor::from(config),
+            Err(err) => {
+                tracing::error!("{}", err);
+                std::process::exit(1);
+            }
+        };
+        for listener in listeners.tls {
+            if let Ok(addr) = listener.local_addr() {
+                tracing::info!("Listening on https://{}", addr);
+            }
+            servers.spawn(tls::serve(
+                listener,
+                acceptor.clone(),
+                app.clone(),
+                state.shutdown.subscribe(),
+            ));
+        }
+        tls::spawn_cert_reload(state.clone());
+    }
+
+    let signal = tokio::select! {
+        () = join_servers(&mut servers) => None,
+        signal = shutdown::signal_received() => Some(signal),
+    };
+    if let Some(signal) = signal {
+        let deadline = Duration::from_secs(state.config().server.shutdown_timeout_secs);
+        tracing::info!(
+            "Received {}, draining in-flight requests for up to {:?}",
+            signal,
+            deadline
+        );
+        state.shutdown.advance(shutdown::Phase::Draining);
+        if tokio::time::timeout(deadline, join_servers(&mut servers))
+            .await
+            .is_err()
+        {
+            state.shutdown.advance(shutdown::Phase::Aborting);
+            let isolates = state.js_runtime_manager.terminate_all();
+            tracing::warn!(
+                "Drain deadline passed; killed runner processes and {} JS isolates",
+                isolates
+            );
+            // Killed invocations answer with 503; give them a moment to go out.
+            let _ = tokio::time::timeout(ABORT_GRACE, join_servers(&mut servers)).await;
+            telemetry::shutdown();
+            // In-process calls cannot be interrupted and would keep the
+            // runtime from shutting down.
+            std::process::exit(1);
+        }
+    }
+    telemetry::shutdown();
 }
 
-async fn handle_js(
-    root: Arc<String>,
-    js_runtime_manager: Arc<JsRuntimeManager>,
+/// Waits for all listeners to stop serving.
+async fn join_servers(servers: &mut JoinSet<std::io::Result<()>>) {
+    while let Some(result) = servers.join_next().await {
+        match result {
+            Ok(Ok(())) => {}
+            Ok(Err(err)) => tracing::error!("Server error: {}", err),
+            Err(err) => tracing::error!("Server task failed: {}", err),
+        }
+    }
+}
+
+/// Periodically drops libraries that have been idle for longer than the TTL.
+fn spawn_library_eviction(library_cache: Arc<LibraryCache>) {
+    let period = (library_cache.ttl() / 2).max(Duration::from_secs(1));
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            let evicted = library_cache.evict_expired();
+            if evicted > 0 {
+                let stats = library_cache.stats();
+                tracing::info!(
+                    "Library cache evicted {} idle entries (resident={}, hits={}, misses={}, evictions={})",
+                    evicted, stats.resident, stats.hits, stats.misses, stats.evictions
+                );
+            }
+        }
+    });
+}
+
+/// Periodically forgets rate limit buckets that have refilled completely.
+fn spawn_rate_limit_pruning(state: Arc<AppState>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(60));
+        loop {
+            interval.tick().await;
+            let pruned = state.rate_limiter.prune();
+            if pruned > 0 {
+                tracing::debug!("Pruned {} idle rate limit buckets", pruned);
+            }
+        }
+    });
+}
+
+async fn metrics_handler(state: Arc<AppState>) -> axum::response::Response {
+    match state.metrics.render() {
+        Ok((content_type, body)) => axum::response::Response::builder()
+            .header(CONTENT_TYPE, content_type)
+            .body(axum::body::Body::from(body))
+            .unwrap(),
+        Err(err) => error_response(500, err),
+    }
+}
+
+/// Serves `/rpc` and `/js-embed`, unless `server.expose_rpc` is off and a
+/// `[[routes]]` host claims the request: then the path goes through the
+/// routes table like any other.
+async fn dispatch_raw(
+    state: Arc<AppState>,
+    route: &'static str,
     org: String,
     func: String,
     version: String,
     req: Request<axum::body::Body>,
 ) -> axum::response::Response {
+    let config = state.config();
+    if !config.server.expose_rpc && routes::claims_host(&config.routes, &req) {
+        return route_by_host(state, req).await;
+    }
+    dispatch(state, route, org, func, version, req).await
+}
+
+/// Serves requests outside `/rpc` and `/js-embed` through the `[[routes]]`
+/// table, as if they had been sent to `/rpc/{org}/{func}/{version}`.
+async fn route_by_host(
+    state: Arc<AppState>,
+    mut req: Request<axum::body::Body>,
+) -> axum::response::Response {
+    let config = state.config();
+    let Some(matched) = routes::find(&config.routes, &req) else {
+        return error_response(404, format!("No route for '{}'", req.uri().path()));
+    };
+    let target = matched.route.function.splitn(3, '/').collect::<Vec<_>>();
+    let [org, func, version] = target[..] else {
+        return error_response(500, format!("Invalid route target '{}'", matched.route.function));
+    };
+    let Some(uri) = routes::rpc_uri(org, func, version, &matched.path_and_query) else {
+        return error_response(500, format!("Invalid route target '{}'", matched.route.function));
+    };
+    let (org, func, version) = (org.to_string(), func.to_string(), version.to_string());
+    *req.uri_mut() = uri;
+    dispatch(state, "rpc", org, func, version, req).await
+}
+
+/// Picks a split target, resolves the requested version and the function's
+/// settings, answers CORS preflights and records request metrics around
+/// [`admit`].
+async fn dispatch(
+    state: Arc<AppState>,
+    route: &'static str,
+    org: String,
+    func: String,
+    version: String,
+    mut req: Request<axum::body::Body>,
+) -> axum::response::Response {
+    let config = state.config();
+    let split_name = format!("{}/{}/{}", org, func, version);
+    let routed = config
+        .splits
+        .get(&split_name)
+        .map(|split| state.traffic.route(&split_name, split, req.headers()));
+    let requested = routed
+        .as_ref()
+        .map(|routed| routed.target.as_str())
+        .unwrap_or(&version);
+    // The segments end up in filesystem paths; `..%2F` must not escape the root.
+    let mut key = FunctionKey {
+        org,
+        func,
+        version: requested.to_string(),
+    };
+    if !key.is_valid() {
+        return error_response(404, format!("Invalid function path '{}'", key));
+    }
+    key.version =
+        match versions::resolve(&config.server.root, &config, &key.org, &key.func, requested) {
+            Ok(resolved) => resolved,
+            Err(err) => return error_response(404, err),
+        };
+    if !key.is_valid() {
+        return error_response(404, format!("Invalid function path '{}'", key));
+    }
+    if key.version != version {
+        rewrite_version(&mut req, route, &key);
+    }
+    let settings = FunctionSettings::resolve(&config, &key);
+    let backend = if route == "js-embed" {
+        ExecBackend::Js
+    } else {
+        settings.backend
+    };
+
+    let trace_id = req
+        .extensions()
+        .get::<RequestMeta>()
+        .map(|meta| meta.trace_id.clone())
+        .unwrap_or_default();
+    let span = tracing::info_span!(
+        "request",
+        org = %key.org,
+        func = %key.func,
+        version = %key.version,
+        backend = backend.as_str(),
+        trace_id = %trace_id,
+    );
+    telemetry::link_parent(&span, req.headers());
+
     let start_time = Instant::now();
+    let in_flight = state.metrics.in_flight(&key, backend);
+    let cors = settings.cors.clone();
+    let origin = req.headers().get(ORIGIN).cloned();
+    let preflight = cors
+        .as_ref()
+        .and_then(|cors| cors::preflight(cors, req.method(), req.headers()));
+    let mut resp = match preflight {
+        Some(resp) => resp,
+        None => {
+            admit(state.clone(), route, key.clone(), &config, settings, req)
+                .instrument(span)
+                .await
+        }
+    };
+    drop(in_flight);
+    if let Some(cors) = &cors {
+        cors::apply(cors, origin.as_ref(), &mut resp);
+    }
+    if let Ok(version) = HeaderValue::from_str(&key.version) {
+        resp.headers_mut().insert(versions::VERSION_HEADER, version);
+    }
+
+    state.metrics.observe(&key, backend, Phase::Total, start_time.elapsed());
+    state.metrics.record_request(&key, backend, resp.status().as_u16());
+    // HHRF's own answers (load shedding, timeouts, ...) don't judge a canary.
+    if let Some(routed) = routed.filter(|_| resp.extensions().get::<FunctionStatus>().is_some()) {
+        let split = &config.splits[&routed.split];
+        if state.traffic.record(&routed, split, resp.status().as_u16()) {
+            state.metrics.record_rollback(&key);
+        }
+    }
+    resp
+}
+
+/// Applies the function's rate limits, reads the body, authenticates the
+/// caller, applies its `api-key` limits and hands the request to the JS or
+/// native handler.
+async fn admit(
+    state: Arc<AppState>,
+    route: &'static str,
+    key: FunctionKey,
+    config: &Config,
+    settings: FunctionSettings,
+    req: Request<axum::body::Body>,
+) -> axum::response::Response {
+    let (mut parts, body) = req.into_parts();
+    let mut meta = parts
+        .extensions
+        .get::<RequestMeta>()
+        .cloned()
+        .unwrap_or_default();
+    // `api-key` limits follow the verified caller, so they wait for auth.
+    let (per_caller, anonymous): (Vec<_>, Vec<_>) = settings
+        .rate_limits
+        .iter()
+        .cloned()
+        .partition(|rule| rule.scope == RateLimitScope::ApiKey);
+    let quota = match state.rate_limiter.check(&key, &anonymous, &meta) {
+        Ok(quota) => quota,
+        Err(limited) => return limited.response(),
+    };
 
-    let (parts, body) = req.into_parts();
-    let body_bytes = match body.collect().await {
-        Ok(collected) => collected.to_bytes(),
+    if let Err(err) = headers::check_request(&parts.headers) {
+        return error_response(400, err);
+    }
+    let body_bytes = match body::read_limited(&parts.headers, body, settings.max_body_bytes)
+        .instrument(tracing::info_span!("read_body"))
+        .await
+    {
+        Ok(bytes) => bytes,
         Err(e) => {
-            return error_response(400, format!("Failed to read request body: {}", e));
+            return error_response(e.status(), e.to_string());
+        }
+    };
+
+    // HMAC signatures cover the body, so authentication runs once it is read.
+    let identity = tracing::info_span!("authenticate").in_scope(|| {
+        state.authenticator.authenticate(
+            &settings.auth,
+            &parts,
+            &body_bytes,
+            &config.server.api_key_header,
+        )
+    });
+    meta.identity = match identity {
+        Ok(identity) => identity,
+        Err(err) => return err.response(),
+    };
+    let quota = match state.rate_limiter.check(&key, &per_caller, &meta) {
+        Ok(caller_quota) => rate_limit::tightest(quota, caller_quota),
+        Err(limited) => return limited.response(),
+    };
+    parts.extensions.insert(meta);
+
+    let body_bytes = if settings.decompress_requests {
+        let limit = settings.max_body_bytes;
+        match compression::decompress_request(&mut parts.headers, body_bytes, limit).await {
+            Ok(bytes) => bytes,
+            Err(e) => return error_response(e.status(), e.to_string()),
         }
+    } else {
+        body_bytes
     };
+    let accept_encoding = parts.headers.get(ACCEPT_ENCODING).cloned();
+    let compress_responses = settings.compress_responses;
 
-    let prefix = format!("/js-embed/{org}/{func}/{version}");
-    let mut stripped_path = parts
-        .uri
-        .path()
-        .strip_prefix(&prefix)
-        .unwrap_or(parts.uri.path())
-        .to_string();
-    if stripped_path.is_empty() {
-        stripped_path = "/".to_string();
-    }
-    if !stripped_path.starts_with('/') {
-        stripped_path = format!("/{stripped_path}");
-    }
-    let path_and_query = if let Some(query) = parts.uri.query() {
-        format!("{stripped_path}?{query}")
+    let mut resp = if config.cache.enabled && settings.cache {
+        cache::handle(state, route, key, settings, parts, body_bytes).await
     } else {
-        stripped_path
+        invoke(state, route, key, settings, parts, body_bytes).await
     };
+    if compress_responses {
+        resp = compression::compress_response(&config.compression, accept_encoding.as_ref(), resp)
+            .await;
+    }
+    if let Some(quota) = quota {
+        quota.apply(resp.headers_mut());
+    }
+    resp
+}
+
+/// Runs the function on its backend and flushes the lines it logged.
+pub async fn invoke(
+    state: Arc<AppState>,
+    route: &'static str,
+    key: FunctionKey,
+    settings: FunctionSettings,
+    parts: Parts,
+    body_bytes: Bytes,
+) -> axum::response::Response {
+    let config = state.config();
+    let trace_id = parts
+        .extensions
+        .get::<RequestMeta>()
+        .map(|meta| meta.trace_id.clone())
+        .unwrap_or_default();
+    let logs = Capture::new(&config.function_logs);
+    let resp = if route == "js-embed" || settings.backend == ExecBackend::Js {
+        handle_js(state.clone(), route, key.clone(), settings, parts, body_bytes, logs.clone())
+            .await
+    } else {
+        handle_rpc(state.clone(), key.clone(), settings, parts, body_bytes, logs.clone()).await
+    };
+    function_logs::flush(
+        &config.function_logs,
+        &state.recent_logs,
+        &key,
+        &trace_id,
+        &logs,
+    );
+    resp
+}
+
+async fn handle_js(
+    state: Arc<AppState>,
+    route: &'static str,
+    key: FunctionKey,
+    settings: FunctionSettings,
+    parts: Parts,
+    body_bytes: Bytes,
+    logs: Capture,
+) -> axum::response::Response {
+    let start_time = Instant::now();
+    let FunctionKey { org, func, version } = &key;
 
-    let function_root = format!("{root}/functions/{org}/{func}/{version}");
+    let meta = parts
+        .extensions
+        .get::<RequestMeta>()
+        .cloned()
+        .unwrap_or_default();
+
+    let path_and_query = key.function_path(route, &parts.uri);
+
+    let function_root = format!("{}/functions/{org}/{func}/{version}", state.root());
     let script_path = format!("{function_root}/fezz.js");
     let env_path = format!("{function_root}/.env");
 
     let env_vars = load_env_vars(&env_path);
+    let deadline = start_time + settings.timeout;
 
-    let headers = parts
-        .headers
+    let headers = headers::sanitize_request(&parts.headers, body_bytes.len())
         .iter()
         .filter_map(|(name, value)| {
             value
@@ -110,9 +689,18 @@ async fn handle_js(
         .collect::<Vec<_>>();
 
     let js_key = JsKey {
-        org: org.clone(),
-        func: func.clone(),
-        version: version.clone(),
+        org: key.org.clone(),
+        func: key.func.clone(),
+        version: key.version.clone(),
+    };
+
+    let permit = match state
+        .concurrency
+        .acquire(&key, &settings, remaining_budget(deadline))
+        .await
+    {
+        Ok(permit) => permit,
+        Err(err) => return err.response(),
     };
 
     let js_req = JsInvoke {
@@ -121,39 +709,70 @@ async fn handle_js(
         headers,
         body: body_bytes.to_vec(),
         env: env_vars,
+        timeout: Some(remaining_budget(deadline)),
+        trace_id: Some(meta.trace_id.clone()),
+        client_ip: meta.client_ip.map(|ip| ip.to_string()),
+        principal: meta.identity.principal.clone(),
+        claims: meta.identity.claims.clone().into_iter().collect(),
+        logs: JsLogs::default(),
     };
-
-    let result = match js_runtime_manager.invoke(&js_key, &script_path, js_req).await {
+    let js_logs = js_req.logs.clone();
+
+    let invoke_start = Instant::now();
+    // The isolate releases the permit once it has stopped, also after a timeout.
+    let result = state
+        .js_runtime_manager
+        .invoke(&js_key, &script_path, js_req, permit)
+        .instrument(tracing::info_span!("invoke"))
+        .await;
+    for (level, message) in js_logs.take() {
+        logs.push(Stream::Log, &level, &message);
+    }
+    state
+        .metrics
+        .observe(&key, ExecBackend::Js, Phase::Execute, invoke_start.elapsed());
+    let result = match result {
         Ok(result) => result,
+        Err(err) if err.downcast_ref::<JsTimeout>().is_some() => {
+            return error_response(504, format!("JS execution error: {}", err));
+        }
         Err(err) => {
-            return error_response(500, format!("JS execution error: {}", err));
+            return function_error(500, format!("JS execution error: {}", err));
         }
     };
 
-    let mut http_resp = axum::response::Response::builder().status(result.status);
+    if result.body.len() as u64 > settings.max_response_bytes {
+        return error_response(
+            502,
+            format!(
+                "JS execution error: response exceeds the limit of {} bytes",
+                settings.max_response_bytes
+            ),
+        );
+    }
 
-    for (name, value) in result.headers {
-        let name = match HeaderName::from_bytes(name.as_bytes()) {
-            Ok(name) => name,
-            Err(_) => {
-                println!("[HHRF] Skipping invalid header name");
-                continue;
-            }
-        };
-        let value = match HeaderValue::from_bytes(value.as_bytes()) {
-            Ok(value) => value,
-            Err(_) => {
-                println!("[HHRF] Skipping invalid header value");
-                continue;
-            }
-        };
-        http_resp = http_resp.header(name, value);
+    let mut http_resp = axum::response::Response::builder()
+        .status(result.status)
+        .extension(FunctionStatus);
+    if let (Some(resp_headers), Ok(status)) = (
+        http_resp.headers_mut(),
+        StatusCode::from_u16(result.status),
+    ) {
+        resp_headers.extend(headers::sanitize_response(
+            result
+                .headers
+                .iter()
+                .map(|(name, value)| (name.as_bytes(), value.as_bytes())),
+            status,
+            parts.method == Method::HEAD,
+            result.body.len(),
+        ));
     }
 
     let total_time = start_time.elapsed();
-    println!(
-        "[HHRF] Total JS request time for '{}/{}/{}': {:?}",
-        org, func, version, total_time
+    tracing::info!(
+        "Total JS request time for '{}/{}/{}' (trace_id={}): {:?}",
+        org, func, version, meta.trace_id, total_time
     );
 
     http_resp
@@ -162,53 +781,45 @@ async fn handle_js(
 }
 
 async fn handle_rpc(
-    root: Arc<String>,
-    org: String,
-    func: String,
-    version: String,
-    req: Request<axum::body::Body>,
+    state: Arc<AppState>,
+    key: FunctionKey,
+    settings: FunctionSettings,
+    parts: Parts,
+    body_bytes: Bytes,
+    logs: Capture,
 ) -> axum::response::Response {
     let start_time = Instant::now();
+    let FunctionKey { org, func, version } = &key;
 
-    let (parts, body) = req.into_parts();
-    let body_bytes = match body.collect().await {
-        Ok(collected) => collected.to_bytes(),
-        Err(e) => {
-            return error_response(400, format!("Failed to read request body: {}", e));
-        }
-    };
+    let meta = parts
+        .extensions
+        .get::<RequestMeta>()
+        .cloned()
+        .unwrap_or_default();
 
-    let prefix = format!("/rpc/{org}/{func}/{version}");
-    let mut stripped_path = parts
-        .uri
-        .path()
-        .strip_prefix(&prefix)
-        .unwrap_or(parts.uri.path())
-        .to_string();
-    if stripped_path.is_empty() {
-        stripped_path = "/".to_string();
-    }
-    if !stripped_path.starts_with('/') {
-        stripped_path = format!("/{stripped_path}");
-    }
-    let path_and_query = if let Some(query) = parts.uri.query() {
-        format!("{stripped_path}?{query}")
-    } else {
-        stripped_path
-    };
+    let path_and_query = key.function_path("rpc", &parts.uri);
 
-    let function_root = format!("{root}/functions/{org}/{func}/{version}");
+    let function_root = format!("{}/functions/{org}/{func}/{version}", state.root());
     let so_path = format!("{function_root}/fezz.so");
     let env_path = format!("{function_root}/.env");
 
     let env_vars = load_env_vars(&env_path);
+    let deadline = start_time + settings.timeout;
 
-    let headers = parts
-        .headers
+    let headers = headers::sanitize_request(&parts.headers, body_bytes.len())
         .iter()
         .map(|(name, value)| FezzWireHeader::new(name.as_str(), value.as_bytes()))
         .collect::<Vec<_>>();
 
+    let permit = match state
+        .concurrency
+        .acquire(&key, &settings, remaining_budget(deadline))
+        .await
+    {
+        Ok(permit) => permit,
+        Err(err) => return err.response(),
+    };
+
     // 2) Create FezzWireRequest to send to the function process
     let wire_req = FezzWireRequest {
         method: parts.method.to_string(),
@@ -227,128 +838,133 @@ async fn handle_rpc(
         path_and_query,
         headers,
         body: ByteBuf::from(body_bytes.to_vec()),
-        meta: None,
+        meta: Some(meta.to_wire(remaining_budget(deadline).as_millis() as u64)),
+        env: env_vars.iter().cloned().collect(),
     };
 
-    let req_bytes = match fezz_sdk::encode_request(&wire_req) {
+    let encoded =
+        tracing::info_span!("encode_request").in_scope(|| fezz_sdk::encode_request(&wire_req));
+    let req_bytes = match encoded {
         Ok(bytes) => bytes,
         Err(e) => {
             return error_response(500, format!("Failed to serialize request: {}", e));
         }
     };
 
-    // 3) Execute function in-process via libloading
+    // 3) Execute function with the backend chosen for it (runner process or in-process)
+    let backend = settings.backend;
     let fetch_start = Instant::now();
-    let resp_bytes = match execute_in_process(&so_path, &req_bytes, env_vars).await {
-        Ok(bytes) => bytes,
+    let call = exec::NativeCall {
+        key: key.clone(),
+        so_path,
+        req_bytes,
+        env_vars,
+        timeout: remaining_budget(deadline),
+        permit,
+        logs: logs.clone(),
+        shutdown: state.shutdown.subscribe(),
+    };
+    let executed = match exec::execute(&settings, &state.library_cache, call).await {
+        Ok(executed) => executed,
         Err(e) => {
-            return error_response(500, format!("Function execution error: {}", e));
+            let message = format!("Function execution error: {}", e);
+            return match e {
+                ExecError::Crashed(_) => {
+                    state.metrics.record_runner_crash(&key);
+                    function_error(e.status(), message)
+                }
+                ExecError::Failed(_) => function_error(e.status(), message),
+                _ => error_response(e.status(), message),
+            };
         }
     };
 
     let fetch_time = fetch_start.elapsed();
-    println!("[HHRF] external function execution time: {:?}", fetch_time);
+    state.metrics.observe(&key, backend, Phase::Load, executed.load_time);
+    state.metrics.observe(
+        &key,
+        backend,
+        Phase::Execute,
+        fetch_time.saturating_sub(executed.load_time),
+    );
+    tracing::info!(
+        "external function execution time ({:?}): {:?}",
+        backend, fetch_time
+    );
 
-    let fezz_resp: FezzWireResponse = match fezz_sdk::decode_response(&resp_bytes) {
+    let _encode_response = tracing::info_span!("encode_response").entered();
+    let fezz_resp: FezzWireResponse = match fezz_sdk::decode_response(&executed.resp_bytes) {
         Ok(r) => r,
         Err(e) => {
-            return error_response(500, format!("Invalid response bytes: {}", e));
+            return function_error(500, format!("Invalid response bytes: {}", e));
         }
     };
+    for line in &fezz_resp.logs {
+        logs.push(Stream::Log, &line.level, &line.message);
+    }
 
     // 5) Convert to HTTP response
-    let mut http_resp = axum::response::Response::builder().status(fezz_resp.status);
-
-    for header in &fezz_resp.headers {
-        let name = match HeaderName::from_bytes(&header.name) {
-            Ok(name) => name,
-            Err(_) => {
-                println!("[HHRF] Skipping invalid header name");
-                continue;
-            }
-        };
-        let value = match HeaderValue::from_bytes(&header.value) {
-            Ok(value) => value,
-            Err(_) => {
-                println!("[HHRF] Skipping invalid header value");
-                continue;
-            }
-        };
-        http_resp = http_resp.header(name, value);
+    let mut http_resp = axum::response::Response::builder()
+        .status(fezz_resp.status)
+        .extension(FunctionStatus);
+    if let (Some(resp_headers), Ok(status)) = (
+        http_resp.headers_mut(),
+        StatusCode::from_u16(fezz_resp.status),
+    ) {
+        resp_headers.extend(headers::sanitize_response(
+            fezz_resp
+                .headers
+                .iter()
+                .map(|header| (header.name.as_slice(), header.value.as_slice())),
+            status,
+            parts.method == Method::HEAD,
+            fezz_resp.body.len(),
+        ));
     }
 
     let body = fezz_resp.body.into_vec();
 
     let total_time = start_time.elapsed();
-    println!(
-        "[HHRF] Total request time for '{}/{}/{}': {:?}",
-        org, func, version, total_time
+    tracing::info!(
+        "Total request time for '{}/{}/{}' (trace_id={}): {:?}",
+        org, func, version, meta.trace_id, total_time
     );
 
     http_resp.body(axum::body::Body::from(body)).unwrap()
 }
 
-/// Execute a Fezz function in-process via libloading.
-///
-/// `so_path` is the path to the dynamic library containing `fezz_handle_v2`.
-/// `req_bytes` is the FezzWireRequest bytes passed to the plugin.
-async fn execute_in_process(
-    so_path: &str,
-    req_bytes: &[u8],
-    env_vars: Vec<(String, String)>,
-) -> Result<Vec<u8>, String> {
-    if !FsPath::new(so_path).exists() {
-        return Err(format!("Library not found at {}", so_path));
+/// Points the request path at the resolved version, so handlers strip the
+/// same `/{route}/{org}/{func}/{version}` prefix as for an exact version.
+fn rewrite_version(req: &mut Request<axum::body::Body>, route: &str, key: &FunctionKey) {
+    let uri = req.uri();
+    let mut path_and_query = format!("/{}/{}/{}/{}", route, key.org, key.func, key.version);
+    if let Some(tail) = uri.path().splitn(6, '/').nth(5) {
+        path_and_query.push('/');
+        path_and_query.push_str(tail);
+    }
+    if let Some(query) = uri.query() {
+        path_and_query.push('?');
+        path_and_query.push_str(query);
     }
 
-    let so_path = so_path.to_string();
-    let req_bytes = req_bytes.to_vec();
-
-    tokio::task::spawn_blocking(move || unsafe {
-        type FezzHandleV2Fn = unsafe extern "C" fn(fezz_sdk::FezzSlice) -> fezz_sdk::FezzOwned;
-        type FezzFreeV2Fn = unsafe extern "C" fn(fezz_sdk::FezzOwned);
-
-        for (key, value) in env_vars {
-            std::env::set_var(key, value);
-        }
-
-        let library = Library::new(&so_path)
-            .map_err(|e| format!("Failed to load library '{}': {}", so_path, e))?;
-
-        let fezz_handle_v2: Symbol<FezzHandleV2Fn> = library
-            .get(b"fezz_handle_v2")
-            .map_err(|e| format!("Failed to resolve fezz_handle_v2: {}", e))?;
-
-        let fezz_free_v2: Symbol<FezzFreeV2Fn> = library
-            .get(b"fezz_free_v2")
-            .map_err(|e| format!("Failed to resolve fezz_free_v2: {}", e))?;
-
-        let owned = fezz_handle_v2(fezz_sdk::FezzSlice {
-            ptr: req_bytes.as_ptr(),
-            len: req_bytes.len(),
-        });
-
-        if owned.ptr.is_null() && owned.len != 0 {
-            return Err("fezz_handle_v2 returned null pointer".to_string());
-        }
-
-        let resp_bytes = if owned.len == 0 {
-            Vec::new()
-        } else {
-            std::slice::from_raw_parts(owned.ptr, owned.len).to_vec()
-        };
-
-        fezz_free_v2(owned);
+    let mut parts = uri.clone().into_parts();
+    parts.path_and_query = match path_and_query.parse() {
+        Ok(path_and_query) => Some(path_and_query),
+        Err(_) => return,
+    };
+    if let Ok(uri) = Uri::from_parts(parts) {
+        *req.uri_mut() = uri;
+    }
+}
 
-        Ok(resp_bytes)
-    })
-    .await
-    .map_err(|e| format!("Failed to join blocking task: {}", e))?
+/// Time left until `deadline`, never negative.
+fn remaining_budget(deadline: Instant) -> std::time::Duration {
+    deadline.saturating_duration_since(Instant::now())
 }
 
 /// Creates an error HTTP response with the given status code and message.
 fn error_response(status: u16, message: String) -> axum::response::Response {
-    println!("[HHRF] Error: {}", message);
+    tracing::warn!("Error: {}", message);
     axum::response::Response::builder()
         .status(status)
         .header("content-type", "text/plain")
@@ -356,12 +972,20 @@ fn error_response(status: u16, message: String) -> axum::response::Response {
         .unwrap()
 }
 
+/// An error response the function is to blame for, e.g. a crash; it counts
+/// against split canaries like a status the function returned.
+fn function_error(status: u16, message: String) -> axum::response::Response {
+    let mut resp = error_response(status, message);
+    resp.extensions_mut().insert(FunctionStatus);
+    resp
+}
+
 fn load_env_vars(env_path: &str) -> Vec<(String, String)> {
     let contents = match std::fs::read_to_string(env_path) {
         Ok(contents) => contents,
         Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Vec::new(),
         Err(err) => {
-            println!("[HHRF] Failed to read env file '{}': {}", env_path, err);
+            tracing::warn!("Failed to read env file '{}': {}", env_path, err);
             return Vec::new();
         }
     };
@@ -0,0 +1,224 @@
+use crate::{exec::ExecBackend, library_cache::LibraryCache, FunctionKey};
+use prometheus::{
+    core::Collector, Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge,
+    IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/// Latency buckets in seconds, up to the default 30s function timeout.
+const LATENCY_BUCKETS: &[f64] = &[
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0,
+];
+
+/// Phase of an invocation recorded in `hhrf_request_duration_seconds`.
+#[derive(Clone, Copy, Debug)]
+pub enum Phase {
+    /// Resolving/loading the native library or spawning the runner.
+    Load,
+    /// Running the function itself.
+    Execute,
+    /// The whole request as seen by HHRF, including auth and body upload.
+    Total,
+}
+
+impl Phase {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Phase::Load => "load",
+            Phase::Execute => "execute",
+            Phase::Total => "total",
+        }
+    }
+}
+
+/// Prometheus metrics served on `/metrics`.
+pub struct Metrics {
+    registry: Registry,
+    requests: IntCounterVec,
+    duration: HistogramVec,
+    in_flight: IntGaugeVec,
+    runner_crashes: IntCounterVec,
+    rollbacks: IntCounterVec,
+    response_cache: IntCounterVec,
+    library_cache: Arc<LibraryCache>,
+    cache_hits: IntCounter,
+    cache_misses: IntCounter,
+    cache_evictions: IntCounter,
+    cache_resident: IntGauge,
+    // Serializes scrapes so the cache counters are synced exactly once per delta.
+    render_lock: Mutex<()>,
+}
+
+impl Metrics {
+    pub fn new(library_cache: Arc<LibraryCache>) -> Self {
+        let registry = Registry::new();
+
+        let metrics = Self {
+            requests: IntCounterVec::new(
+                Opts::new("hhrf_requests_total", "Function requests by status class."),
+                &["org", "func", "version", "backend", "status"],
+            )
+            .unwrap(),
+            duration: HistogramVec::new(
+                HistogramOpts::new(
+                    "hhrf_request_duration_seconds",
+                    "Function request latency by phase (load, execute, total).",
+                )
+                .buckets(LATENCY_BUCKETS.to_vec()),
+                &["org", "func", "version", "backend", "phase"],
+            )
+            .unwrap(),
+            in_flight: IntGaugeVec::new(
+                Opts::new("hhrf_requests_in_flight", "Function requests being handled."),
+                &["org", "func", "version", "backend"],
+            )
+            .unwrap(),
+            runner_crashes: IntCounterVec::new(
+                Opts::new(
+                    "hhrf_runner_crashes_total",
+                    "Runner children that died from a signal or exited unsuccessfully.",
+                ),
+                &["org", "func", "version"],
+            )
+            .unwrap(),
+            rollbacks: IntCounterVec::new(
+                Opts::new(
+                    "hhrf_split_rollbacks_total",
+                    "Canary versions taken out of a traffic split for their 5xx rate.",
+                ),
+                &["org", "func", "version"],
+            )
+            .unwrap(),
+            response_cache: IntCounterVec::new(
+                Opts::new(
+                    "hhrf_response_cache_total",
+                    "Cacheable requests by outcome (hit, stale, revalidated, miss, bypass).",
+                ),
+                &["org", "func", "version", "result"],
+            )
+            .unwrap(),
+            cache_hits: IntCounter::new(
+                "hhrf_library_cache_hits_total",
+                "In-process library cache hits.",
+            )
+            .unwrap(),
+            cache_misses: IntCounter::new(
+                "hhrf_library_cache_misses_total",
+                "In-process library cache misses (loads and reloads).",
+            )
+            .unwrap(),
+            cache_evictions: IntCounter::new(
+                "hhrf_library_cache_evictions_total",
+                "Libraries evicted from the in-process cache.",
+            )
+            .unwrap(),
+            cache_resident: IntGauge::new(
+                "hhrf_library_cache_resident",
+                "Libraries currently resident in the in-process cache.",
+            )
+            .unwrap(),
+            library_cache,
+            registry,
+            render_lock: Mutex::new(()),
+        };
+
+        let collectors: [Box<dyn Collector>; 10] = [
+            Box::new(metrics.requests.clone()),
+            Box::new(metrics.duration.clone()),
+            Box::new(metrics.in_flight.clone()),
+            Box::new(metrics.runner_crashes.clone()),
+            Box::new(metrics.rollbacks.clone()),
+            Box::new(metrics.response_cache.clone()),
+            Box::new(metrics.cache_hits.clone()),
+            Box::new(metrics.cache_misses.clone()),
+            Box::new(metrics.cache_evictions.clone()),
+            Box::new(metrics.cache_resident.clone()),
+        ];
+        for collector in collectors {
+            metrics.registry.register(collector).unwrap();
+        }
+        metrics
+    }
+
+    /// Counts a request as in flight until the returned guard is dropped.
+    pub fn in_flight(&self, key: &FunctionKey, backend: ExecBackend) -> InFlight {
+        let gauge = self.in_flight.with_label_values(&labels(key, backend));
+        gauge.inc();
+        InFlight(gauge)
+    }
+
+    pub fn observe(
+        &self,
+        key: &FunctionKey,
+        backend: ExecBackend,
+        phase: Phase,
+        elapsed: Duration,
+    ) {
+        let [org, func, version, backend] = labels(key, backend);
+        self.duration
+            .with_label_values(&[org, func, version, backend, phase.as_str()])
+            .observe(elapsed.as_secs_f64());
+    }
+
+    /// Counts a finished request under its status class (`2xx`, `4xx`, ...).
+    pub fn record_request(&self, key: &FunctionKey, backend: ExecBackend, status: u16) {
+        let class = format!("{}xx", status / 100);
+        let [org, func, version, backend] = labels(key, backend);
+        self.requests
+            .with_label_values(&[org, func, version, backend, class.as_str()])
+            .inc();
+    }
+
+    pub fn record_runner_crash(&self, key: &FunctionKey) {
+        self.runner_crashes
+            .with_label_values(&[key.org.as_str(), key.func.as_str(), key.version.as_str()])
+            .inc();
+    }
+
+    pub fn record_rollback(&self, key: &FunctionKey) {
+        self.rollbacks
+            .with_label_values(&[key.org.as_str(), key.func.as_str(), key.version.as_str()])
+            .inc();
+    }
+
+    pub fn record_cache(&self, key: &FunctionKey, result: &str) {
+        self.response_cache
+            .with_label_values(&[key.org.as_str(), key.func.as_str(), key.version.as_str(), result])
+            .inc();
+    }
+
+    /// Renders every metric in the Prometheus text format.
+    pub fn render(&self) -> Result<(String, Vec<u8>), String> {
+        let _guard = self.render_lock.lock().unwrap();
+
+        // The cache keeps its own counters; bring ours up to date by the delta.
+        let stats = self.library_cache.stats();
+        self.cache_hits.inc_by(stats.hits.saturating_sub(self.cache_hits.get()));
+        self.cache_misses.inc_by(stats.misses.saturating_sub(self.cache_misses.get()));
+        self.cache_evictions.inc_by(stats.evictions.saturating_sub(self.cache_evictions.get()));
+        self.cache_resident.set(stats.resident as i64);
+
+        let encoder = TextEncoder::new();
+        let mut buffer = Vec::new();
+        encoder
+            .encode(&self.registry.gather(), &mut buffer)
+            .map_err(|e| format!("Failed to encode metrics: {}", e))?;
+        Ok((encoder.format_type().to_string(), buffer))
+    }
+}
+
+fn labels(key: &FunctionKey, backend: ExecBackend) -> [&str; 4] {
+    [&key.org, &key.func, &key.version, backend.as_str()]
+}
+
+/// Decrements the in-flight gauge on drop.
+pub struct InFlight(IntGauge);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.dec();
+    }
+}
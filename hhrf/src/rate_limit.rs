@@ -0,0 +1,396 @@
+use crate::{
+    config::{RateLimitConfig, RateLimitScope},
+    request_meta::RequestMeta,
+    FunctionKey,
+};
+use axum::http::{header::RETRY_AFTER, HeaderMap, HeaderValue};
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+const RATELIMIT_LIMIT: &str = "ratelimit-limit";
+const RATELIMIT_REMAINING: &str = "ratelimit-remaining";
+const RATELIMIT_RESET: &str = "ratelimit-reset";
+
+/// Bucket state reported back to the client as `RateLimit-*` headers.
+#[derive(Clone, Copy, Debug)]
+pub struct Quota {
+    pub limit: u64,
+    pub remaining: u64,
+    /// Time until the bucket is full again.
+    pub reset: Duration,
+}
+
+impl Quota {
+    pub fn apply(&self, headers: &mut HeaderMap) {
+        headers.insert(RATELIMIT_LIMIT, HeaderValue::from(self.limit));
+        headers.insert(RATELIMIT_REMAINING, HeaderValue::from(self.remaining));
+        headers.insert(RATELIMIT_RESET, HeaderValue::from(ceil_secs(self.reset)));
+    }
+}
+
+/// A request rejected by one of its rate limits.
+#[derive(Debug)]
+pub struct RateLimited {
+    pub quota: Quota,
+    /// Time until the exhausted bucket holds a token again.
+    pub retry_after: Duration,
+}
+
+impl RateLimited {
+    pub fn response(&self) -> axum::response::Response {
+        let mut resp = crate::error_response(429, "Rate limit exceeded".to_string());
+        self.quota.apply(resp.headers_mut());
+        resp.headers_mut()
+            .insert(RETRY_AFTER, HeaderValue::from(ceil_secs(self.retry_after)));
+        resp
+    }
+}
+
+/// Identifies a bucket by what it limits rather than by the rule's position
+/// in a function's list, so an `org` rule is shared by every function of the
+/// org that has it, while two rules with the same scope (e.g. per second and
+/// per minute) keep separate buckets.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+struct BucketKey {
+    scope: RateLimitScope,
+    subject: String,
+    /// `requests_per_second` as bits, since `f64` is not `Hash`.
+    rate: u64,
+    burst: Option<u32>,
+}
+
+struct Bucket {
+    tokens: f64,
+    capacity: f64,
+    rate: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn new(capacity: f64, rate: f64, now: Instant) -> Self {
+        Self {
+            tokens: capacity,
+            capacity,
+            rate,
+            updated: now,
+        }
+    }
+
+    /// Adds the tokens earned since the last update, adopting `capacity`/`rate`
+    /// when the config changed.
+    fn refill(&mut self, capacity: f64, rate: f64, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.capacity = capacity;
+        self.rate = rate;
+        self.tokens = (self.tokens + elapsed * rate).min(capacity);
+        self.updated = now;
+    }
+
+    fn quota(&self) -> Quota {
+        Quota {
+            limit: self.capacity as u64,
+            remaining: self.tokens.floor().max(0.0) as u64,
+            reset: secs_f64((self.capacity - self.tokens) / self.rate),
+        }
+    }
+
+    fn is_full_at(&self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens + elapsed * self.rate >= self.capacity
+    }
+}
+
+/// Token buckets for the `rate_limits` rules of every function.
+///
+/// Buckets are shared by everything that maps to the same scope and subject:
+/// all functions of an org share the `org` bucket, while `client-ip` and
+/// `api-key` buckets are per function and per caller.
+#[derive(Default)]
+pub struct RateLimiter {
+    buckets: Mutex<HashMap<BucketKey, Bucket>>,
+}
+
+impl RateLimiter {
+    /// Takes one token from every bucket matching `rules`, or none at all if
+    /// any of them is empty.
+    ///
+    /// Returns the most constrained bucket's quota, or `None` without rules.
+    pub fn check(
+        &self,
+        key: &FunctionKey,
+        rules: &[RateLimitConfig],
+        meta: &RequestMeta,
+    ) -> Result<Option<Quota>, RateLimited> {
+        if rules.is_empty() {
+            return Ok(None);
+        }
+
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        let keys = rules
+            .iter()
+            .map(|rule| BucketKey {
+                scope: rule.scope,
+                subject: subject(rule.scope, key, meta),
+                rate: rule.requests_per_second.to_bits(),
+                burst: rule.burst,
+            })
+            .collect::<Vec<_>>();
+
+        for (rule, bucket_key) in rules.iter().zip(&keys) {
+            let (capacity, rate) = (rule.capacity(), rule.requests_per_second);
+            let bucket = buckets
+                .entry(bucket_key.clone())
+                .or_insert_with(|| Bucket::new(capacity, rate, now));
+            bucket.refill(capacity, rate, now);
+            if bucket.tokens < 1.0 {
+                return Err(RateLimited {
+                    quota: bucket.quota(),
+                    retry_after: secs_f64((1.0 - bucket.tokens) / bucket.rate),
+                });
+            }
+        }
+
+        let mut tightest: Option<Quota> = None;
+        for bucket_key in &keys {
+            if let Some(bucket) = buckets.get_mut(bucket_key) {
+                bucket.tokens -= 1.0;
+                let quota = bucket.quota();
+                match tightest {
+                    Some(tightest) if tightest.remaining <= quota.remaining => {}
+                    _ => tightest = Some(quota),
+                }
+            }
+        }
+        Ok(tightest)
+    }
+
+    /// Forgets buckets that have refilled completely; they would be recreated
+    /// full anyway. Keeps per-client buckets from growing without bound.
+    pub fn prune(&self) -> usize {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        let before = buckets.len();
+        buckets.retain(|_, bucket| !bucket.is_full_at(now));
+        before - buckets.len()
+    }
+}
+
+/// Of two quotas, the one with fewer requests left.
+pub fn tightest(a: Option<Quota>, b: Option<Quota>) -> Option<Quota> {
+    match (a, b) {
+        (Some(a), Some(b)) if b.remaining < a.remaining => Some(b),
+        (Some(a), _) => Some(a),
+        (None, b) => b,
+    }
+}
+
+/// The value a rule's bucket is keyed on.
+///
+/// `api-key` buckets follow the verified principal (`meta.identity`), so they
+/// must be checked after authentication; a caller without one is limited by
+/// client IP instead. Requests without a client IP share a single anonymous
+/// bucket per function rather than bypassing the limit.
+fn subject(scope: RateLimitScope, key: &FunctionKey, meta: &RequestMeta) -> String {
+    let by_ip = || match meta.client_ip {
+        Some(ip) => format!("{key}|{ip}"),
+        None => format!("{key}|-"),
+    };
+    match scope {
+        RateLimitScope::Org => key.org.clone(),
+        RateLimitScope::Function => key.to_string(),
+        RateLimitScope::ClientIp => by_ip(),
+        RateLimitScope::ApiKey => match &meta.identity.principal {
+            Some(principal) => format!("{key}|principal:{principal}"),
+            None => by_ip(),
+        },
+    }
+}
+
+fn secs_f64(secs: f64) -> Duration {
+    Duration::try_from_secs_f64(secs.max(0.0)).unwrap_or(Duration::MAX)
+}
+
+fn ceil_secs(duration: Duration) -> u64 {
+    duration.as_secs() + u64::from(duration.subsec_nanos() > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(func: &str) -> FunctionKey {
+        FunctionKey {
+            org: "acme".to_string(),
+            func: func.to_string(),
+            version: "0.0.1".to_string(),
+        }
+    }
+
+    fn rule(
+        scope: RateLimitScope,
+        requests_per_second: f64,
+        burst: Option<u32>,
+    ) -> RateLimitConfig {
+        RateLimitConfig {
+            scope,
+            requests_per_second,
+            burst,
+        }
+    }
+
+    fn meta(ip: &str) -> RequestMeta {
+        RequestMeta {
+            client_ip: Some(ip.parse().unwrap()),
+            ..RequestMeta::default()
+        }
+    }
+
+    fn check(
+        limiter: &RateLimiter,
+        key: &FunctionKey,
+        rules: &[RateLimitConfig],
+        meta: &RequestMeta,
+    ) -> Result<Option<Quota>, RateLimited> {
+        limiter.check(key, rules, meta)
+    }
+
+    #[test]
+    fn bucket_refills_at_rate_up_to_capacity() {
+        let start = Instant::now();
+        let mut bucket = Bucket::new(4.0, 2.0, start);
+        bucket.tokens = 0.0;
+
+        bucket.refill(4.0, 2.0, start + Duration::from_millis(500));
+        assert_eq!(bucket.tokens, 1.0);
+        assert_eq!(bucket.quota().reset, Duration::from_millis(1500));
+
+        bucket.refill(4.0, 2.0, start + Duration::from_secs(60));
+        assert_eq!(bucket.tokens, 4.0);
+        assert!(bucket.is_full_at(start + Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn bucket_adopts_changed_capacity() {
+        let start = Instant::now();
+        let mut bucket = Bucket::new(10.0, 1.0, start);
+        bucket.refill(2.0, 1.0, start);
+        assert_eq!(bucket.tokens, 2.0);
+        assert_eq!(bucket.quota().limit, 2);
+    }
+
+    #[test]
+    fn burst_allows_capacity_then_rejects() {
+        let limiter = RateLimiter::default();
+        let rules = [rule(RateLimitScope::Function, 0.001, Some(3))];
+        let meta = RequestMeta::default();
+
+        for remaining in [2, 1, 0] {
+            let quota = check(&limiter, &key("todos"), &rules, &meta).unwrap().unwrap();
+            assert_eq!(quota.limit, 3);
+            assert_eq!(quota.remaining, remaining);
+        }
+        let limited = check(&limiter, &key("todos"), &rules, &meta).unwrap_err();
+        assert_eq!(limited.quota.remaining, 0);
+        assert!(limited.retry_after > Duration::from_secs(900));
+        assert_eq!(limited.response().status(), 429);
+    }
+
+    #[test]
+    fn burst_defaults_to_one_second_of_requests() {
+        assert_eq!(rule(RateLimitScope::Org, 2.5, None).capacity(), 3.0);
+        assert_eq!(rule(RateLimitScope::Org, 0.5, None).capacity(), 1.0);
+    }
+
+    #[test]
+    fn rejected_request_takes_no_token_from_other_buckets() {
+        let limiter = RateLimiter::default();
+        let rules = [
+            rule(RateLimitScope::Org, 0.001, Some(5)),
+            rule(RateLimitScope::ClientIp, 0.001, Some(1)),
+        ];
+        let meta = meta("192.0.2.1");
+
+        let quota = check(&limiter, &key("todos"), &rules, &meta).unwrap().unwrap();
+        assert_eq!(quota.remaining, 0);
+        assert!(check(&limiter, &key("todos"), &rules, &meta).is_err());
+        let org_only = &rules[..1];
+        let quota = check(&limiter, &key("todos"), org_only, &meta).unwrap().unwrap();
+        assert_eq!(quota.remaining, 3);
+    }
+
+    #[test]
+    fn scopes_pick_their_subject() {
+        let limiter = RateLimiter::default();
+        let per_org = [rule(RateLimitScope::Org, 0.001, Some(1))];
+        let per_ip = [rule(RateLimitScope::ClientIp, 0.001, Some(1))];
+
+        assert!(check(&limiter, &key("todos"), &per_org, &meta("192.0.2.1")).is_ok());
+        assert!(check(&limiter, &key("notes"), &per_org, &meta("192.0.2.2")).is_err());
+
+        assert!(check(&limiter, &key("todos"), &per_ip, &meta("192.0.2.1")).is_ok());
+        assert!(check(&limiter, &key("todos"), &per_ip, &meta("192.0.2.2")).is_ok());
+        assert!(check(&limiter, &key("todos"), &per_ip, &meta("192.0.2.1")).is_err());
+    }
+
+    #[test]
+    fn org_bucket_is_shared_whatever_the_rule_position() {
+        let limiter = RateLimiter::default();
+        let org = rule(RateLimitScope::Org, 0.001, Some(1));
+        let todos = [org.clone()];
+        let notes = [rule(RateLimitScope::Function, 0.001, Some(5)), org];
+        let meta = RequestMeta::default();
+
+        assert!(check(&limiter, &key("todos"), &todos, &meta).is_ok());
+        assert!(check(&limiter, &key("notes"), &notes, &meta).is_err());
+    }
+
+    #[test]
+    fn api_key_scope_follows_the_verified_principal() {
+        let limiter = RateLimiter::default();
+        let per_key = [rule(RateLimitScope::ApiKey, 0.001, Some(1))];
+        let caller = |principal: Option<&str>, ip: &str| {
+            let mut meta = meta(ip);
+            meta.identity.principal = principal.map(str::to_string);
+            meta
+        };
+
+        assert!(check(&limiter, &key("todos"), &per_key, &caller(Some("ci"), "192.0.2.1")).is_ok());
+        // Same principal from elsewhere, same bucket.
+        let moved = caller(Some("ci"), "192.0.2.2");
+        assert!(check(&limiter, &key("todos"), &per_key, &moved).is_err());
+
+        // Unverified callers are limited per client IP, however many keys they try.
+        let anonymous = caller(None, "192.0.2.3");
+        assert!(check(&limiter, &key("todos"), &per_key, &anonymous).is_ok());
+        assert!(check(&limiter, &key("todos"), &per_key, &anonymous).is_err());
+    }
+
+    #[test]
+    fn tightest_quota_wins() {
+        let quota = |remaining| Quota {
+            limit: 10,
+            remaining,
+            reset: Duration::ZERO,
+        };
+        assert_eq!(tightest(Some(quota(3)), Some(quota(1))).unwrap().remaining, 1);
+        assert_eq!(tightest(Some(quota(3)), None).unwrap().remaining, 3);
+        assert_eq!(tightest(None, Some(quota(2))).unwrap().remaining, 2);
+        assert!(tightest(None, None).is_none());
+    }
+
+    #[test]
+    fn prune_keeps_only_partially_used_buckets() {
+        let limiter = RateLimiter::default();
+        let slow = [rule(RateLimitScope::Function, 0.001, Some(2))];
+        let fast = [rule(RateLimitScope::Function, 1e9, Some(2))];
+        check(&limiter, &key("slow"), &slow, &RequestMeta::default()).unwrap();
+        check(&limiter, &key("fast"), &fast, &RequestMeta::default()).unwrap();
+        std::thread::sleep(Duration::from_millis(1));
+        assert_eq!(limiter.prune(), 1);
+        assert_eq!(limiter.buckets.lock().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,149 @@
+use crate::{config::Config, AppState};
+use std::{
+    fs,
+    path::Path,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+use tokio::signal::unix::{signal, SignalKind};
+
+/// How often the config file is checked for changes.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Reloads the config file on `SIGHUP` and whenever it changes on disk.
+///
+/// Function settings (limits, timeouts, backends, rate limits) apply to the
+/// next request. A file that fails to parse or validate is logged and the
+/// previous config stays in effect.
+pub fn spawn_config_reload(state: Arc<AppState>) {
+    let Some(path) = state.cli.config.clone() else {
+        return;
+    };
+
+    match signal(SignalKind::hangup()) {
+        Ok(mut hangup) => {
+            let state = state.clone();
+            tokio::spawn(async move {
+                while hangup.recv().await.is_some() {
+                    reload(&state, "SIGHUP");
+                }
+            });
+        }
+        Err(err) => tracing::warn!("Failed to install SIGHUP handler: {}", err),
+    }
+
+    tokio::spawn(async move {
+        let mut last_seen = file_stamp(&path);
+        let mut interval = tokio::time::interval(POLL_INTERVAL);
+        loop {
+            interval.tick().await;
+            let stamp = file_stamp(&path);
+            if stamp != last_seen {
+                last_seen = stamp;
+                if stamp.is_some() {
+                    reload(&state, "file changed");
+                }
+            }
+        }
+    });
+}
+
+pub fn file_stamp(path: &Path) -> Option<(SystemTime, u64)> {
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+fn reload(state: &AppState, reason: &str) {
+    let config = match Config::load(&state.cli) {
+        Ok(config) => config,
+        Err(err) => {
+            tracing::error!(
+                "Config reload ({}) failed, keeping the current config: {}",
+                reason,
+                err
+            );
+            return;
+        }
+    };
+
+    let current = state.config();
+    let ignored = restart_only_changes(&current, &config);
+    if !ignored.is_empty() {
+        tracing::warn!(
+            "Config changes to {} take effect after a restart",
+            ignored.join(", ")
+        );
+    }
+
+    // Edited splits start over, bringing rolled back canaries back in.
+    for (name, split) in &current.splits {
+        if config.splits.get(name) != Some(split) {
+            state.traffic.reset(name);
+        }
+    }
+
+    if config.tls.certificates != current.tls.certificates {
+        if let Err(err) = state.tls.load(&config.tls) {
+            tracing::error!(
+                "TLS certificate reload failed, keeping the current certificates: {}",
+                err
+            );
+        }
+    }
+
+    *state.config.write().unwrap() = Arc::new(config);
+    tracing::info!("Config reloaded ({})", reason);
+}
+
+/// Settings baked into listeners and shared pools at startup.
+fn restart_only_changes(current: &Config, new: &Config) -> Vec<&'static str> {
+    let mut changed = Vec::new();
+    if current.server.listen != new.server.listen {
+        changed.push("server.listen");
+    }
+    if current.server.reuse_port != new.server.reuse_port {
+        changed.push("server.reuse_port");
+    }
+    if current.server.trusted_proxies != new.server.trusted_proxies {
+        changed.push("server.trusted_proxies");
+    }
+    if current.server.max_concurrency != new.server.max_concurrency {
+        changed.push("server.max_concurrency");
+    }
+    if current.server.max_queue != new.server.max_queue {
+        changed.push("server.max_queue");
+    }
+    if current.server.retry_after_secs != new.server.retry_after_secs {
+        changed.push("server.retry_after_secs");
+    }
+    if current.logging != new.logging {
+        changed.push("logging");
+    }
+    if current.library_cache != new.library_cache {
+        changed.push("library_cache");
+    }
+    if current.cache.dir != new.cache.dir {
+        changed.push("cache.dir");
+    }
+    if current.metrics != new.metrics {
+        changed.push("metrics");
+    }
+    if current.health.enabled != new.health.enabled
+        || current.health.liveness_path != new.health.liveness_path
+        || current.health.readiness_path != new.health.readiness_path
+    {
+        changed.push("health");
+    }
+    if current.discovery.enabled != new.discovery.enabled
+        || current.discovery.interval_secs != new.discovery.interval_secs
+    {
+        changed.push("discovery");
+    }
+    if current.admin.listen != new.admin.listen {
+        changed.push("admin.listen");
+    }
+    if current.tls.listen != new.tls.listen {
+        changed.push("tls.listen");
+    }
+    changed
+}
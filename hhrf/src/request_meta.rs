@@ -0,0 +1,251 @@
+use crate::{auth::Identity, AppState};
+use axum::{
+    extract::{ConnectInfo, OriginalUri, Request, State},
+    http::{HeaderMap, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use fezz_sdk::FezzWireMeta;
+use ipnet::IpNet;
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
+
+const REQUEST_ID_HEADER: &str = "x-request-id";
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// Per-request metadata forwarded to functions through `FezzWireMeta`.
+#[derive(Clone, Debug)]
+pub struct RequestMeta {
+    pub trace_id: String,
+    pub client_ip: Option<IpAddr>,
+    /// Set once the function's `auth` config has been satisfied.
+    pub identity: Identity,
+}
+
+impl Default for RequestMeta {
+    fn default() -> Self {
+        Self {
+            trace_id: new_trace_id(),
+            client_ip: None,
+            identity: Identity::default(),
+        }
+    }
+}
+
+impl RequestMeta {
+    /// Builds the wire meta; `deadline_ms` is the remaining execution budget.
+    pub fn to_wire(&self, deadline_ms: u64) -> FezzWireMeta {
+        FezzWireMeta {
+            trace_id: Some(self.trace_id.clone()),
+            deadline_ms: Some(deadline_ms),
+            client_ip: self.client_ip.map(|ip| ip.to_string()),
+            principal: self.identity.principal.clone(),
+            claims: self.identity.claims.clone(),
+        }
+    }
+}
+
+/// Parses an IP address or CIDR (`10.0.0.0/8`, `127.0.0.1`).
+pub fn parse_net(value: &str) -> Option<IpNet> {
+    value
+        .parse::<IpNet>()
+        .ok()
+        .or_else(|| value.parse::<IpAddr>().ok().map(IpNet::from))
+}
+
+/// Middleware that attaches a [`RequestMeta`] to every request and echoes the
+/// trace id back as `x-request-id`.
+///
+/// It also keeps the request target as the client sent it in [`OriginalUri`],
+/// before host routes and version resolution rewrite it to
+/// `/rpc/{org}/{func}/{version}`; HMAC signatures cover that target.
+pub async fn layer(State(state): State<Arc<AppState>>, mut req: Request, next: Next) -> Response {
+    let peer = req
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip());
+    let meta = RequestMeta {
+        trace_id: trace_id(req.headers()),
+        client_ip: peer.map(|peer| client_ip(peer, req.headers(), &state.trusted_proxies)),
+        identity: Identity::default(),
+    };
+    let echo = HeaderValue::from_str(&meta.trace_id).ok();
+    req.extensions_mut().insert(meta);
+    keep_original_uri(&mut req);
+
+    let mut resp = next.run(req).await;
+    if let Some(echo) = echo {
+        resp.headers_mut().insert(REQUEST_ID_HEADER, echo);
+    }
+    resp
+}
+
+/// Stores the request target as received, unless a router already did.
+pub fn keep_original_uri<B>(req: &mut axum::http::Request<B>) {
+    if req.extensions().get::<OriginalUri>().is_none() {
+        let original = OriginalUri(req.uri().clone());
+        req.extensions_mut().insert(original);
+    }
+}
+
+/// Resolves the trace id: W3C `traceparent` first, then `x-request-id`,
+/// otherwise a freshly generated W3C-compatible id.
+fn trace_id(headers: &HeaderMap) -> String {
+    if let Some(trace_id) = headers
+        .get("traceparent")
+        .and_then(|value| value.to_str().ok())
+        .and_then(parse_traceparent)
+    {
+        return trace_id;
+    }
+
+    if let Some(request_id) = headers
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::trim)
+        .filter(|value| {
+            !value.is_empty()
+                && value.len() <= MAX_REQUEST_ID_LEN
+                && value.bytes().all(|b| b.is_ascii_graphic())
+        })
+    {
+        return request_id.to_string();
+    }
+
+    new_trace_id()
+}
+
+/// Extracts the trace-id field of a `traceparent` header
+/// (`version-traceid-parentid-flags`).
+fn parse_traceparent(value: &str) -> Option<String> {
+    let mut fields = value.trim().split('-');
+    let version = fields.next()?;
+    let trace_id = fields.next()?;
+    let parent_id = fields.next()?;
+    let flags = fields.next()?;
+
+    let is_hex = |field: &str, len: usize| {
+        field.len() == len && field.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+    };
+    if !is_hex(version, 2) || version == "ff" || !is_hex(parent_id, 16) || !is_hex(flags, 2) {
+        return None;
+    }
+    if !is_hex(trace_id, 32) || trace_id.bytes().all(|b| b == b'0') {
+        return None;
+    }
+    Some(trace_id.to_string())
+}
+
+fn new_trace_id() -> String {
+    uuid::Uuid::new_v4().simple().to_string()
+}
+
+/// Resolves the client address.
+///
+/// `X-Forwarded-For` is only honoured when the peer is a trusted proxy; the
+/// chain is walked right to left and the first untrusted hop is the client.
+/// A hop that is not an IP address ends the walk at the last trusted one,
+/// since nothing left of it can be attributed.
+fn client_ip(peer: IpAddr, headers: &HeaderMap, trusted: &[IpNet]) -> IpAddr {
+    let is_trusted = |ip: &IpAddr| trusted.iter().any(|net| net.contains(ip));
+    if !is_trusted(&peer) {
+        return peer;
+    }
+
+    let hops = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .flat_map(|value| value.to_str().unwrap_or("").split(','))
+        .collect::<Vec<_>>();
+
+    let mut last_trusted = peer;
+    for hop in hops.iter().rev() {
+        let Ok(hop) = hop.trim().parse::<IpAddr>() else {
+            break;
+        };
+        if !is_trusted(&hop) {
+            return hop;
+        }
+        last_trusted = hop;
+    }
+    last_trusted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn forwarded(values: &[&str]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for value in values {
+            headers.append("x-forwarded-for", HeaderValue::from_str(value).unwrap());
+        }
+        headers
+    }
+
+    fn ip(value: &str) -> IpAddr {
+        value.parse().unwrap()
+    }
+
+    fn trusted() -> Vec<IpNet> {
+        ["10.0.0.0/8", "192.0.2.7"].into_iter().filter_map(parse_net).collect()
+    }
+
+    #[test]
+    fn untrusted_peer_is_the_client() {
+        let headers = forwarded(&["198.51.100.1"]);
+        assert_eq!(client_ip(ip("203.0.113.9"), &headers, &trusted()), ip("203.0.113.9"));
+    }
+
+    #[test]
+    fn first_untrusted_hop_from_the_right_is_the_client() {
+        let headers = forwarded(&["198.51.100.66, 198.51.100.1", "192.0.2.7, 10.0.0.2"]);
+        assert_eq!(client_ip(ip("10.0.0.1"), &headers, &trusted()), ip("198.51.100.1"));
+        let all_trusted = forwarded(&["10.0.0.3, 192.0.2.7"]);
+        assert_eq!(client_ip(ip("10.0.0.1"), &all_trusted, &trusted()), ip("10.0.0.3"));
+        assert_eq!(client_ip(ip("10.0.0.1"), &HeaderMap::new(), &trusted()), ip("10.0.0.1"));
+    }
+
+    #[test]
+    fn unparseable_hop_stops_at_the_last_trusted_one() {
+        // Without stopping, the spoofed address left of the garbage would win.
+        let headers = forwarded(&["198.51.100.66, unknown, 10.0.0.2"]);
+        assert_eq!(client_ip(ip("10.0.0.1"), &headers, &trusted()), ip("10.0.0.2"));
+        let headers = forwarded(&["198.51.100.66", "not-an-ip"]);
+        assert_eq!(client_ip(ip("10.0.0.1"), &headers, &trusted()), ip("10.0.0.1"));
+    }
+
+    #[test]
+    fn traceparent_yields_the_trace_id() {
+        let trace_id = "4bf92f3577b34da6a3ce929d0e0e4736";
+        let header = format!("00-{}-00f067aa0ba902b7-01", trace_id);
+        assert_eq!(parse_traceparent(&header).as_deref(), Some(trace_id));
+        assert_eq!(parse_traceparent(&format!(" {} ", header)).as_deref(), Some(trace_id));
+    }
+
+    #[test]
+    fn invalid_traceparent_is_ignored() {
+        for header in [
+            "",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e473-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902-01",
+        ] {
+            assert_eq!(parse_traceparent(header), None, "{}", header);
+        }
+    }
+
+    #[test]
+    fn trace_id_falls_back_to_request_id() {
+        let mut headers = HeaderMap::new();
+        headers.insert("traceparent", HeaderValue::from_static("garbage"));
+        headers.insert(REQUEST_ID_HEADER, HeaderValue::from_static("req-42"));
+        assert_eq!(trace_id(&headers), "req-42");
+        assert_eq!(trace_id(&HeaderMap::new()).len(), 32);
+    }
+}
@@ -0,0 +1,203 @@
+use crate::config::RouteConfig;
+use axum::http::{header::HOST, HeaderMap, Request, Uri};
+
+/// A `[[routes]]` entry matched by host and path.
+pub struct Matched<'a> {
+    pub route: &'a RouteConfig,
+    /// Path (and query) the function sees.
+    pub path_and_query: String,
+}
+
+/// Finds the `[[routes]]` entry for a request.
+///
+/// Exact hosts beat wildcards (`*.acme.example`, any subdomain depth), longer
+/// wildcard suffixes beat shorter ones, and routes without `host` come last.
+/// Among those, the longest `path_prefix` wins. Prefixes match on segment
+/// boundaries: `/todos` matches `/todos` and `/todos/1`, not `/todosx`.
+pub fn find<'a, B>(routes: &'a [RouteConfig], req: &Request<B>) -> Option<Matched<'a>> {
+    let host = request_host(req.headers(), req.uri());
+    let path = req.uri().path();
+
+    let route = routes
+        .iter()
+        .filter_map(|route| {
+            let host_rank = match (&route.host, &host) {
+                (None, _) => 0,
+                (Some(pattern), Some(host)) => host_rank(pattern, host)?,
+                (Some(_), None) => return None,
+            };
+            if !matches_prefix(&route.path_prefix, path) {
+                return None;
+            }
+            Some(((host_rank, route.path_prefix.len()), route))
+        })
+        .max_by_key(|(rank, _)| *rank)
+        .map(|(_, route)| route)?;
+
+    let rest = if route.strip_prefix {
+        &path[route.path_prefix.trim_end_matches('/').len()..]
+    } else {
+        path
+    };
+    let mut path_and_query = if rest.is_empty() {
+        "/".to_string()
+    } else {
+        rest.to_string()
+    };
+    if let Some(query) = req.uri().query() {
+        path_and_query.push('?');
+        path_and_query.push_str(query);
+    }
+    Some(Matched {
+        route,
+        path_and_query,
+    })
+}
+
+/// Whether a `[[routes]]` entry with a `host` matches the request's host.
+pub fn claims_host<B>(routes: &[RouteConfig], req: &Request<B>) -> bool {
+    let Some(host) = request_host(req.headers(), req.uri()) else {
+        return false;
+    };
+    routes
+        .iter()
+        .filter_map(|route| route.host.as_deref())
+        .any(|pattern| host_rank(pattern, &host).is_some())
+}
+
+/// Builds the `/rpc/{org}/{func}/{version}{path}` URI that the regular
+/// handler serves; the version may be a requirement such as `^0.1`.
+pub fn rpc_uri(org: &str, func: &str, version: &str, path_and_query: &str) -> Option<Uri> {
+    let version = version
+        .bytes()
+        .map(|b| {
+            if b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.' | b'~') {
+                (b as char).to_string()
+            } else {
+                format!("%{:02X}", b)
+            }
+        })
+        .collect::<String>();
+    format!("/rpc/{}/{}/{}{}", org, func, version, path_and_query)
+        .parse()
+        .ok()
+}
+
+/// Lowercased host without the port, from `Host` or the URI (HTTP/2).
+pub fn request_host(headers: &HeaderMap, uri: &Uri) -> Option<String> {
+    let host = headers
+        .get(HOST)
+        .and_then(|value| value.to_str().ok())
+        .or_else(|| uri.host())?;
+    // `rsplit_once` leaves a bare IPv6 literal such as `[::1]` intact: its
+    // last "port" is `1]`.
+    let host = match host.rsplit_once(':') {
+        Some((name, port)) if port.bytes().all(|b| b.is_ascii_digit()) => name,
+        _ => host,
+    };
+    Some(host.trim_end_matches('.').to_ascii_lowercase())
+}
+
+/// `None` when `pattern` does not match; higher values are more specific.
+pub fn host_rank(pattern: &str, host: &str) -> Option<usize> {
+    match pattern.strip_prefix("*.") {
+        Some(suffix) => {
+            let subdomain = host.strip_suffix(suffix)?.strip_suffix('.')?;
+            (!subdomain.is_empty()).then_some(suffix.len())
+        }
+        // Exact hosts always outrank wildcards.
+        None => (pattern == host).then_some(usize::MAX),
+    }
+}
+
+fn matches_prefix(prefix: &str, path: &str) -> bool {
+    let prefix = prefix.trim_end_matches('/');
+    match path.strip_prefix(prefix) {
+        Some(rest) => rest.is_empty() || rest.starts_with('/'),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn route(host: Option<&str>, path_prefix: &str, function: &str, strip: bool) -> RouteConfig {
+        RouteConfig {
+            host: host.map(str::to_string),
+            path_prefix: path_prefix.to_string(),
+            function: function.to_string(),
+            strip_prefix: strip,
+        }
+    }
+
+    fn request(host: &str, uri: &str) -> Request<()> {
+        Request::builder().uri(uri).header(HOST, host).body(()).unwrap()
+    }
+
+    fn matched<'a>(routes: &'a [RouteConfig], host: &str, uri: &str) -> Option<&'a str> {
+        find(routes, &request(host, uri)).map(|matched| matched.route.function.as_str())
+    }
+
+    #[test]
+    fn host_rank_prefers_exact_then_longest_wildcard() {
+        assert_eq!(host_rank("api.acme.example", "api.acme.example"), Some(usize::MAX));
+        assert_eq!(host_rank("api.acme.example", "www.acme.example"), None);
+        assert!(host_rank("*.acme.example", "a.b.acme.example").is_some());
+        assert!(host_rank("*.acme.example", "acme.example").is_none());
+        assert!(host_rank("*.acme.example", "evilacme.example").is_none());
+        assert!(host_rank("*.api.acme.example", "v1.api.acme.example")
+            > host_rank("*.acme.example", "v1.api.acme.example"));
+    }
+
+    #[test]
+    fn host_beats_path_and_longest_prefix_wins() {
+        let routes = [
+            route(None, "/", "acme/any/1", false),
+            route(None, "/todos", "acme/todos/1", false),
+            route(Some("*.acme.example"), "/", "acme/wild/1", false),
+            route(Some("api.acme.example"), "/", "acme/api/1", false),
+            route(Some("api.acme.example"), "/todos/", "acme/api-todos/1", false),
+        ];
+        assert_eq!(matched(&routes, "api.acme.example", "/todos/1"), Some("acme/api-todos/1"));
+        assert_eq!(matched(&routes, "API.acme.example:443", "/x"), Some("acme/api/1"));
+        assert_eq!(matched(&routes, "www.acme.example", "/todos"), Some("acme/wild/1"));
+        assert_eq!(matched(&routes, "other.example", "/todos"), Some("acme/todos/1"));
+        assert_eq!(matched(&routes, "other.example", "/todosx"), Some("acme/any/1"));
+    }
+
+    #[test]
+    fn host_routes_need_a_host() {
+        let routes = [route(Some("api.acme.example"), "/", "acme/api/1", false)];
+        let req = Request::builder().uri("/").body(()).unwrap();
+        assert!(find(&routes, &req).is_none());
+        let req = Request::builder().uri("https://api.acme.example./").body(()).unwrap();
+        assert!(find(&routes, &req).is_some());
+    }
+
+    #[test]
+    fn only_host_routes_claim_a_host() {
+        let routes = [
+            route(None, "/", "acme/any/1", false),
+            route(Some("*.acme.example"), "/todos", "acme/todos/1", false),
+        ];
+        assert!(claims_host(&routes, &request("api.acme.example:8443", "/rpc/x")));
+        assert!(!claims_host(&routes, &request("other.example", "/rpc/x")));
+        assert!(!claims_host(&routes, &Request::builder().uri("/").body(()).unwrap()));
+    }
+
+    #[test]
+    fn strip_prefix_keeps_query() {
+        let routes = [route(None, "/api/", "acme/api/1", true)];
+        let found = find(&routes, &request("a.example", "/api/todos?done=1")).unwrap();
+        assert_eq!(found.path_and_query, "/todos?done=1");
+        let found = find(&routes, &request("a.example", "/api")).unwrap();
+        assert_eq!(found.path_and_query, "/");
+    }
+
+    #[test]
+    fn rpc_uri_escapes_requirements() {
+        let uri = rpc_uri("acme", "todos", "^0.1", "/x?y=1").unwrap();
+        assert_eq!(uri, "/rpc/acme/todos/%5E0.1/x?y=1");
+    }
+}
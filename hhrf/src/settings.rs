@@ -0,0 +1,65 @@
+use crate::{
+    config::{AuthConfig, Config, CorsConfig, RateLimitConfig},
+    exec::ExecBackend,
+    FunctionKey,
+};
+use std::time::Duration;
+
+/// Default execution budget when no config level sets `timeout_ms`.
+const DEFAULT_TIMEOUT_MS: u64 = 30_000;
+/// Default request body limit when no config level sets `max_body_bytes`.
+const DEFAULT_MAX_BODY_BYTES: u64 = 10 * 1024 * 1024;
+/// Default response limit when no config level sets `max_response_bytes`.
+const DEFAULT_MAX_RESPONSE_BYTES: u64 = 10 * 1024 * 1024;
+const DEFAULT_MAX_CONCURRENCY: usize = 64;
+const DEFAULT_MAX_QUEUE: usize = 128;
+const DEFAULT_QUEUE_TIMEOUT_MS: u64 = 5_000;
+
+/// Effective per-function settings resolved from the config.
+#[derive(Clone, Debug)]
+pub struct FunctionSettings {
+    pub backend: ExecBackend,
+    pub timeout: Duration,
+    /// Request body limit, already clamped to `server.max_body_bytes`.
+    pub max_body_bytes: u64,
+    /// Response size limit, already clamped to `server.max_response_bytes`.
+    pub max_response_bytes: u64,
+    pub max_concurrency: usize,
+    pub max_queue: usize,
+    pub queue_timeout: Duration,
+    pub rate_limits: Vec<RateLimitConfig>,
+    pub auth: AuthConfig,
+    pub compress_responses: bool,
+    pub decompress_requests: bool,
+    pub cache: bool,
+    pub cors: Option<CorsConfig>,
+}
+
+impl FunctionSettings {
+    pub fn resolve(config: &Config, key: &FunctionKey) -> Self {
+        let function = config.function(key);
+        Self {
+            backend: function.backend.unwrap_or(ExecBackend::Runner),
+            timeout: Duration::from_millis(function.timeout_ms.unwrap_or(DEFAULT_TIMEOUT_MS)),
+            max_body_bytes: function
+                .max_body_bytes
+                .unwrap_or(DEFAULT_MAX_BODY_BYTES)
+                .min(config.server.max_body_bytes),
+            max_response_bytes: function
+                .max_response_bytes
+                .unwrap_or(DEFAULT_MAX_RESPONSE_BYTES)
+                .min(config.server.max_response_bytes),
+            max_concurrency: function.max_concurrency.unwrap_or(DEFAULT_MAX_CONCURRENCY),
+            max_queue: function.max_queue.unwrap_or(DEFAULT_MAX_QUEUE),
+            queue_timeout: Duration::from_millis(
+                function.queue_timeout_ms.unwrap_or(DEFAULT_QUEUE_TIMEOUT_MS),
+            ),
+            rate_limits: function.rate_limits.unwrap_or_default(),
+            auth: function.auth.unwrap_or_default(),
+            compress_responses: function.compress_responses.unwrap_or(true),
+            decompress_requests: function.decompress_requests.unwrap_or(true),
+            cache: function.cache.unwrap_or(true),
+            cors: function.cors,
+        }
+    }
+}
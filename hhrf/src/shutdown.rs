@@ -0,0 +1,68 @@
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    sync::watch,
+};
+
+/// Where HHRF is in its shutdown sequence.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Phase {
+    Running,
+    /// Listeners are closed; in-flight requests may still finish.
+    Draining,
+    /// The drain deadline passed; runner children and JS isolates are killed.
+    Aborting,
+}
+
+/// Broadcasts the shutdown [`Phase`] to listeners and in-flight invocations.
+pub struct Shutdown {
+    phase: watch::Sender<Phase>,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self {
+            phase: watch::Sender::new(Phase::Running),
+        }
+    }
+}
+
+impl Shutdown {
+    pub fn advance(&self, phase: Phase) {
+        self.phase.send_if_modified(|current| {
+            if *current < phase {
+                *current = phase;
+                true
+            } else {
+                false
+            }
+        });
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<Phase> {
+        self.phase.subscribe()
+    }
+}
+
+/// Resolves once `phase` (or a later one) is reached.
+pub async fn reached(mut receiver: watch::Receiver<Phase>, phase: Phase) {
+    // An error means the sender is gone, i.e. the process is exiting anyway.
+    let _ = receiver.wait_for(|current| *current >= phase).await;
+}
+
+/// Waits for `SIGTERM` or `SIGINT` and returns its name.
+pub async fn signal_received() -> &'static str {
+    let mut terminate = match signal(SignalKind::terminate()) {
+        Ok(terminate) => terminate,
+        Err(err) => {
+            tracing::warn!("Failed to install SIGTERM handler: {}", err);
+            return match tokio::signal::ctrl_c().await {
+                Ok(()) => "SIGINT",
+                Err(_) => std::future::pending().await,
+            };
+        }
+    };
+    tokio::select! {
+        _ = terminate.recv() => "SIGTERM",
+        _ = tokio::signal::ctrl_c() => "SIGINT",
+    }
+}
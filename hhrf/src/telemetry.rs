@@ -0,0 +1,203 @@
+use crate::{
+    config::{LogFormat, LoggingConfig},
+    function_logs::{Capture, Stream},
+};
+use axum::http::HeaderMap;
+use serde_json::Value;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, BufReader};
+use tracing::Span;
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Layer};
+
+/// Longest runner output line handled as one; longer lines are split.
+const MAX_OUTPUT_LINE: u64 = 8 * 1024;
+
+/// Installs the global subscriber: `RUST_LOG` (or `logging.level`) filter,
+/// the selected output format and, with the `otlp` feature, a span exporter.
+pub fn init(logging: &LoggingConfig) -> Result<(), String> {
+    let filter =
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(&logging.level));
+    let output = match logging.format {
+        LogFormat::Pretty => tracing_subscriber::fmt::layer().pretty().boxed(),
+        LogFormat::Compact => tracing_subscriber::fmt::layer().compact().boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::layer()
+            .json()
+            .with_current_span(true)
+            .with_span_list(false)
+            .boxed(),
+    };
+
+    tracing_subscriber::registry()
+        .with(output)
+        .with(otlp::layer(logging)?)
+        .with(filter)
+        .init();
+    Ok(())
+}
+
+/// Flushes spans still buffered for the collector.
+pub fn shutdown() {
+    otlp::shutdown();
+}
+
+/// Makes the incoming W3C `traceparent` the parent of `span` in the exported
+/// trace, so HHRF spans join the caller's trace.
+pub fn link_parent(span: &Span, headers: &HeaderMap) {
+    otlp::link_parent(span, headers);
+}
+
+/// Re-emits the `tracing` JSON events `fezz-runner` writes to its dedicated
+/// log fd in the current span, with their level and message.
+pub async fn forward_runner_log<R: AsyncRead + Unpin>(log: R) {
+    for_each_line(log, |line| {
+        if !emit_runner_event(line.trim_end()) {
+            tracing::info!(target: "fezz_runner", "{}", line.trim_end());
+        }
+    })
+    .await;
+}
+
+/// Sends everything a runner prints on stdout/stderr, i.e. the plugin's
+/// `println!` and `eprintln!`, to the invocation's `capture`. It is
+/// untrusted text, never parsed as a runner event.
+pub async fn forward_stdio<R: AsyncRead + Unpin>(stdio: R, capture: Capture) {
+    for_each_line(stdio, |line| capture.push(Stream::Stdio, "info", line)).await;
+}
+
+async fn for_each_line<R: AsyncRead + Unpin>(reader: R, mut f: impl FnMut(&str)) {
+    let mut reader = BufReader::new(reader);
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        match (&mut reader)
+            .take(MAX_OUTPUT_LINE)
+            .read_until(b'\n', &mut line)
+            .await
+        {
+            Ok(0) => return,
+            Ok(_) => f(&String::from_utf8_lossy(&line)),
+            Err(err) => {
+                tracing::warn!("Failed to read runner output: {}", err);
+                return;
+            }
+        }
+    }
+}
+
+/// Returns `false` if `line` is not an event logged by `fezz-runner` itself.
+fn emit_runner_event(line: &str) -> bool {
+    let Ok(Value::Object(event)) = serde_json::from_str::<Value>(line) else {
+        return false;
+    };
+    let from_runner = event
+        .get("target")
+        .and_then(Value::as_str)
+        .is_some_and(|target| target.starts_with("fezz_runner"));
+    if !from_runner {
+        return false;
+    }
+    let level = event.get("level").and_then(Value::as_str).unwrap_or("INFO");
+    let fields = event.get("fields").and_then(Value::as_object);
+    let message = fields
+        .and_then(|fields| fields.get("message"))
+        .and_then(Value::as_str)
+        .unwrap_or(line);
+    let extra = fields
+        .map(|fields| {
+            fields
+                .iter()
+                .filter(|(name, _)| name.as_str() != "message")
+                .map(|(name, value)| format!("{}={}", name, value))
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+        .unwrap_or_default();
+
+    match level {
+        "ERROR" => tracing::error!(target: "fezz_runner", fields = %extra, "{}", message),
+        "WARN" => tracing::warn!(target: "fezz_runner", fields = %extra, "{}", message),
+        "DEBUG" => tracing::debug!(target: "fezz_runner", fields = %extra, "{}", message),
+        "TRACE" => tracing::trace!(target: "fezz_runner", fields = %extra, "{}", message),
+        _ => tracing::info!(target: "fezz_runner", fields = %extra, "{}", message),
+    }
+    true
+}
+
+#[cfg(feature = "otlp")]
+mod otlp {
+    use crate::config::LoggingConfig;
+    use axum::http::HeaderMap;
+    use opentelemetry::{
+        global,
+        propagation::{Extractor, TextMapPropagator},
+        trace::TracerProvider as _,
+        KeyValue,
+    };
+    use opentelemetry_otlp::WithExportConfig;
+    use opentelemetry_sdk::{
+        propagation::TraceContextPropagator, runtime, trace::TracerProvider, Resource,
+    };
+    use tracing::{Span, Subscriber};
+    use tracing_opentelemetry::OpenTelemetrySpanExt;
+    use tracing_subscriber::{registry::LookupSpan, Layer};
+
+    pub fn layer<S>(logging: &LoggingConfig) -> Result<Option<impl Layer<S>>, String>
+    where
+        S: Subscriber + for<'span> LookupSpan<'span>,
+    {
+        let Some(endpoint) = &logging.otlp_endpoint else {
+            return Ok(None);
+        };
+        let exporter = opentelemetry_otlp::SpanExporter::builder()
+            .with_tonic()
+            .with_endpoint(endpoint)
+            .build()
+            .map_err(|e| format!("Failed to create OTLP exporter for '{}': {}", endpoint, e))?;
+        let provider = TracerProvider::builder()
+            .with_batch_exporter(exporter, runtime::Tokio)
+            .with_resource(Resource::new([KeyValue::new("service.name", "hhrf")]))
+            .build();
+        let tracer = provider.tracer("hhrf");
+        global::set_tracer_provider(provider);
+        Ok(Some(tracing_opentelemetry::layer().with_tracer(tracer)))
+    }
+
+    pub fn shutdown() {
+        global::shutdown_tracer_provider();
+    }
+
+    pub fn link_parent(span: &Span, headers: &HeaderMap) {
+        let parent = TraceContextPropagator::new().extract(&HeaderExtractor(headers));
+        span.set_parent(parent);
+    }
+
+    struct HeaderExtractor<'a>(&'a HeaderMap);
+
+    impl Extractor for HeaderExtractor<'_> {
+        fn get(&self, key: &str) -> Option<&str> {
+            self.0.get(key).and_then(|value| value.to_str().ok())
+        }
+
+        fn keys(&self) -> Vec<&str> {
+            self.0.keys().map(|name| name.as_str()).collect()
+        }
+    }
+}
+
+#[cfg(not(feature = "otlp"))]
+mod otlp {
+    use crate::config::LoggingConfig;
+    use axum::http::HeaderMap;
+    use tracing::{Span, Subscriber};
+    use tracing_subscriber::{layer::Identity, registry::LookupSpan, Layer};
+
+    pub fn layer<S>(_logging: &LoggingConfig) -> Result<Option<impl Layer<S>>, String>
+    where
+        S: Subscriber + for<'span> LookupSpan<'span>,
+    {
+        Ok(None::<Identity>)
+    }
+
+    pub fn shutdown() {}
+
+    pub fn link_parent(_span: &Span, _headers: &HeaderMap) {}
+}
@@ -0,0 +1,440 @@
+use crate::{
+    config::{TlsCertificate, TlsConfig},
+    reload::file_stamp,
+    routes,
+    shutdown::{self, Phase},
+    AppState,
+};
+use axum::{extract::ConnectInfo, http::Request, Router};
+use hyper::body::Incoming;
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo},
+    server::{conn::auto, graceful::GracefulShutdown},
+    service::TowerToHyperService,
+};
+use rustls::{
+    crypto::ring,
+    server::{ClientHello, ResolvesServerCert},
+    sign::CertifiedKey,
+    ServerConfig,
+};
+use std::{
+    fs::File,
+    io::{self, BufReader},
+    net::SocketAddr,
+    path::Path,
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, SystemTime},
+};
+use tokio::{net::TcpListener, sync::watch};
+use tokio_rustls::TlsAcceptor;
+use tower::ServiceExt;
+
+/// How often certificate and key files are checked for changes.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Clients that don't finish the handshake in time are dropped.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Certificates from `[tls] certificates`, picked by SNI.
+///
+/// Reloading swaps the whole set; established connections keep the
+/// certificate they were handshaken with.
+#[derive(Debug, Default)]
+pub struct CertResolver {
+    certs: RwLock<Vec<(Vec<String>, Arc<CertifiedKey>)>>,
+    /// Cert and key file stamps of the last load, to notice changes.
+    stamps: Mutex<Vec<Option<(SystemTime, u64)>>>,
+}
+
+impl CertResolver {
+    /// Loads every certificate of `config`, replacing the current set only
+    /// when all of them load.
+    pub fn load(&self, config: &TlsConfig) -> Result<(), String> {
+        *self.stamps.lock().unwrap() = stamps(config);
+        let certs = config
+            .certificates
+            .iter()
+            .map(|certificate| {
+                let key = certified_key(certificate)?;
+                Ok((certificate.hosts.clone(), Arc::new(key)))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        *self.certs.write().unwrap() = certs;
+        Ok(())
+    }
+
+    /// Reloads `config` if a cert or key file changed since the last load;
+    /// `None` when nothing changed. A failed reload keeps the current set and
+    /// is not retried until the files change again.
+    fn reload_if_changed(&self, config: &TlsConfig) -> Option<Result<(), String>> {
+        if *self.stamps.lock().unwrap() == stamps(config) {
+            return None;
+        }
+        Some(self.load(config))
+    }
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        let certs = self.certs.read().unwrap();
+        let fallback = certs.first().map(|(_, key)| key.clone());
+        let Some(name) = hello.server_name() else {
+            return fallback;
+        };
+        let name = name.trim_end_matches('.').to_ascii_lowercase();
+
+        // Same precedence as `[[routes]]` hosts; earlier entries win ties.
+        let mut best: Option<(usize, &Arc<CertifiedKey>)> = None;
+        for (hosts, key) in certs.iter() {
+            let Some(rank) = hosts
+                .iter()
+                .filter_map(|host| routes::host_rank(host, &name))
+                .max()
+            else {
+                continue;
+            };
+            if !best.is_some_and(|(best_rank, _)| rank <= best_rank) {
+                best = Some((rank, key));
+            }
+        }
+        best.map(|(_, key)| key.clone()).or(fallback)
+    }
+}
+
+/// rustls config for HTTPS listeners; ALPN prefers h2 over HTTP/1.1.
+pub fn server_config(resolver: Arc<CertResolver>) -> Result<Arc<ServerConfig>, String> {
+    let mut config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(|e| format!("Failed to set up TLS: {}", e))?
+        .with_no_client_auth()
+        .with_cert_resolver(resolver);
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(Arc::new(config))
+}
+
+/// Reloads certificates when their files change on disk. Config reloads
+/// that edit `[tls] certificates` go through [`crate::reload`] instead.
+pub fn spawn_cert_reload(state: Arc<AppState>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(POLL_INTERVAL);
+        loop {
+            interval.tick().await;
+            match state.tls.reload_if_changed(&state.config().tls) {
+                None => {}
+                Some(Ok(())) => tracing::info!("TLS certificates reloaded (file changed)"),
+                Some(Err(err)) => tracing::error!(
+                    "TLS certificate reload failed, keeping the current certificates: {}",
+                    err
+                ),
+            }
+        }
+    });
+}
+
+/// Accepts TLS connections on `listener` and serves `app` over HTTP/1.1 or
+/// h2, whichever ALPN settled on.
+///
+/// Stops accepting once shutdown starts draining and returns when every
+/// open connection has finished its in-flight requests.
+pub async fn serve(
+    listener: TcpListener,
+    acceptor: TlsAcceptor,
+    app: Router,
+    shutdown: watch::Receiver<Phase>,
+) -> io::Result<()> {
+    let graceful = GracefulShutdown::new();
+    let builder = auto::Builder::new(TokioExecutor::new());
+    let draining = shutdown::reached(shutdown, Phase::Draining);
+    tokio::pin!(draining);
+
+    loop {
+        let (stream, peer) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(err) => {
+                    // Usually out of file descriptors; back off like `axum::serve`.
+                    tracing::warn!("Failed to accept TLS connection: {}", err);
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                    continue;
+                }
+            },
+            () = &mut draining => break,
+        };
+
+        let acceptor = acceptor.clone();
+        let app = app.clone();
+        let builder = builder.clone();
+        let watcher = graceful.watcher();
+        tokio::spawn(async move {
+            let handshake = tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream));
+            let stream = match handshake.await {
+                Ok(Ok(stream)) => stream,
+                Ok(Err(err)) => {
+                    tracing::debug!("TLS handshake with {} failed: {}", peer, err);
+                    return;
+                }
+                Err(_) => {
+                    tracing::debug!("TLS handshake with {} timed out", peer);
+                    return;
+                }
+            };
+            // What `into_make_service_with_connect_info` does for plain listeners.
+            let service = app.map_request(move |mut req: Request<Incoming>| {
+                req.extensions_mut().insert(ConnectInfo::<SocketAddr>(peer));
+                req
+            });
+            let connection = builder
+                .serve_connection_with_upgrades(
+                    TokioIo::new(stream),
+                    TowerToHyperService::new(service),
+                )
+                .into_owned();
+            if let Err(err) = watcher.watch(connection).await {
+                tracing::debug!("TLS connection with {} failed: {}", peer, err);
+            }
+        });
+    }
+
+    drop(listener);
+    graceful.shutdown().await;
+    Ok(())
+}
+
+fn certified_key(certificate: &TlsCertificate) -> Result<CertifiedKey, String> {
+    let chain = rustls_pemfile::certs(&mut open(&certificate.cert)?)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Invalid certificate '{}': {}", certificate.cert.display(), e))?;
+    if chain.is_empty() {
+        return Err(format!(
+            "No certificate found in '{}'",
+            certificate.cert.display()
+        ));
+    }
+    let key = rustls_pemfile::private_key(&mut open(&certificate.key)?)
+        .map_err(|e| format!("Invalid private key '{}': {}", certificate.key.display(), e))?
+        .ok_or_else(|| format!("No private key found in '{}'", certificate.key.display()))?;
+    let key = ring::sign::any_supported_type(&key).map_err(|e| {
+        format!(
+            "Unsupported private key '{}': {}",
+            certificate.key.display(),
+            e
+        )
+    })?;
+    Ok(CertifiedKey::new(chain, key))
+}
+
+fn open(path: &Path) -> Result<BufReader<File>, String> {
+    File::open(path)
+        .map(BufReader::new)
+        .map_err(|e| format!("Failed to open '{}': {}", path.display(), e))
+}
+
+fn stamps(config: &TlsConfig) -> Vec<Option<(SystemTime, u64)>> {
+    config
+        .certificates
+        .iter()
+        .flat_map(|certificate| [file_stamp(&certificate.cert), file_stamp(&certificate.key)])
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustls::{
+        client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+        pki_types::{CertificateDer, ServerName, UnixTime},
+        ClientConfig, DigitallySignedStruct, SignatureScheme,
+    };
+    use std::fs;
+    use tokio_rustls::TlsConnector;
+
+    /// Accepts whatever the server presents; the tests inspect it instead.
+    #[derive(Debug)]
+    struct AcceptAny;
+
+    impl ServerCertVerifier for AcceptAny {
+        fn verify_server_cert(
+            &self,
+            _end_entity: &CertificateDer<'_>,
+            _intermediates: &[CertificateDer<'_>],
+            _server_name: &ServerName<'_>,
+            _ocsp_response: &[u8],
+            _now: UnixTime,
+        ) -> Result<ServerCertVerified, rustls::Error> {
+            Ok(ServerCertVerified::assertion())
+        }
+
+        fn verify_tls12_signature(
+            &self,
+            _message: &[u8],
+            _cert: &CertificateDer<'_>,
+            _dss: &DigitallySignedStruct,
+        ) -> Result<HandshakeSignatureValid, rustls::Error> {
+            Ok(HandshakeSignatureValid::assertion())
+        }
+
+        fn verify_tls13_signature(
+            &self,
+            _message: &[u8],
+            _cert: &CertificateDer<'_>,
+            _dss: &DigitallySignedStruct,
+        ) -> Result<HandshakeSignatureValid, rustls::Error> {
+            Ok(HandshakeSignatureValid::assertion())
+        }
+
+        fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+            ring::default_provider()
+                .signature_verification_algorithms
+                .supported_schemes()
+        }
+    }
+
+    /// A self-signed certificate for `names`, written as PEM into `dir`.
+    /// Returns its config entry and DER encoding.
+    fn certificate(
+        dir: &Path,
+        file: &str,
+        names: &[&str],
+        hosts: &[&str],
+    ) -> (TlsCertificate, Vec<u8>) {
+        let generated = rcgen::generate_simple_self_signed(
+            names.iter().map(|name| name.to_string()).collect::<Vec<_>>(),
+        )
+        .unwrap();
+        let cert = dir.join(format!("{file}.crt"));
+        let key = dir.join(format!("{file}.key"));
+        fs::write(&cert, generated.cert.pem()).unwrap();
+        fs::write(&key, generated.key_pair.serialize_pem()).unwrap();
+        let entry = TlsCertificate {
+            cert,
+            key,
+            hosts: hosts.iter().map(|host| host.to_string()).collect(),
+        };
+        (entry, generated.cert.der().to_vec())
+    }
+
+    fn tls_config(certificates: Vec<TlsCertificate>) -> TlsConfig {
+        TlsConfig {
+            listen: Vec::new(),
+            certificates,
+        }
+    }
+
+    /// Moves the file's mtime forward so a rewrite is noticed even within
+    /// the filesystem's timestamp granularity.
+    fn touch_later(path: &Path) {
+        let modified = fs::metadata(path).unwrap().modified().unwrap();
+        fs::File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(modified + Duration::from_secs(10))
+            .unwrap();
+    }
+
+    /// Handshakes with a server using `resolver`; returns the certificate it
+    /// presented and the negotiated ALPN protocol. An IP address sends no SNI.
+    async fn handshake(
+        resolver: Arc<CertResolver>,
+        server_name: &str,
+        alpn: &[&[u8]],
+    ) -> (Vec<u8>, Option<Vec<u8>>) {
+        let mut client = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(AcceptAny))
+            .with_no_client_auth();
+        client.alpn_protocols = alpn.iter().map(|protocol| protocol.to_vec()).collect();
+
+        let (client_io, server_io) = tokio::io::duplex(64 * 1024);
+        let acceptor = TlsAcceptor::from(server_config(resolver).unwrap());
+        let connector = TlsConnector::from(Arc::new(client));
+        let server_name = ServerName::try_from(server_name.to_string()).unwrap();
+        let (client, server) = tokio::join!(
+            connector.connect(server_name, client_io),
+            acceptor.accept(server_io)
+        );
+        server.unwrap();
+        let client = client.unwrap();
+        let connection = client.get_ref().1;
+        let presented = connection.peer_certificates().unwrap()[0].to_vec();
+        (presented, connection.alpn_protocol().map(<[u8]>::to_vec))
+    }
+
+    #[tokio::test]
+    async fn exact_host_beats_wildcard() {
+        let dir = tempfile::tempdir().unwrap();
+        let (wildcard, wildcard_der) =
+            certificate(dir.path(), "wildcard", &["*.acme.example"], &["*.acme.example"]);
+        let (exact, exact_der) =
+            certificate(dir.path(), "exact", &["api.acme.example"], &["api.acme.example"]);
+        let resolver = Arc::new(CertResolver::default());
+        resolver.load(&tls_config(vec![wildcard, exact])).unwrap();
+
+        let (presented, _) = handshake(resolver.clone(), "api.acme.example", &[]).await;
+        assert_eq!(presented, exact_der);
+        let (presented, _) = handshake(resolver.clone(), "WWW.acme.example", &[]).await;
+        assert_eq!(presented, wildcard_der);
+    }
+
+    #[tokio::test]
+    async fn unmatched_or_missing_sni_gets_first_certificate() {
+        let dir = tempfile::tempdir().unwrap();
+        let (first, first_der) =
+            certificate(dir.path(), "first", &["a.example"], &["a.example"]);
+        let (second, _) = certificate(dir.path(), "second", &["b.example"], &["b.example"]);
+        let resolver = Arc::new(CertResolver::default());
+        resolver.load(&tls_config(vec![first, second])).unwrap();
+
+        let (presented, _) = handshake(resolver.clone(), "unknown.example", &[]).await;
+        assert_eq!(presented, first_der);
+        let (presented, _) = handshake(resolver.clone(), "127.0.0.1", &[]).await;
+        assert_eq!(presented, first_der);
+    }
+
+    #[tokio::test]
+    async fn alpn_prefers_h2_and_falls_back_to_http1() {
+        let dir = tempfile::tempdir().unwrap();
+        let (certificate, _) = certificate(dir.path(), "api", &["api.example"], &["api.example"]);
+        let resolver = Arc::new(CertResolver::default());
+        resolver.load(&tls_config(vec![certificate])).unwrap();
+
+        let (_, alpn) = handshake(resolver.clone(), "api.example", &[b"http/1.1", b"h2"]).await;
+        assert_eq!(alpn.as_deref(), Some(&b"h2"[..]));
+        let (_, alpn) = handshake(resolver.clone(), "api.example", &[b"http/1.1"]).await;
+        assert_eq!(alpn.as_deref(), Some(&b"http/1.1"[..]));
+    }
+
+    #[tokio::test]
+    async fn reload_picks_up_replaced_certificate_and_keeps_it_on_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let (original, original_der) =
+            certificate(dir.path(), "api", &["api.example"], &["api.example"]);
+        let config = tls_config(vec![original.clone()]);
+        let resolver = Arc::new(CertResolver::default());
+        resolver.load(&config).unwrap();
+        assert!(resolver.reload_if_changed(&config).is_none());
+
+        let replaced = tempfile::tempdir().unwrap();
+        let (renewed, renewed_der) =
+            certificate(replaced.path(), "api", &["api.example"], &["api.example"]);
+        fs::copy(&renewed.cert, &original.cert).unwrap();
+        fs::copy(&renewed.key, &original.key).unwrap();
+        touch_later(&original.cert);
+        touch_later(&original.key);
+        assert!(matches!(resolver.reload_if_changed(&config), Some(Ok(()))));
+        let (presented, _) = handshake(resolver.clone(), "api.example", &[]).await;
+        assert_ne!(presented, original_der);
+        assert_eq!(presented, renewed_der);
+
+        fs::write(&original.cert, "not a certificate").unwrap();
+        touch_later(&original.cert);
+        assert!(matches!(resolver.reload_if_changed(&config), Some(Err(_))));
+        let (presented, _) = handshake(resolver.clone(), "api.example", &[]).await;
+        assert_eq!(presented, renewed_der);
+        // The broken files are not retried until they change again.
+        assert!(resolver.reload_if_changed(&config).is_none());
+    }
+}
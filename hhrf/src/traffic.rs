@@ -0,0 +1,336 @@
+use crate::config::{RollbackConfig, SplitConfig};
+use axum::http::{header::COOKIE, HeaderMap};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// The split target picked for one request.
+#[derive(Clone, Debug)]
+pub struct Routed {
+    /// `org/func/{requested version}`, the key in `[splits]`.
+    pub split: String,
+    /// Version (or requirement) from the chosen target.
+    pub target: String,
+}
+
+/// Response extension marking a status the function is responsible for:
+/// what it returned, or its crash. Only these are [recorded]; HHRF's own
+/// 429/503 load shedding, 504 timeouts and other errors are not.
+///
+/// [recorded]: TrafficSplitter::record
+#[derive(Clone, Copy, Debug)]
+pub struct FunctionStatus;
+
+/// 5xx counters of one canary over the current window.
+struct Window {
+    started: Instant,
+    requests: u64,
+    errors: u64,
+}
+
+/// Weighted traffic splitting between versions, with automatic rollback of
+/// canaries whose 5xx rate gets too high.
+///
+/// Rolled back canaries get no traffic until the split's config changes on
+/// reload (see [`TrafficSplitter::reset`]) or HHRF restarts.
+#[derive(Default)]
+pub struct TrafficSplitter {
+    windows: Mutex<HashMap<(String, String), Window>>,
+    rolled_back: Mutex<HashSet<(String, String)>>,
+}
+
+impl TrafficSplitter {
+    /// Picks a target of `split` for a request. With a sticky header or
+    /// cookie present, the same value always lands on the same target (as
+    /// long as the weights don't change); otherwise the pick is random.
+    pub fn route(&self, name: &str, split: &SplitConfig, headers: &HeaderMap) -> Routed {
+        let rolled_back = self.rolled_back.lock().unwrap();
+        let candidates = split
+            .targets
+            .iter()
+            .enumerate()
+            .filter(|(index, target)| {
+                // The baseline is never rolled back.
+                *index == 0 || !rolled_back.contains(&(name.to_string(), target.version.clone()))
+            })
+            .map(|(_, target)| target)
+            .filter(|target| target.weight > 0)
+            .collect::<Vec<_>>();
+        drop(rolled_back);
+
+        let total = candidates
+            .iter()
+            .map(|target| u64::from(target.weight))
+            .sum::<u64>();
+        let target = if total == 0 {
+            &split.targets[0]
+        } else {
+            let mut point = match sticky_value(split, headers) {
+                Some(value) => stable_hash(name, &value) % total,
+                None => (uuid::Uuid::new_v4().as_u128() % u128::from(total)) as u64,
+            };
+            candidates
+                .iter()
+                .find(|target| {
+                    let weight = u64::from(target.weight);
+                    if point < weight {
+                        return true;
+                    }
+                    point -= weight;
+                    false
+                })
+                .copied()
+                .unwrap_or(&split.targets[0])
+        };
+
+        Routed {
+            split: name.to_string(),
+            target: target.version.clone(),
+        }
+    }
+
+    /// Counts the outcome of a routed request against its target. Returns
+    /// `true` when this request tipped the canary over the rollback threshold.
+    pub fn record(&self, routed: &Routed, split: &SplitConfig, status: u16) -> bool {
+        let Some(rollback) = &split.rollback else {
+            return false;
+        };
+        if split
+            .targets
+            .first()
+            .is_some_and(|baseline| baseline.version == routed.target)
+        {
+            return false;
+        }
+
+        let id = (routed.split.clone(), routed.target.clone());
+        let mut windows = self.windows.lock().unwrap();
+        let window = windows.entry(id.clone()).or_insert_with(|| Window {
+            started: Instant::now(),
+            requests: 0,
+            errors: 0,
+        });
+        if window.started.elapsed() >= Duration::from_secs(rollback.window_secs) {
+            *window = Window {
+                started: Instant::now(),
+                requests: 0,
+                errors: 0,
+            };
+        }
+        window.requests += 1;
+        if status >= 500 {
+            window.errors += 1;
+        }
+        if !exceeds(window, rollback) {
+            return false;
+        }
+
+        let (errors, requests) = (window.errors, window.requests);
+        windows.remove(&id);
+        drop(windows);
+        if !self.rolled_back.lock().unwrap().insert(id) {
+            return false;
+        }
+        tracing::warn!(
+            "Rolled back '{}' in split '{}': {} of its last {} requests failed with 5xx",
+            routed.target,
+            routed.split,
+            errors,
+            requests
+        );
+        true
+    }
+
+    /// Forgets rollbacks and counters of `split`, e.g. after its config changed.
+    pub fn reset(&self, split: &str) {
+        self.rolled_back
+            .lock()
+            .unwrap()
+            .retain(|(name, _)| name != split);
+        self.windows
+            .lock()
+            .unwrap()
+            .retain(|(name, _), _| name != split);
+    }
+}
+
+fn exceeds(window: &Window, rollback: &RollbackConfig) -> bool {
+    window.requests >= rollback.min_requests.max(1)
+        && window.errors as f64 / window.requests as f64 > rollback.max_error_rate
+}
+
+fn sticky_value(split: &SplitConfig, headers: &HeaderMap) -> Option<String> {
+    if let Some(name) = &split.sticky_header {
+        if let Some(value) = headers
+            .get(name.as_str())
+            .and_then(|value| value.to_str().ok())
+            .filter(|value| !value.is_empty())
+        {
+            return Some(value.to_string());
+        }
+    }
+
+    let name = split.sticky_cookie.as_deref()?;
+    headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(cookie, value)| *cookie == name && !value.is_empty())
+        .map(|(_, value)| value.to_string())
+}
+
+/// Stable across restarts and hosts, unlike `DefaultHasher`.
+fn stable_hash(split: &str, value: &str) -> u64 {
+    let digest = Sha256::new()
+        .chain_update(split.as_bytes())
+        .chain_update([0])
+        .chain_update(value.as_bytes())
+        .finalize();
+    u64::from_be_bytes(digest[..8].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::SplitTarget;
+    use axum::http::HeaderValue;
+
+    const SPLIT: &str = "acme/todos/stable";
+
+    fn split(weights: &[(&str, u32)], rollback: Option<RollbackConfig>) -> SplitConfig {
+        SplitConfig {
+            targets: weights
+                .iter()
+                .map(|(version, weight)| SplitTarget {
+                    version: version.to_string(),
+                    weight: *weight,
+                })
+                .collect(),
+            sticky_header: Some("x-user".to_string()),
+            sticky_cookie: Some("uid".to_string()),
+            rollback,
+        }
+    }
+
+    fn rollback(max_error_rate: f64, min_requests: u64, window_secs: u64) -> RollbackConfig {
+        RollbackConfig {
+            max_error_rate,
+            min_requests,
+            window_secs,
+        }
+    }
+
+    fn header(name: &'static str, value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(name, HeaderValue::from_str(value).unwrap());
+        headers
+    }
+
+    fn routed(target: &str) -> Routed {
+        Routed {
+            split: SPLIT.to_string(),
+            target: target.to_string(),
+        }
+    }
+
+    /// A sticky user the split sends to `target`.
+    fn user_on(splitter: &TrafficSplitter, split: &SplitConfig, target: &str) -> HeaderMap {
+        (0..1000)
+            .map(|user| header("x-user", &format!("user-{}", user)))
+            .find(|headers| splitter.route(SPLIT, split, headers).target == target)
+            .expect("some user lands on the target")
+    }
+
+    #[test]
+    fn picks_targets_by_weight() {
+        let splitter = TrafficSplitter::default();
+        let split = split(&[("0.1.0", 90), ("0.2.0", 10), ("0.3.0", 0)], None);
+        let mut counts = HashMap::new();
+        for _ in 0..2000 {
+            let routed = splitter.route(SPLIT, &split, &HeaderMap::new());
+            *counts.entry(routed.target).or_insert(0) += 1;
+        }
+        let canary = counts.get("0.2.0").copied().unwrap_or(0);
+        assert!((100..=320).contains(&canary), "canary got {} of 2000", canary);
+        assert!(!counts.contains_key("0.3.0"));
+    }
+
+    #[test]
+    fn sticky_header_and_cookie_keep_their_target() {
+        let splitter = TrafficSplitter::default();
+        let split = split(&[("0.1.0", 50), ("0.2.0", 50)], None);
+        for headers in [
+            header("x-user", "alice"),
+            header("cookie", "theme=dark; uid=alice"),
+        ] {
+            let first = splitter.route(SPLIT, &split, &headers).target;
+            for _ in 0..20 {
+                assert_eq!(splitter.route(SPLIT, &split, &headers).target, first);
+            }
+        }
+
+        let targets = (0..100)
+            .map(|user| splitter.route(SPLIT, &split, &header("x-user", &user.to_string())))
+            .map(|routed| routed.target)
+            .collect::<HashSet<_>>();
+        assert_eq!(targets.len(), 2);
+    }
+
+    #[test]
+    fn rolled_back_canary_gets_no_traffic_until_reset() {
+        let splitter = TrafficSplitter::default();
+        let split = split(&[("0.1.0", 50), ("0.2.0", 50)], Some(rollback(0.5, 2, 60)));
+        let user = user_on(&splitter, &split, "0.2.0");
+
+        assert!(!splitter.record(&routed("0.2.0"), &split, 500));
+        assert!(splitter.record(&routed("0.2.0"), &split, 500));
+        for _ in 0..50 {
+            assert_eq!(splitter.route(SPLIT, &split, &HeaderMap::new()).target, "0.1.0");
+        }
+        assert_eq!(splitter.route(SPLIT, &split, &user).target, "0.1.0");
+
+        splitter.reset("acme/todos/other");
+        assert_eq!(splitter.route(SPLIT, &split, &user).target, "0.1.0");
+        splitter.reset(SPLIT);
+        assert_eq!(splitter.route(SPLIT, &split, &user).target, "0.2.0");
+    }
+
+    #[test]
+    fn baseline_is_never_rolled_back() {
+        let splitter = TrafficSplitter::default();
+        let split = split(&[("0.1.0", 50), ("0.2.0", 50)], Some(rollback(0.0, 1, 60)));
+        for _ in 0..10 {
+            assert!(!splitter.record(&routed("0.1.0"), &split, 500));
+        }
+        let user = user_on(&splitter, &split, "0.1.0");
+        assert_eq!(splitter.route(SPLIT, &split, &user).target, "0.1.0");
+    }
+
+    #[test]
+    fn rollback_waits_for_min_requests_and_the_error_rate() {
+        let splitter = TrafficSplitter::default();
+        let split = split(&[("0.1.0", 50), ("0.2.0", 50)], Some(rollback(0.5, 4, 60)));
+        let canary = routed("0.2.0");
+        assert!(!splitter.record(&canary, &split, 500));
+        assert!(!splitter.record(&canary, &split, 200));
+        assert!(!splitter.record(&canary, &split, 503));
+        // 2 of 4 is not above 50%.
+        assert!(!splitter.record(&canary, &split, 404));
+        assert!(splitter.record(&canary, &split, 500));
+    }
+
+    #[test]
+    fn errors_only_count_within_the_window() {
+        let splitter = TrafficSplitter::default();
+        // A zero-length window starts over on every request.
+        let split = split(&[("0.1.0", 50), ("0.2.0", 50)], Some(rollback(0.1, 2, 0)));
+        for _ in 0..10 {
+            assert!(!splitter.record(&routed("0.2.0"), &split, 500));
+        }
+    }
+}
@@ -0,0 +1,176 @@
+use crate::config::Config;
+use semver::{Version, VersionReq};
+use std::{collections::BTreeMap, fs, path::Path};
+
+/// Response header naming the version that actually served the request.
+pub const VERSION_HEADER: &str = "x-fezz-version";
+
+/// Per-function alias file, next to the version directories.
+const ALIAS_FILE: &str = "aliases.toml";
+
+/// Resolves the `:version` route segment to a deployed version directory.
+///
+/// In order: an exact directory name, `latest` (the highest semver version,
+/// preferring releases over pre-releases), an alias from `[aliases."org/func"]`
+/// or `functions/{org}/{func}/aliases.toml` (config wins), and finally a
+/// semver requirement such as `^0.1` or `~1.2`. Alias targets may be a
+/// version, `latest` or a requirement, but not another alias.
+pub fn resolve(
+    root: &str,
+    config: &Config,
+    org: &str,
+    func: &str,
+    requested: &str,
+) -> Result<String, String> {
+    let func_dir = Path::new(root).join("functions").join(org).join(func);
+    if is_plain_segment(requested) && func_dir.join(requested).is_dir() {
+        return Ok(requested.to_string());
+    }
+
+    let target = match alias(config, &func_dir, org, func, requested) {
+        Some(target) => {
+            if is_plain_segment(&target) && func_dir.join(&target).is_dir() {
+                return Ok(target);
+            }
+            target
+        }
+        None => requested.to_string(),
+    };
+
+    let versions = deployed_versions(&func_dir);
+    let found = if target == "latest" {
+        latest(&versions)
+    } else {
+        let requirement = VersionReq::parse(&target)
+            .map_err(|_| format!("No version of '{}/{}' matches '{}'", org, func, requested))?;
+        versions
+            .iter()
+            .filter(|(version, _)| requirement.matches(version))
+            .max_by(|a, b| a.0.cmp(&b.0))
+    };
+    found
+        .map(|(_, dir)| dir.clone())
+        .ok_or_else(|| format!("No version of '{}/{}' matches '{}'", org, func, requested))
+}
+
+fn alias(
+    config: &Config,
+    func_dir: &Path,
+    org: &str,
+    func: &str,
+    name: &str,
+) -> Option<String> {
+    if let Some(target) = config
+        .aliases
+        .get(&format!("{}/{}", org, func))
+        .and_then(|aliases| aliases.get(name))
+    {
+        return Some(target.clone());
+    }
+
+    let path = func_dir.join(ALIAS_FILE);
+    let contents = fs::read_to_string(&path).ok()?;
+    match toml::from_str::<BTreeMap<String, String>>(&contents) {
+        Ok(mut aliases) => aliases.remove(name),
+        Err(err) => {
+            tracing::warn!("Ignoring invalid alias file '{}': {}", path.display(), err);
+            None
+        }
+    }
+}
+
+/// Version directories whose names parse as semver (a leading `v` is allowed).
+fn deployed_versions(func_dir: &Path) -> Vec<(Version, String)> {
+    let Ok(entries) = fs::read_dir(func_dir) else {
+        return Vec::new();
+    };
+    entries
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_ok_and(|kind| kind.is_dir()))
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter_map(|name| {
+            let version = Version::parse(name.strip_prefix('v').unwrap_or(&name)).ok()?;
+            Some((version, name))
+        })
+        .collect()
+}
+
+fn latest(versions: &[(Version, String)]) -> Option<&(Version, String)> {
+    versions
+        .iter()
+        .filter(|(version, _)| version.pre.is_empty())
+        .max_by(|a, b| a.0.cmp(&b.0))
+        .or_else(|| versions.iter().max_by(|a, b| a.0.cmp(&b.0)))
+}
+
+fn is_plain_segment(value: &str) -> bool {
+    !value.is_empty() && value != "." && value != ".." && !value.contains(['/', '\\'])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A root with `functions/acme/todos/{versions}`.
+    fn root(versions: &[&str]) -> tempfile::TempDir {
+        let root = tempfile::tempdir().unwrap();
+        let func_dir = root.path().join("functions/acme/todos");
+        for version in versions {
+            fs::create_dir_all(func_dir.join(version)).unwrap();
+        }
+        root
+    }
+
+    fn resolve_in(root: &tempfile::TempDir, config: &Config, requested: &str) -> Option<String> {
+        resolve(root.path().to_str().unwrap(), config, "acme", "todos", requested).ok()
+    }
+
+    #[test]
+    fn exact_directory_wins() {
+        let root = root(&["0.1.0", "stable"]);
+        let config = Config::default();
+        assert_eq!(resolve_in(&root, &config, "0.1.0").as_deref(), Some("0.1.0"));
+        assert_eq!(resolve_in(&root, &config, "stable").as_deref(), Some("stable"));
+        assert_eq!(resolve_in(&root, &config, ".."), None);
+    }
+
+    #[test]
+    fn latest_prefers_releases() {
+        let config = Config::default();
+        let root = root(&["0.1.0", "v0.2.0", "0.3.0-beta.1", "stable"]);
+        assert_eq!(resolve_in(&root, &config, "latest").as_deref(), Some("v0.2.0"));
+
+        let pre_only = self::root(&["1.0.0-rc.1", "1.0.0-rc.2"]);
+        assert_eq!(resolve_in(&pre_only, &config, "latest").as_deref(), Some("1.0.0-rc.2"));
+    }
+
+    #[test]
+    fn semver_requirements_pick_highest_match() {
+        let config = Config::default();
+        let root = root(&["0.1.0", "0.1.7", "0.2.0", "1.2.3", "1.3.0"]);
+        assert_eq!(resolve_in(&root, &config, "^0.1").as_deref(), Some("0.1.7"));
+        assert_eq!(resolve_in(&root, &config, "~1.2").as_deref(), Some("1.2.3"));
+        assert_eq!(resolve_in(&root, &config, "^1").as_deref(), Some("1.3.0"));
+        assert_eq!(resolve_in(&root, &config, "^2"), None);
+        assert_eq!(resolve_in(&root, &config, "not-a-version"), None);
+    }
+
+    #[test]
+    fn aliases_from_config_beat_alias_file() {
+        let root = root(&["0.1.0", "0.2.0", "0.3.0"]);
+        fs::write(
+            root.path().join("functions/acme/todos").join(ALIAS_FILE),
+            "stable = \"0.1.0\"\ncanary = \"latest\"\n",
+        )
+        .unwrap();
+        let mut config = Config::default();
+        assert_eq!(resolve_in(&root, &config, "stable").as_deref(), Some("0.1.0"));
+        assert_eq!(resolve_in(&root, &config, "canary").as_deref(), Some("0.3.0"));
+
+        config.aliases.insert(
+            "acme/todos".to_string(),
+            BTreeMap::from([("stable".to_string(), "^0.2".to_string())]),
+        );
+        assert_eq!(resolve_in(&root, &config, "stable").as_deref(), Some("0.2.0"));
+    }
+}